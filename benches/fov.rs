@@ -0,0 +1,79 @@
+//! Compares computing the player's field of view over a whole generated map
+//! with a hash set of opaque tiles, as it used to be done every frame, against
+//! the opacity grid and reused output buffer used now, with each of the
+//! shadowcasting variants.
+//!
+//! ```text
+//! cargo bench --bench fov
+//! ```
+use std::collections::HashSet;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use rogue_algebra::{
+    Pos, Rect,
+    bitgrid::BitGrid,
+    fov::{self, FovAlgorithm, FovOpts},
+};
+use something_in_the_fog::{map::PLAYER_FOV, mapgen, spawn::Spawn};
+
+fn bench_fov(c: &mut Criterion) {
+    let map = mapgen::gen_map(0, &mapgen::default_zone_specs()).unwrap();
+    let opaque_set = map
+        .spawns
+        .iter()
+        .filter(|(_, spawns)| {
+            spawns
+                .iter()
+                .any(|s| matches!(s, Spawn::Tile(t) if t.blocks_sight()))
+        })
+        .map(|(&pos, _)| Pos::from(pos))
+        .collect::<HashSet<_>>();
+    let min_x = opaque_set.iter().map(|p| p.x).min().unwrap();
+    let max_x = opaque_set.iter().map(|p| p.x).max().unwrap();
+    let min_y = opaque_set.iter().map(|p| p.y).min().unwrap();
+    let max_y = opaque_set.iter().map(|p| p.y).max().unwrap();
+    let mut opaque_grid = BitGrid::new(Rect::new(min_x, max_x, min_y, max_y));
+    for &pos in &opaque_set {
+        opaque_grid.insert(pos);
+    }
+    // look around from the middle of every zone
+    let origins = map
+        .zones
+        .iter()
+        .map(|zone| Pos::from(zone.rect.center()))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("fov full map");
+    group.bench_function("hash set", |b| {
+        b.iter(|| {
+            for &origin in &origins {
+                std::hint::black_box(fov::calculate_fov(origin, PLAYER_FOV.radius, |p| {
+                    opaque_set.contains(&p)
+                }));
+            }
+        })
+    });
+    let mut seen = BitGrid::default();
+    for algorithm in [
+        FovAlgorithm::Recursive,
+        FovAlgorithm::Symmetric,
+        FovAlgorithm::Permissive,
+    ] {
+        let opts = FovOpts {
+            algorithm,
+            ..PLAYER_FOV
+        };
+        group.bench_function(format!("bit grid, {algorithm:?}"), |b| {
+            b.iter(|| {
+                for &origin in &origins {
+                    fov::calculate_fov_grid(origin, opts, &opaque_grid, &mut seen);
+                    std::hint::black_box(&seen);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_fov);
+criterion_main!(benches);
@@ -0,0 +1,172 @@
+//! Dense set of positions inside a rectangle, one bit per tile.
+//!
+//! Unlike a `HashSet<Pos>` or a [`TileMap`](crate::TileMap), lookups are a
+//! shift and a mask, and clearing or comparing a whole grid is a pass over a
+//! few machine words. Good for per-frame maps like opacity and visibility.
+use crate::{Pos, Rect};
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitGrid {
+    /// Top-left corner of the covered rectangle.
+    origin: Pos,
+    width: usize,
+    height: usize,
+    words: Vec<u64>,
+}
+
+impl Default for BitGrid {
+    /// An empty grid covering nothing.
+    fn default() -> Self {
+        BitGrid {
+            origin: Pos::new(0, 0),
+            width: 0,
+            height: 0,
+            words: Vec::new(),
+        }
+    }
+}
+
+impl BitGrid {
+    /// Creates an empty grid covering `rect`.
+    #[must_use]
+    pub fn new(rect: Rect) -> Self {
+        let mut grid = BitGrid::default();
+        grid.reset(rect);
+        grid
+    }
+
+    /// Empties the grid and makes it cover `rect`, reusing its storage.
+    pub fn reset(&mut self, rect: Rect) {
+        self.origin = Pos::new(rect.x1, rect.y1);
+        self.width = rect.width().max(0) as usize;
+        self.height = rect.height().max(0) as usize;
+        self.words.clear();
+        self.words
+            .resize((self.width * self.height).div_ceil(WORD_BITS), 0);
+    }
+
+    /// Empties the grid, keeping the rectangle it covers.
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+
+    /// The rectangle the grid covers, or `None` if it covers nothing.
+    #[must_use]
+    pub fn rect(&self) -> Option<Rect> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        Some(Rect::new(
+            self.origin.x,
+            self.origin.x + self.width as i32 - 1,
+            self.origin.y,
+            self.origin.y + self.height as i32 - 1,
+        ))
+    }
+
+    fn index(&self, pos: Pos) -> Option<usize> {
+        let x = usize::try_from(pos.x - self.origin.x).ok()?;
+        let y = usize::try_from(pos.y - self.origin.y).ok()?;
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    /// Whether `pos` is in the set. Positions outside the grid never are.
+    #[must_use]
+    pub fn contains(&self, pos: Pos) -> bool {
+        self.index(pos)
+            .is_some_and(|i| self.words[i / WORD_BITS] & (1 << (i % WORD_BITS)) != 0)
+    }
+
+    /// Adds `pos` to the set. Returns false, and does nothing, if `pos` is
+    /// outside the grid.
+    pub fn insert(&mut self, pos: Pos) -> bool {
+        let Some(i) = self.index(pos) else {
+            return false;
+        };
+        self.words[i / WORD_BITS] |= 1 << (i % WORD_BITS);
+        true
+    }
+
+    /// Removes `pos` from the set.
+    pub fn remove(&mut self, pos: Pos) {
+        if let Some(i) = self.index(pos) {
+            self.words[i / WORD_BITS] &= !(1 << (i % WORD_BITS));
+        }
+    }
+
+    /// Number of positions in the set.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// Iterates over the positions in the set, row by row.
+    pub fn iter(&self) -> impl Iterator<Item = Pos> + '_ {
+        self.words.iter().enumerate().flat_map(move |(w, &word)| {
+            let mut bits = word;
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let i = w * WORD_BITS + bit;
+                Some(Pos::new(
+                    self.origin.x + (i % self.width) as i32,
+                    self.origin.y + (i / self.width) as i32,
+                ))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitgrid() {
+        let rect = Rect::new(-3, 9, -2, 7);
+        let mut grid = BitGrid::new(rect);
+        assert_eq!(grid.rect(), Some(rect));
+        assert!(grid.is_empty());
+        let inside = [
+            Pos::new(-3, -2),
+            Pos::new(9, 7),
+            Pos::new(0, 0),
+            Pos::new(5, 4),
+        ];
+        for pos in inside {
+            assert!(grid.insert(pos));
+        }
+        assert!(!grid.insert(Pos::new(10, 0)));
+        assert!(!grid.contains(Pos::new(10, 0)));
+        assert!(!grid.contains(Pos::new(-4, -2)));
+        assert_eq!(grid.len(), inside.len());
+        let mut found = grid.iter().collect::<Vec<_>>();
+        found.sort_by_key(|p| (p.y, p.x));
+        let mut expected = inside.to_vec();
+        expected.sort_by_key(|p| (p.y, p.x));
+        assert_eq!(found, expected);
+
+        grid.remove(Pos::new(0, 0));
+        assert!(!grid.contains(Pos::new(0, 0)));
+        assert_eq!(grid.len(), inside.len() - 1);
+
+        let mut other = BitGrid::new(rect);
+        assert_ne!(grid, other);
+        grid.clear();
+        assert_eq!(grid, other);
+        other.reset(Rect::new(0, 0, 0, 0));
+        assert!(other.insert(Pos::new(0, 0)));
+        assert_eq!(other.iter().collect::<Vec<_>>(), [Pos::new(0, 0)]);
+        assert_eq!(BitGrid::default().rect(), None);
+    }
+}
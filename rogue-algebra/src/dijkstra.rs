@@ -0,0 +1,280 @@
+//! Dijkstra maps: the cost of reaching the nearest of a set of goals from
+//! every tile in a rectangle. Anything that wants to reach a goal just steps
+//! downhill, so one map serves every mob heading for the same place, and an
+//! inverted map leads away from the goals for anything fleeing them. See
+//! <http://www.roguebasin.com/index.php/The_Incredible_Power_of_Dijkstra_Maps>.
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::{Pos, Rect, CARDINALS};
+
+/// Value of tiles no goal can be reached from.
+const UNREACHED: i32 = i32::MAX;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DijkstraMap {
+    /// Top-left corner of the covered rectangle.
+    origin: Pos,
+    width: usize,
+    height: usize,
+    values: Vec<i32>,
+}
+
+impl Default for DijkstraMap {
+    /// A map covering nothing.
+    fn default() -> Self {
+        DijkstraMap {
+            origin: Pos::new(0, 0),
+            width: 0,
+            height: 0,
+            values: Vec::new(),
+        }
+    }
+}
+
+impl DijkstraMap {
+    /// Computes the cost of reaching the nearest of `goals` from each tile
+    /// in `bound`, moving in cardinal directions. `cost` is the cost of
+    /// stepping onto a tile, at least 1, or `None` if it can't be entered.
+    /// Goals can always be entered.
+    pub fn new(bound: Rect, goals: &[Pos], cost: impl FnMut(Pos) -> Option<i32>) -> Self {
+        Self::from_values(bound, goals.iter().map(|&p| (p, 0)), cost)
+    }
+
+    /// Like [`DijkstraMap::new`], but each goal starts at its own value
+    /// instead of 0, so some goals can be made more attractive than others.
+    pub fn from_values(
+        bound: Rect,
+        goals: impl IntoIterator<Item = (Pos, i32)>,
+        mut cost: impl FnMut(Pos) -> Option<i32>,
+    ) -> Self {
+        let mut map = DijkstraMap {
+            origin: Pos::new(bound.x1, bound.y1),
+            width: bound.width().max(0) as usize,
+            height: bound.height().max(0) as usize,
+            values: Vec::new(),
+        };
+        map.values = vec![UNREACHED; map.width * map.height];
+        let mut queue = BinaryHeap::new();
+        for (pos, value) in goals {
+            if let Some(i) = map.index(pos) {
+                if value < map.values[i] {
+                    map.values[i] = value;
+                    queue.push(Reverse((value, i)));
+                }
+            }
+        }
+        while let Some(Reverse((value, i))) = queue.pop() {
+            if value > map.values[i] {
+                continue;
+            }
+            let pos = map.pos(i);
+            for offset in CARDINALS {
+                let next = pos + offset;
+                let Some(j) = map.index(next) else {
+                    continue;
+                };
+                let Some(step) = cost(next) else {
+                    continue;
+                };
+                let next_value = value.saturating_add(step.max(1));
+                if next_value < map.values[j] {
+                    map.values[j] = next_value;
+                    queue.push(Reverse((next_value, j)));
+                }
+            }
+        }
+        map
+    }
+
+    fn index(&self, pos: Pos) -> Option<usize> {
+        let x = usize::try_from(pos.x - self.origin.x).ok()?;
+        let y = usize::try_from(pos.y - self.origin.y).ok()?;
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    fn pos(&self, i: usize) -> Pos {
+        Pos::new(
+            self.origin.x + (i % self.width) as i32,
+            self.origin.y + (i / self.width) as i32,
+        )
+    }
+
+    /// The cost of reaching the nearest goal from `pos`, or `None` if `pos`
+    /// is outside the map or no goal can be reached from it.
+    #[must_use]
+    pub fn get(&self, pos: Pos) -> Option<i32> {
+        self.index(pos)
+            .map(|i| self.values[i])
+            .filter(|&v| v != UNREACHED)
+    }
+
+    /// The neighbour of `pos` that's the cheapest step towards a goal, or
+    /// `None` if `pos` is already as close as its neighbours. `pos` itself
+    /// doesn't need to be reachable, so a mob standing on a tile that is
+    /// blocked, by itself say, can still find its way.
+    #[must_use]
+    pub fn downhill(&self, pos: Pos) -> Option<Pos> {
+        let here = self.get(pos).unwrap_or(UNREACHED);
+        CARDINALS
+            .iter()
+            .map(|&offset| pos + offset)
+            .filter_map(|p| Some((self.get(p)?, p)))
+            .filter(|&(value, _)| value < here)
+            .min_by_key(|&(value, _)| value)
+            .map(|(_, p)| p)
+    }
+
+    /// A map for fleeing the goals of this one. Every reached tile becomes a
+    /// goal worth `-factor` times its value here, so stepping downhill leads
+    /// away from this map's goals; a `factor` above 1 lets fleers run past
+    /// them to escape rather than into corners. `cost` is as for
+    /// [`DijkstraMap::new`].
+    pub fn inverted(&self, factor: f64, cost: impl FnMut(Pos) -> Option<i32>) -> Self {
+        let Some(bound) = self.rect() else {
+            return DijkstraMap::default();
+        };
+        let goals = self
+            .values
+            .iter()
+            .enumerate()
+            .filter(|&(_, &v)| v != UNREACHED)
+            .map(|(i, &v)| (self.pos(i), (-(v as f64) * factor).round() as i32))
+            .collect::<Vec<_>>();
+        Self::from_values(bound, goals, cost)
+    }
+
+    /// The rectangle the map covers, or `None` if it covers nothing.
+    #[must_use]
+    pub fn rect(&self) -> Option<Rect> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        Some(Rect::new(
+            self.origin.x,
+            self.origin.x + self.width as i32 - 1,
+            self.origin.y,
+            self.origin.y + self.height as i32 - 1,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A test map where `#` is a wall, `~` is mud costing 3 to enter and
+    /// digits are goals with that value.
+    struct TestMap(Vec<Vec<char>>);
+
+    impl TestMap {
+        fn parse(s: &str) -> Self {
+            TestMap(
+                s.trim()
+                    .lines()
+                    .map(|line| line.chars().collect())
+                    .collect(),
+            )
+        }
+
+        fn rect(&self) -> Rect {
+            Rect::new(0, self.0[0].len() as i32 - 1, 0, self.0.len() as i32 - 1)
+        }
+
+        fn goals(&self) -> Vec<(Pos, i32)> {
+            self.rect()
+                .into_iter()
+                .filter_map(|p| {
+                    let value = self.0[p.y as usize][p.x as usize].to_digit(10)?;
+                    Some((p, value as i32))
+                })
+                .collect()
+        }
+
+        fn cost(&self, p: Pos) -> Option<i32> {
+            match self.0[p.y as usize][p.x as usize] {
+                '#' => None,
+                '~' => Some(3),
+                _ => Some(1),
+            }
+        }
+
+        fn dijkstra_map(&self) -> DijkstraMap {
+            DijkstraMap::from_values(self.rect(), self.goals(), |p| self.cost(p))
+        }
+    }
+
+    fn walk_downhill(map: &DijkstraMap, mut pos: Pos) -> Vec<Pos> {
+        let mut path = vec![pos];
+        while let Some(next) = map.downhill(pos) {
+            pos = next;
+            path.push(pos);
+        }
+        path
+    }
+
+    #[test]
+    fn test_dijkstra_map() {
+        let test_map = TestMap::parse(
+            "
+0.....#...
+.####.#.#.
+......~.#0
+#######.##
+..........",
+        );
+        let map = test_map.dijkstra_map();
+        assert_eq!(map.get(Pos::new(0, 0)), Some(0));
+        assert_eq!(map.get(Pos::new(9, 2)), Some(0));
+        // walls aren't reached
+        assert_eq!(map.get(Pos::new(6, 0)), None);
+        assert_eq!(map.get(Pos::new(20, 0)), None);
+        // multi-source: each tile is as far as the nearest goal
+        assert_eq!(map.get(Pos::new(5, 2)), Some(7));
+        assert_eq!(map.get(Pos::new(7, 2)), Some(6));
+        // the mud costs 3 to enter
+        assert_eq!(map.get(Pos::new(6, 2)), Some(9));
+        assert_eq!(map.get(Pos::new(0, 4)), Some(15));
+
+        let path = walk_downhill(&map, Pos::new(0, 4));
+        assert_eq!(path.len(), 16);
+        assert_eq!(path.last(), Some(&Pos::new(9, 2)));
+        for step in path.windows(2) {
+            assert_eq!(
+                (step[0].x - step[1].x).abs() + (step[0].y - step[1].y).abs(),
+                1
+            );
+            assert!(test_map.cost(step[1]).is_some());
+        }
+        // a blocked start still finds the way
+        assert_eq!(map.downhill(Pos::new(6, 1)), Some(Pos::new(7, 1)));
+        assert_eq!(map.downhill(Pos::new(0, 0)), None);
+
+        let same = DijkstraMap::new(test_map.rect(), &[Pos::new(0, 0), Pos::new(9, 2)], |p| {
+            test_map.cost(p)
+        });
+        assert_eq!(same, map);
+    }
+
+    #[test]
+    fn test_inverted_dijkstra_map() {
+        let test_map = TestMap::parse(
+            "
+..........
+.0........
+..........",
+        );
+        let chase = test_map.dijkstra_map();
+        let flee = chase.inverted(1.2, |p| test_map.cost(p));
+        // fleeing from next to the goal heads for the far corner
+        let path = walk_downhill(&flee, Pos::new(2, 1));
+        let end = *path.last().unwrap();
+        assert_eq!(end.x, 9, "{path:?}");
+        for step in path.windows(2) {
+            assert!(chase.get(step[1]) >= chase.get(step[0]), "{path:?}");
+        }
+        assert_eq!(
+            DijkstraMap::default().inverted(1.2, |_| Some(1)).rect(),
+            None
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Offset, Pos};
+use crate::{bitgrid::BitGrid, Offset, Pos};
 use std::collections::HashSet;
 
 /// Consider 8 quadrants on a standard graph, each one an infinitely-long
@@ -24,22 +24,275 @@ fn apply_quad_transform(quad: usize, off: Offset) -> Offset {
     }
 }
 
+/// How light is cast from the origin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FovAlgorithm {
+    /// Recursive shadowcasting. Not symmetric: `a` seeing `b` doesn't mean
+    /// `b` sees `a`.
+    #[default]
+    Recursive,
+    /// Albert Ford's symmetric shadowcasting. A floor tile is only seen if
+    /// its center is lit, so `a` sees `b` exactly when `b` sees `a`. Walls are
+    /// seen if any part of them is lit.
+    Symmetric,
+    /// Symmetric shadowcasting that sees every tile the light crosses, not
+    /// just those with a lit center. Shows more around pillars and corners,
+    /// but isn't symmetric.
+    Permissive,
+}
+
+/// How distance from the origin is measured against the radius.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RadiusMetric {
+    /// A square: the larger of the x and y distances.
+    #[default]
+    Chebyshev,
+    /// A circle.
+    Euclidean,
+}
+
+impl RadiusMetric {
+    /// Whether `offset` from the origin is within `radius`.
+    #[must_use]
+    pub fn contains(self, offset: Offset, radius: i32) -> bool {
+        match self {
+            RadiusMetric::Chebyshev => offset.x.abs().max(offset.y.abs()) <= radius,
+            RadiusMetric::Euclidean => offset.x * offset.x + offset.y * offset.y <= radius * radius,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FovOpts {
+    pub algorithm: FovAlgorithm,
+    pub radius: i32,
+    pub metric: RadiusMetric,
+}
+
+/// Casts light from `pos`, calling `see` with each position it reaches, the
+/// origin included. Positions may be seen more than once.
+pub fn cast_fov(
+    pos: Pos,
+    opts: FovOpts,
+    mut opaque: impl FnMut(Pos) -> bool,
+    mut see: impl FnMut(Pos),
+) {
+    see(pos);
+    let mut see = |p: Pos| {
+        if opts.metric.contains(p - pos, opts.radius) {
+            see(p);
+        }
+    };
+    match opts.algorithm {
+        FovAlgorithm::Recursive => {
+            for quadrant in 0..8 {
+                cast_light(
+                    &mut see,
+                    pos,
+                    1,
+                    0.0,
+                    1.0,
+                    opts.radius,
+                    quadrant,
+                    &mut opaque,
+                );
+            }
+        }
+        FovAlgorithm::Symmetric | FovAlgorithm::Permissive => {
+            let permissive = opts.algorithm == FovAlgorithm::Permissive;
+            for quarter in 0..4 {
+                scan_quarter(&mut see, pos, quarter, opts.radius, permissive, &mut opaque);
+            }
+        }
+    }
+}
+
 /// Uses shadowcasting to return set of positions visible from pos.
-pub fn calculate_fov(pos: Pos, radius: i32, mut opaque: impl FnMut(Pos) -> bool) -> HashSet<Pos> {
+pub fn calculate_fov(pos: Pos, radius: i32, opaque: impl FnMut(Pos) -> bool) -> HashSet<Pos> {
+    let opts = FovOpts {
+        algorithm: FovAlgorithm::Recursive,
+        radius,
+        metric: RadiusMetric::Chebyshev,
+    };
+    calculate_fov_with(pos, opts, opaque)
+}
+
+/// Returns the set of positions visible from `pos`.
+pub fn calculate_fov_with(
+    pos: Pos,
+    opts: FovOpts,
+    opaque: impl FnMut(Pos) -> bool,
+) -> HashSet<Pos> {
     let mut seen = HashSet::new();
-    seen.insert(pos);
-    for quadrant in 0..8 {
-        cast_light(&mut seen, pos, 1, 0.0, 1.0, radius, quadrant, &mut opaque);
-    }
+    cast_fov(pos, opts, opaque, |p| {
+        seen.insert(p);
+    });
     seen
 }
 
+/// Like [`calculate_fov_with`], but reads opacity from a grid and writes the
+/// visible positions into `seen`, which is reset to cover the same rectangle
+/// as `opaque`. Positions outside `opaque`'s grid are treated as opaque.
+pub fn calculate_fov_grid(pos: Pos, opts: FovOpts, opaque: &BitGrid, seen: &mut BitGrid) {
+    let Some(rect) = opaque.rect() else {
+        *seen = BitGrid::default();
+        return;
+    };
+    seen.reset(rect);
+    let is_opaque = |p| !rect.contains(p) || opaque.contains(p);
+    cast_fov(pos, opts, is_opaque, |p| {
+        seen.insert(p);
+    });
+}
+
+/// Whether the center of `to` can be seen from `from`, ignoring range. Only
+/// the line between the two centers is checked, so this is much cheaper than
+/// casting a whole field of view when only one tile matters. Walls block it
+/// the way they block [`FovAlgorithm::Symmetric`]: only where the line
+/// crosses the middle of their row, or column. A line passing right between
+/// two tiles is only blocked if both are walls, so it's symmetric, and sees
+/// everything the symmetric cast does plus a few tiles whose line grazes a
+/// corner.
+pub fn line_of_sight(from: Pos, to: Pos, mut opaque: impl FnMut(Pos) -> bool) -> bool {
+    let offset = to - from;
+    // scan rows along the longer axis, like the quarter `to` is in
+    let (depth, col, transform): (i32, i32, fn(i32, i32) -> Offset) =
+        if offset.y.abs() >= offset.x.abs() {
+            (offset.y.abs(), offset.x, |depth, col| {
+                Offset::new(col, depth)
+            })
+        } else {
+            (offset.x.abs(), offset.y, |depth, col| {
+                Offset::new(depth, col)
+            })
+        };
+    let sign = if offset.y.abs() >= offset.x.abs() {
+        Offset::new(1, offset.y.signum())
+    } else {
+        Offset::new(offset.x.signum(), 1)
+    };
+    let at = |depth: i32, col: i32| {
+        let o = transform(depth, col);
+        from + Offset::new(o.x * sign.x, o.y * sign.y)
+    };
+    for d in 1..depth {
+        // the line crosses the middle of row d at column d * col / depth;
+        // in halves of a column, rounding towards negative infinity
+        let halves = (2 * d * col).div_euclid(depth);
+        let exact = (2 * d * col).rem_euclid(depth) == 0;
+        let blocked = if exact && halves.rem_euclid(2) == 1 {
+            // right between two tiles
+            opaque(at(d, (halves - 1) / 2)) && opaque(at(d, (halves + 1) / 2))
+        } else {
+            opaque(at(d, (halves + 1).div_euclid(2)))
+        };
+        if blocked {
+            return false;
+        }
+    }
+    true
+}
+
+/// Slope of a line from the origin, as columns per row, kept as an exact
+/// fraction so that tiles on the edge of the light are treated the same
+/// whichever way it's cast.
+#[derive(Debug, Clone, Copy)]
+struct Slope {
+    num: i64,
+    /// Always positive.
+    den: i64,
+}
+
+impl Slope {
+    /// Slope of the line through the corner between `col - 1` and `col` at
+    /// the near edge of row `depth`.
+    fn at_tile_edge(depth: i32, col: i32) -> Slope {
+        Slope {
+            num: 2 * col as i64 - 1,
+            den: 2 * depth as i64,
+        }
+    }
+
+    /// Column the line crosses the middle of row `depth` in, rounding
+    /// ties up.
+    fn col_rounding_up(self, depth: i32) -> i32 {
+        (2 * depth as i64 * self.num + self.den).div_euclid(2 * self.den) as i32
+    }
+
+    /// Column the line crosses the middle of row `depth` in, rounding
+    /// ties down.
+    fn col_rounding_down(self, depth: i32) -> i32 {
+        -(self.den - 2 * depth as i64 * self.num).div_euclid(2 * self.den) as i32
+    }
+
+    /// Whether the center of `col` in row `depth` is on the line or past it,
+    /// towards higher columns.
+    fn has_center_after(self, depth: i32, col: i32) -> bool {
+        col as i64 * self.den >= depth as i64 * self.num
+    }
+
+    /// Whether the center of `col` in row `depth` is on the line or before
+    /// it, towards lower columns.
+    fn has_center_before(self, depth: i32, col: i32) -> bool {
+        col as i64 * self.den <= depth as i64 * self.num
+    }
+}
+
+// Symmetric shadowcasting over one quarter of the plane, scanning rows
+// outward from the origin. See https://www.albertford.com/shadowcasting/
+// for an explanation.
+fn scan_quarter(
+    see: &mut impl FnMut(Pos),
+    origin: Pos,
+    quarter: usize,
+    radius: i32,
+    permissive: bool,
+    opaque: &mut impl FnMut(Pos) -> bool,
+) {
+    let transform = |depth: i32, col: i32| {
+        origin
+            + match quarter {
+                0 => Offset::new(col, depth),
+                1 => Offset::new(col, -depth),
+                2 => Offset::new(depth, col),
+                _ => Offset::new(-depth, col),
+            }
+    };
+    let mut rows = vec![(1, Slope { num: -1, den: 1 }, Slope { num: 1, den: 1 })];
+    while let Some((depth, mut start, end)) = rows.pop() {
+        if depth > radius {
+            continue;
+        }
+        // whether the last tile in the row was a wall, if there was one
+        let mut prev_wall = None;
+        for col in start.col_rounding_up(depth)..=end.col_rounding_down(depth) {
+            let pos = transform(depth, col);
+            let wall = opaque(pos);
+            let lit_center =
+                start.has_center_after(depth, col) && end.has_center_before(depth, col);
+            if wall || permissive || lit_center {
+                see(pos);
+            }
+            if prev_wall == Some(true) && !wall {
+                start = Slope::at_tile_edge(depth, col);
+            }
+            if prev_wall == Some(false) && wall {
+                rows.push((depth + 1, start, Slope::at_tile_edge(depth, col)));
+            }
+            prev_wall = Some(wall);
+        }
+        if prev_wall == Some(false) {
+            rows.push((depth + 1, start, end));
+        }
+    }
+}
+
 // Recursive function to perform the shadowcasting. See
 // http://www.roguebasin.com/index.php?title=FOV_using_recursive_shadowcasting
 // for an explanation.
 #[allow(clippy::too_many_arguments)]
 fn cast_light(
-    seen: &mut HashSet<Pos>,
+    see: &mut impl FnMut(Pos),
     start_pos: Pos,
     start_y: i32,
     mut start_slope: f64,
@@ -67,7 +320,7 @@ fn cast_light(
                 continue;
             }
 
-            seen.insert(pos);
+            see(pos);
 
             if opaque(pos) {
                 if prev_blocked {
@@ -76,7 +329,7 @@ fn cast_light(
                     // end of row for transparent tiles
                     prev_blocked = true;
                     cast_light(
-                        seen,
+                        see,
                         start_pos,
                         dy + 1,
                         start_slope,
@@ -102,8 +355,18 @@ fn cast_light(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Rect;
 
     fn test_map(s: &str, radius: i32) {
+        let opts = FovOpts {
+            algorithm: FovAlgorithm::Recursive,
+            radius,
+            metric: RadiusMetric::Chebyshev,
+        };
+        test_map_with(s, opts);
+    }
+
+    fn test_map_with(s: &str, opts: FovOpts) {
         let mut map = vec![vec![]];
         let mut player_pos = None;
         for c in s.trim().chars() {
@@ -125,7 +388,26 @@ mod tests {
                 || (p.y as usize) >= map[0].len()
                 || map[p.x as usize][p.y as usize] == '#'
         };
-        let fov = calculate_fov(player_pos, radius, opaque);
+        let fov = calculate_fov_with(player_pos, opts, opaque);
+
+        // the grid version sees the same, inside the grid
+        let rect = Rect::new(0, map.len() as i32 - 1, 0, map[0].len() as i32 - 1);
+        let mut opaque_grid = BitGrid::new(rect);
+        for p in rect {
+            if opaque(p) {
+                opaque_grid.insert(p);
+            }
+        }
+        let mut seen = BitGrid::default();
+        calculate_fov_grid(player_pos, opts, &opaque_grid, &mut seen);
+        let mut expected = fov
+            .iter()
+            .filter(|p| rect.contains(**p))
+            .collect::<Vec<_>>();
+        let mut found = seen.iter().collect::<Vec<_>>();
+        expected.sort();
+        found.sort();
+        assert_eq!(found.iter().collect::<Vec<_>>(), expected);
         let mut new_map = vec![];
         for x in 0..map.len() {
             new_map.push(vec![]);
@@ -150,7 +432,7 @@ mod tests {
             .flat_map(|row| row.into_iter().chain(['\n']))
             .collect::<String>();
         eprintln!("{}", s.trim());
-        eprintln!("");
+        eprintln!();
         eprintln!("{}", new_s.trim());
         assert_eq!(s.trim(), new_s.trim());
     }
@@ -192,6 +474,45 @@ mod tests {
 .........***...********....................................
 .........##...##########...................................";
 
+    const MAP_DOOR_SYMMETRIC: &str = "\
+.**#####**.
+...*****...
+....#@#....
+...***#....
+.****#*....";
+    const MAP_PILLARS_RECURSIVE: &str = "\
+..*******..
+**.*****.**
+***#***#***
+***********
+*****@*****
+***********
+***#***#***
+**.*****.**
+..*******..";
+    const MAP_PILLARS_PERMISSIVE: &str = "\
+*.*******.*
+**.*****.**
+***#***#***
+***********
+*****@*****
+***********
+***#***#***
+**.*****.**
+*.*******.*";
+    const MAP_CIRCLE: &str = "\
+...........
+.....*.....
+...*****...
+..*******..
+..*******..
+.****@****.
+..*******..
+..*******..
+...*****...
+.....*.....
+...........";
+
     #[test]
     fn test_fov() {
         test_map(MAP_TRIVIAL, 0);
@@ -201,4 +522,87 @@ mod tests {
         test_map(MAP_DOOR, 2);
         test_map(MAP_BIG, 10)
     }
+
+    #[test]
+    fn test_fov_variants() {
+        let opts = |algorithm, radius, metric| FovOpts {
+            algorithm,
+            radius,
+            metric,
+        };
+        use FovAlgorithm::*;
+        use RadiusMetric::*;
+        test_map_with(MAP_DOOR_SYMMETRIC, opts(Symmetric, 10, Chebyshev));
+        test_map_with(MAP_DOOR_SYMMETRIC, opts(Permissive, 10, Chebyshev));
+        test_map_with(MAP_PILLARS_RECURSIVE, opts(Recursive, 10, Chebyshev));
+        // symmetric can't see the corners behind the pillars, as they can't
+        // see the origin either
+        test_map_with(MAP_PILLARS_RECURSIVE, opts(Symmetric, 10, Chebyshev));
+        test_map_with(MAP_PILLARS_PERMISSIVE, opts(Permissive, 10, Chebyshev));
+        test_map_with(MAP_CIRCLE, opts(Symmetric, 4, Euclidean));
+        test_map_with(MAP_CIRCLE, opts(Recursive, 4, Euclidean));
+    }
+
+    #[test]
+    fn test_symmetric_fov_is_symmetric() {
+        let map = MAP_BIG
+            .lines()
+            .map(|line| line.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let rect = Rect::new(0, map.len() as i32 - 1, 0, map[0].len() as i32 - 1);
+        let opaque = |p: Pos| !rect.contains(p) || map[p.x as usize][p.y as usize] == '#';
+        let opts = FovOpts {
+            algorithm: FovAlgorithm::Symmetric,
+            radius: 99,
+            metric: RadiusMetric::Euclidean,
+        };
+        let floors = rect.into_iter().filter(|&p| !opaque(p)).collect::<Vec<_>>();
+        let fovs = floors
+            .iter()
+            .map(|&p| calculate_fov_with(p, opts, opaque))
+            .collect::<Vec<_>>();
+        for (a, fov_a) in floors.iter().zip(&fovs) {
+            for (b, fov_b) in floors.iter().zip(&fovs) {
+                assert_eq!(fov_a.contains(b), fov_b.contains(a), "{a:?} and {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let map = MAP_BIG
+            .lines()
+            .map(|line| line.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let rect = Rect::new(0, map.len() as i32 - 1, 0, map[0].len() as i32 - 1);
+        let opaque = |p: Pos| !rect.contains(p) || map[p.x as usize][p.y as usize] == '#';
+        let opts = FovOpts {
+            algorithm: FovAlgorithm::Symmetric,
+            radius: 99,
+            metric: RadiusMetric::Euclidean,
+        };
+        let floors = rect.into_iter().filter(|&p| !opaque(p)).collect::<Vec<_>>();
+        for &a in &floors {
+            let fov = calculate_fov_with(a, opts, opaque);
+            for &b in &floors {
+                let los = line_of_sight(a, b, opaque);
+                assert_eq!(los, line_of_sight(b, a, opaque), "{a:?} and {b:?}");
+                // it only differs from the cast where a line grazes a corner
+                assert!(los || !fov.contains(&b), "{a:?} and {b:?}");
+            }
+        }
+
+        let wall = |p: Pos| p == Pos::new(1, 1);
+        assert!(line_of_sight(Pos::new(0, 0), Pos::new(0, 2), wall));
+        assert!(!line_of_sight(Pos::new(0, 0), Pos::new(2, 2), wall));
+        assert!(!line_of_sight(Pos::new(0, 1), Pos::new(3, 1), wall));
+        // the ends don't block
+        assert!(line_of_sight(Pos::new(0, 0), Pos::new(1, 1), wall));
+        assert!(line_of_sight(Pos::new(1, 1), Pos::new(1, 1), wall));
+        // squeezing between diagonal walls
+        let diagonal = |p: Pos| p == Pos::new(1, 0) || p == Pos::new(0, 1);
+        assert!(line_of_sight(Pos::new(0, 0), Pos::new(2, 2), diagonal));
+        let both = |p: Pos| p == Pos::new(1, 1) || p == Pos::new(2, 1);
+        assert!(!line_of_sight(Pos::new(0, 0), Pos::new(3, 2), both));
+    }
 }
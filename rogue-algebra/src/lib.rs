@@ -16,8 +16,12 @@ use std::{
 
 use rand::Rng;
 
+pub mod bitgrid;
+pub mod dijkstra;
 pub mod fov;
 pub mod path;
+pub mod procgen;
+pub mod wfc;
 
 const CHUNKSIZE: usize = 16;
 
@@ -294,6 +298,13 @@ impl<Tile: Clone> TileMap<Tile> {
             self[p] = tile.clone();
         }
     }
+
+    /// Drops every chunk lying wholly left of column `x`, freeing its memory.
+    /// Tiles there read as the default tile again.
+    pub fn forget_left_of(&mut self, x: i32) {
+        self.chunks
+            .retain(|ci, _| (ci.x + 1) * CHUNKSIZE as i32 > x);
+    }
 }
 
 impl<Tile: Clone> Index<Pos> for TileMap<Tile> {
@@ -323,7 +334,7 @@ impl<Tile: Clone> IndexMut<Pos> for TileMap<Tile> {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 /// Represents a rectangle on the map.
 pub struct Rect {
     /// X-coordinate of the leftmost column in the rectangle.
@@ -697,6 +708,21 @@ mod tests {
         assert_eq!(Offset { x: 1, y: -1 }.nearest_cardinal(), EAST);
     }
 
+    #[test]
+    fn test_tile_map_forget_left_of() {
+        let mut map = TileMap::new(0);
+        for x in -40..40 {
+            map[Pos::new(x, 3)] = 1;
+        }
+        map.forget_left_of(-5);
+        for x in -40..40 {
+            // whole chunks are dropped, so a few tiles left of -5 survive
+            let expected = if x < -16 { 0 } else { 1 };
+            assert_eq!(map[Pos::new(x, 3)], expected, "x = {x}");
+        }
+        assert_eq!(map.iter().filter(|(_, tile)| *tile == 1).count(), 56);
+    }
+
     #[test]
     fn test_rect_trivial() {
         let origin = Pos { x: 0, y: 0 };
@@ -0,0 +1,519 @@
+//! Building blocks for procedural map generation: BSP partitioning into rooms
+//! and the graph of doors between them, cellular automata caves, drunkard's
+//! walks, and value noise.
+//!
+//! Everything takes the RNG as an argument, so the same seed always gives the
+//! same map.
+use std::collections::{BTreeMap, HashSet};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{Pos, Rect, TileMap, CARDINALS, DIRECTIONS};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcgenError {
+    /// BSP split options whose minimum size doesn't fit twice in the maximum.
+    BadBspOpts(BspSplitOpts),
+    /// Two halves of a BSP tree share no wall to put a door in.
+    Unbridged,
+}
+
+impl std::fmt::Display for ProcgenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcgenError::BadBspOpts(opts) => write!(f, "bad BSP split options {opts:?}"),
+            ProcgenError::Unbridged => write!(f, "BSP rooms could not be connected"),
+        }
+    }
+}
+
+impl std::error::Error for ProcgenError {}
+
+/// The wall between two rooms separated by a single tile, if they are.
+pub fn get_connecting_wall(room1: Rect, room2: Rect) -> Option<Rect> {
+    // one-tile-wall between them
+    for (room1, room2) in &[(room1, room2), (room2, room1)] {
+        // room2 right of room1
+        if room1.x2 + 2 == room2.x1 {
+            let y1 = room1.y1.max(room2.y1);
+            let y2 = room1.y2.min(room2.y2);
+            if y1 <= y2 {
+                return Some(Rect {
+                    x1: room1.x2 + 1,
+                    x2: room1.x2 + 1,
+                    y1,
+                    y2,
+                });
+            }
+        }
+        // room2 under room1
+        if room1.y2 + 2 == room2.y1 {
+            let x1 = room1.x1.max(room2.x1);
+            let x2 = room1.x2.min(room2.x2);
+            if x1 <= x2 {
+                return Some(Rect {
+                    x1,
+                    x2,
+                    y1: room1.y2 + 1,
+                    y2: room1.y2 + 1,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// How big BSP rooms may get. A rect is split while it's bigger than the
+/// maximum, and never into a room smaller than the minimum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BspSplitOpts {
+    pub max_width: i32,
+    pub max_height: i32,
+    pub min_width: i32,
+    pub min_height: i32,
+}
+
+/// A rect split in two again and again, with a one-tile wall at every split.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BspTree {
+    Split(Box<BspTree>, Box<BspTree>),
+    Room(Rect),
+}
+
+impl BspTree {
+    /// The rooms at the leaves of the tree.
+    pub fn rooms(&self) -> Vec<Rect> {
+        match self {
+            BspTree::Room(rect) => vec![*rect],
+            BspTree::Split(tree1, tree2) => {
+                let mut rooms = tree1.rooms();
+                rooms.extend(tree2.rooms());
+                rooms
+            }
+        }
+    }
+
+    /// Connects the rooms into a tree: the two halves of every split are
+    /// joined by one pair of rooms that share a wall.
+    pub fn into_room_graph(self) -> Result<RoomGraph, ProcgenError> {
+        match self {
+            BspTree::Room(rect) => {
+                let mut graph = RoomGraph::new();
+                graph.add_room(rect);
+                Ok(graph)
+            }
+            BspTree::Split(tree1, tree2) => {
+                let mut rooms1 = tree1.into_room_graph()?;
+                let rooms2 = tree2.into_room_graph()?;
+                // now figure out how to bridge the trees
+                rooms1.extend_bridged(rooms2)?;
+                Ok(rooms1)
+            }
+        }
+    }
+}
+
+/// Rooms and which of them are connected, through the wall between them.
+#[derive(Debug, Clone, Default)]
+pub struct RoomGraph {
+    // Ordered so that iteration, and therefore generation, is reproducible.
+    room_adj: BTreeMap<Rect, Vec<Rect>>,
+}
+
+impl RoomGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.room_adj.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.room_adj.is_empty()
+    }
+
+    /// Rooms connected to `rect`, or `None` if it isn't a room.
+    pub fn get_adj(&self, rect: Rect) -> Option<&[Rect]> {
+        self.room_adj.get(&rect).map(|v| v.as_slice())
+    }
+
+    pub fn choose(&self, rng: &mut impl Rng) -> Option<Rect> {
+        if self.room_adj.is_empty() {
+            return None;
+        }
+        let idx = rng.gen_range(0..self.room_adj.len());
+        self.room_adj.keys().nth(idx).cloned()
+    }
+
+    /// Rooms that share a wall with `rect`, connected or not.
+    pub fn find_spatially_adjacent(&'_ self, rect: Rect) -> impl Iterator<Item = Rect> + '_ {
+        self.room_adj
+            .keys()
+            .cloned()
+            .filter(move |room| get_connecting_wall(rect, *room).is_some())
+    }
+
+    /// Adds the rooms of `other`, connected to this graph through the first
+    /// pair of rooms found that share a wall.
+    pub fn extend_bridged(&mut self, mut other: RoomGraph) -> Result<(), ProcgenError> {
+        let mut bridged = false;
+        'loop1: for (room1, ref mut adj1) in &mut self.room_adj {
+            for (room2, ref mut adj2) in &mut other.room_adj {
+                if get_connecting_wall(*room1, *room2).is_some() {
+                    bridged = true;
+                    adj1.push(*room2);
+                    adj2.push(*room1);
+                    break 'loop1;
+                }
+            }
+        }
+        if !bridged {
+            return Err(ProcgenError::Unbridged);
+        }
+        self.room_adj.extend(other.room_adj);
+        Ok(())
+    }
+
+    pub fn add_room(&mut self, room: Rect) {
+        self.room_adj.insert(room, vec![]);
+    }
+
+    /// Connects two rooms that share a wall.
+    ///
+    /// # Panics
+    ///
+    /// If either isn't a room in the graph, or they don't share a wall.
+    pub fn add_connection(&mut self, room1: Rect, room2: Rect) {
+        assert!(get_connecting_wall(room1, room2).is_some());
+        assert!(self.room_adj.contains_key(&room1));
+        assert!(self.room_adj.contains_key(&room2));
+        self.room_adj.get_mut(&room2).unwrap().push(room1);
+        self.room_adj.get_mut(&room1).unwrap().push(room2);
+    }
+
+    pub fn iter(&'_ self) -> impl Iterator<Item = Rect> + '_ {
+        self.room_adj.keys().copied()
+    }
+
+    /// Tries `num_loops` times to connect a random room to a random
+    /// neighbour it isn't connected to yet, so that the graph is no longer a
+    /// tree. Returns how many connections were added.
+    pub fn add_extra_loops(&mut self, num_loops: usize, rng: &mut impl Rng) -> usize {
+        let mut added = 0;
+        for _ in 0..num_loops {
+            let Some(room1) = self.choose(rng) else {
+                return added;
+            };
+            let unconnected = self
+                .find_spatially_adjacent(room1)
+                .filter(|room2| !self.room_adj[&room1].contains(room2))
+                .collect::<Vec<_>>();
+            if let Some(&room2) = unconnected.choose(rng) {
+                self.add_connection(room1, room2);
+                added += 1;
+            }
+        }
+        added
+    }
+}
+
+/// Splits `rect` into rooms no bigger than the maximum in `opts`, with a
+/// one-tile wall between them.
+pub fn gen_bsp_tree(
+    rect: Rect,
+    opts: BspSplitOpts,
+    rng: &mut impl Rng,
+) -> Result<BspTree, ProcgenError> {
+    if opts.min_width * 2 >= opts.max_width || opts.min_height * 2 >= opts.max_height {
+        return Err(ProcgenError::BadBspOpts(opts));
+    }
+    #[derive(Clone, Copy, Debug)]
+    enum Split {
+        X,
+        Y,
+        None,
+    }
+    let too_wide = (rect.x2 - rect.x1) > opts.max_width;
+    let too_tall = (rect.y2 - rect.y1) > opts.max_height;
+    let split = match (too_wide, too_tall) {
+        (true, true) => *[Split::X, Split::Y].choose(rng).unwrap(),
+        (true, false) => Split::X,
+        (false, true) => Split::Y,
+        _ => Split::None,
+    };
+    match split {
+        Split::X => {
+            let split_x = rng.gen_range(rect.x1 + opts.min_width..(rect.x2 - opts.min_width));
+            let left = Rect::new(rect.x1, split_x - 1, rect.y1, rect.y2);
+            let right = Rect::new(split_x + 1, rect.x2, rect.y1, rect.y2);
+            Ok(BspTree::Split(
+                Box::new(gen_bsp_tree(left, opts, rng)?),
+                Box::new(gen_bsp_tree(right, opts, rng)?),
+            ))
+        }
+        Split::Y => {
+            let split_y = rng.gen_range(rect.y1 + opts.min_height..(rect.y2 - opts.min_height));
+            let top = Rect::new(rect.x1, rect.x2, rect.y1, split_y - 1);
+            let bottom = Rect::new(rect.x1, rect.x2, split_y + 1, rect.y2);
+            Ok(BspTree::Split(
+                Box::new(gen_bsp_tree(top, opts, rng)?),
+                Box::new(gen_bsp_tree(bottom, opts, rng)?),
+            ))
+        }
+        Split::None => Ok(BspTree::Room(rect)),
+    }
+}
+
+/// Sets `tile` along a wobbly path from `start` to `end`, staying inside
+/// `bound`. With probability `waviness` each step goes in a random direction
+/// instead of towards `end`.
+pub fn carve_line_drunk<T: Clone>(
+    tile_map: &mut TileMap<T>,
+    start: Pos,
+    end: Pos,
+    rng: &mut impl Rng,
+    waviness: f64,
+    tile: T,
+    bound: Rect,
+) {
+    let mut pos = start;
+    while pos != end {
+        let dir = if rng.gen_bool(waviness) {
+            *CARDINALS.choose(rng).unwrap()
+        } else {
+            (end - pos).nearest_cardinal()
+        };
+        if !bound.contains(pos + dir) {
+            continue;
+        }
+        pos += dir;
+        tile_map[pos] = tile.clone();
+    }
+}
+
+/// The tiles visited by a random walk of `steps` cardinal steps from
+/// `start`, staying inside `bound`.
+pub fn drunkards_walk(start: Pos, steps: usize, bound: Rect, rng: &mut impl Rng) -> HashSet<Pos> {
+    let mut pos = start;
+    let mut visited = HashSet::from([start]);
+    for _ in 0..steps {
+        let next = pos + *CARDINALS.choose(rng).unwrap();
+        if bound.contains(next) {
+            pos = next;
+            visited.insert(pos);
+        }
+    }
+    visited
+}
+
+/// Open tiles of a cave grown in `room`: each tile starts open with
+/// probability `noise`, then each iteration opens exactly the tiles with one
+/// to four closed neighbours. Everything outside `room` counts as closed.
+pub fn gen_cellular_automata(
+    room: Rect,
+    iterations: usize,
+    noise: f64,
+    rng: &mut impl Rng,
+) -> HashSet<Pos> {
+    // A flat grid with a one-tile border of empty cells rather than a set,
+    // since this looks at every neighbour of every tile on every iteration.
+    let stride = room.width() as usize + 2;
+    let index = |pos: Pos| (pos.y - room.y1 + 1) as usize * stride + (pos.x - room.x1 + 1) as usize;
+    let mut state = vec![false; stride * (room.height() as usize + 2)];
+    for pos in room {
+        state[index(pos)] = rng.gen_bool(noise);
+    }
+    for _ in 0..iterations {
+        let mut next = vec![false; state.len()];
+        for pos in room {
+            let empty_neighbours = DIRECTIONS
+                .iter()
+                .filter(|dir| !state[index(pos + **dir)])
+                .count();
+            next[index(pos)] = (1..=4).contains(&empty_neighbours);
+        }
+        state = next;
+    }
+    room.into_iter().filter(|pos| state[index(*pos)]).collect()
+}
+
+/// Lattice size of [`ValueNoise`]; the noise repeats every this many cells.
+const NOISE_PERIOD: usize = 256;
+
+/// Smooth random values over the plane: random values on a lattice of cells
+/// `scale` tiles wide, smoothly interpolated in between.
+#[derive(Debug, Clone)]
+pub struct ValueNoise {
+    scale: f64,
+    values: Vec<f64>,
+    perm: Vec<usize>,
+}
+
+impl ValueNoise {
+    pub fn new(scale: f64, rng: &mut impl Rng) -> Self {
+        let values = (0..NOISE_PERIOD).map(|_| rng.gen()).collect();
+        let mut perm = (0..NOISE_PERIOD).collect::<Vec<_>>();
+        perm.shuffle(rng);
+        Self {
+            scale,
+            values,
+            perm,
+        }
+    }
+
+    fn lattice(&self, x: i64, y: i64) -> f64 {
+        let n = NOISE_PERIOD as i64;
+        let i = self.perm[x.rem_euclid(n) as usize] as i64;
+        self.values[self.perm[(i + y).rem_euclid(n) as usize]]
+    }
+
+    /// The noise at `pos`, in `[0, 1)`.
+    pub fn get(&self, pos: Pos) -> f64 {
+        let (x, y) = (pos.x as f64 / self.scale, pos.y as f64 / self.scale);
+        let (x0, y0) = (x.floor(), y.floor());
+        let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+        let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let south = lerp(self.lattice(x0, y0), self.lattice(x0 + 1, y0), tx);
+        let north = lerp(self.lattice(x0, y0 + 1), self.lattice(x0 + 1, y0 + 1), tx);
+        lerp(south, north, ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::dfs;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn connected(graph: &RoomGraph) -> bool {
+        let Some(first) = graph.iter().next() else {
+            return true;
+        };
+        let mut seen = HashSet::from([first]);
+        let mut stack = vec![first];
+        while let Some(room) = stack.pop() {
+            for &adj in graph.get_adj(room).unwrap() {
+                if seen.insert(adj) {
+                    stack.push(adj);
+                }
+            }
+        }
+        seen.len() == graph.len()
+    }
+
+    #[test]
+    fn test_bsp_rooms() {
+        let rect = Rect::new(0, 59, 0, 39);
+        let opts = BspSplitOpts {
+            max_width: 12,
+            max_height: 10,
+            min_width: 4,
+            min_height: 3,
+        };
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let tree = gen_bsp_tree(rect, opts, &mut rng).unwrap();
+            let rooms = tree.rooms();
+            for (i, room) in rooms.iter().enumerate() {
+                assert!(rect.contains(room.topleft()) && rect.contains(room.bottomright()));
+                assert!(room.x2 - room.x1 <= opts.max_width);
+                assert!(room.y2 - room.y1 <= opts.max_height);
+                assert!(room.width() >= opts.min_width && room.height() >= opts.min_height);
+                assert!(rooms[i + 1..].iter().all(|other| !room.intersects(other)));
+            }
+
+            let mut graph = tree.into_room_graph().unwrap();
+            assert_eq!(graph.len(), rooms.len());
+            assert!(connected(&graph));
+            let edges = |graph: &RoomGraph| {
+                graph
+                    .iter()
+                    .map(|room| graph.get_adj(room).unwrap().len())
+                    .sum::<usize>()
+                    / 2
+            };
+            // a tree until loops are added
+            assert_eq!(edges(&graph), graph.len() - 1);
+            let added = graph.add_extra_loops(5, &mut rng);
+            assert!(added > 0);
+            assert_eq!(edges(&graph), graph.len() - 1 + added);
+            for room in graph.iter() {
+                for &adj in graph.get_adj(room).unwrap() {
+                    assert!(get_connecting_wall(room, adj).is_some());
+                }
+            }
+        }
+
+        let opts = BspSplitOpts {
+            max_width: 8,
+            max_height: 8,
+            min_width: 4,
+            min_height: 3,
+        };
+        assert_eq!(
+            gen_bsp_tree(rect, opts, &mut StdRng::seed_from_u64(0)),
+            Err(ProcgenError::BadBspOpts(opts))
+        );
+    }
+
+    #[test]
+    fn test_walks_and_caves() {
+        let bound = Rect::new(-10, 10, -5, 5);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let walk = drunkards_walk(Pos::new(0, 0), 200, bound, &mut rng);
+        assert!(walk.iter().all(|p| bound.contains(*p)));
+        let reachable = |p: Pos| {
+            p.adjacent_cardinal()
+                .into_iter()
+                .filter(|p| walk.contains(p))
+                .collect()
+        };
+        assert_eq!(dfs(&[Pos::new(0, 0)], reachable).count(), walk.len());
+
+        let mut map = TileMap::new('#');
+        carve_line_drunk(
+            &mut map,
+            bound.bottomleft(),
+            bound.topright(),
+            &mut rng,
+            0.3,
+            '.',
+            bound,
+        );
+        assert_eq!(map[bound.topright()], '.');
+        assert!(map.iter().all(|(p, t)| t == '#' || bound.contains(p)));
+
+        let cave = gen_cellular_automata(bound, 10, 0.6, &mut StdRng::seed_from_u64(1));
+        assert!(!cave.is_empty() && cave.iter().all(|p| bound.contains(*p)));
+        assert_eq!(
+            cave,
+            gen_cellular_automata(bound, 10, 0.6, &mut StdRng::seed_from_u64(1))
+        );
+    }
+
+    #[test]
+    fn test_value_noise() {
+        let noise = ValueNoise::new(8.0, &mut StdRng::seed_from_u64(0));
+        let same = ValueNoise::new(8.0, &mut StdRng::seed_from_u64(0));
+        let rect = Rect::new(-50, 50, -50, 50);
+        let (mut min, mut max) = (1.0f64, 0.0f64);
+        for pos in rect {
+            let value = noise.get(pos);
+            assert!((0.0..1.0).contains(&value));
+            assert_eq!(value, same.get(pos));
+            // smooth: a step moves at most 1.5 / scale
+            for next in pos.adjacent_cardinal() {
+                assert!((noise.get(next) - value).abs() < 0.2, "{pos:?}");
+            }
+            min = min.min(value);
+            max = max.max(value);
+        }
+        assert!(max - min > 0.5);
+        // lattice points are the random values themselves
+        assert_eq!(noise.get(Pos::new(16, -8)), noise.lattice(2, -1));
+    }
+}
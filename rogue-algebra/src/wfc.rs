@@ -0,0 +1,402 @@
+//! Wave function collapse, simple tiled model.
+//!
+//! Adjacency rules are learned from a sample grid: a tile may sit next to
+//! another in some direction only if it does somewhere in the sample. A
+//! `Rect` is then filled by repeatedly fixing the most constrained position
+//! to a tile, weighted by how common that tile is in the sample, and
+//! narrowing down its neighbours. Positions outside the `Rect` don't
+//! constrain it; pin tiles on its edges to control how it meets the map
+//! around it.
+use rand::Rng;
+
+use crate::{Pos, Rect, TileMap, CARDINALS};
+
+/// Most distinct tiles a sample can have.
+pub const MAX_TILES: usize = 64;
+
+/// How many times a contradiction restarts generation before giving up.
+const MAX_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WfcError {
+    /// The sample has more than [`MAX_TILES`] distinct tiles.
+    TooManyTiles(usize),
+    /// A pinned tile that doesn't appear in the sample.
+    UnknownTile(Pos),
+    /// A pinned tile outside the `Rect` being filled.
+    OutsideRect(Pos),
+    /// Every attempt ran into a position no tile fits.
+    Contradiction,
+}
+
+impl std::fmt::Display for WfcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WfcError::TooManyTiles(n) => {
+                write!(f, "sample has {n} distinct tiles, more than {MAX_TILES}")
+            }
+            WfcError::UnknownTile(pos) => write!(f, "tile pinned at {pos:?} is not in the sample"),
+            WfcError::OutsideRect(pos) => write!(f, "tile pinned at {pos:?} is outside the rect"),
+            WfcError::Contradiction => write!(f, "no tile fits somewhere in the rect"),
+        }
+    }
+}
+
+impl std::error::Error for WfcError {}
+
+/// Adjacency rules and tile frequencies learned from a sample.
+#[derive(Debug, Clone)]
+pub struct Rules<T> {
+    tiles: Vec<T>,
+    weights: Vec<u32>,
+    /// `allowed[d][t]` is the set of tiles that may sit at `CARDINALS[d]`
+    /// from tile `t`, as a bitset of tile indices.
+    allowed: [Vec<u64>; 4],
+}
+
+impl Rules<char> {
+    /// Learns rules from an ASCII grid, one row per line from north to south.
+    /// Blank lines are skipped.
+    pub fn from_ascii(sample: &str) -> Result<Self, WfcError> {
+        let rows = sample
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        Self::from_sample(&rows)
+    }
+}
+
+impl<T: Copy + PartialEq> Rules<T> {
+    /// Learns rules from a grid of rows from north to south. Rows may have
+    /// different lengths.
+    pub fn from_sample(rows: &[Vec<T>]) -> Result<Self, WfcError> {
+        let mut tiles: Vec<T> = Vec::new();
+        let mut weights = Vec::new();
+        for &tile in rows.iter().flatten() {
+            match tiles.iter().position(|t| *t == tile) {
+                Some(i) => weights[i] += 1,
+                None => {
+                    tiles.push(tile);
+                    weights.push(1);
+                }
+            }
+        }
+        if tiles.len() > MAX_TILES {
+            return Err(WfcError::TooManyTiles(tiles.len()));
+        }
+        let index = |tile: T| tiles.iter().position(|t| *t == tile).unwrap();
+        let mut allowed: [Vec<u64>; 4] = std::array::from_fn(|_| vec![0; tiles.len()]);
+        for (row, line) in rows.iter().enumerate() {
+            for (col, &tile) in line.iter().enumerate() {
+                for (d, offset) in CARDINALS.iter().enumerate() {
+                    // north is +y, but rows go south
+                    let (r, c) = (row as i32 - offset.y, col as i32 + offset.x);
+                    if r < 0 || c < 0 {
+                        continue;
+                    }
+                    let Some(neighbour) =
+                        rows.get(r as usize).and_then(|line| line.get(c as usize))
+                    else {
+                        continue;
+                    };
+                    allowed[d][index(tile)] |= 1 << index(*neighbour);
+                }
+            }
+        }
+        Ok(Self {
+            tiles,
+            weights,
+            allowed,
+        })
+    }
+
+    /// The distinct tiles of the sample.
+    pub fn tiles(&self) -> &[T] {
+        &self.tiles
+    }
+
+    /// Whether `b` may sit at `CARDINALS[direction]` from `a`.
+    pub fn allows(&self, a: T, direction: usize, b: T) -> bool {
+        match (self.index(a), self.index(b)) {
+            (Some(a), Some(b)) => self.allowed[direction][a] & (1 << b) != 0,
+            _ => false,
+        }
+    }
+
+    fn index(&self, tile: T) -> Option<usize> {
+        self.tiles.iter().position(|t| *t == tile)
+    }
+
+    /// Picks a tile for every position in `rect`, with the tiles in `pinned`
+    /// fixed in place, restarting a few times if it paints itself into a
+    /// corner.
+    pub fn generate(
+        &self,
+        rect: Rect,
+        pinned: &[(Pos, T)],
+        rng: &mut impl Rng,
+    ) -> Result<Vec<(Pos, T)>, WfcError> {
+        let constraints = self.constraints(rect, pinned)?;
+        for _ in 0..MAX_ATTEMPTS {
+            if let Some(tiles) = self.attempt(rect, &constraints, rng)? {
+                return Ok(tiles);
+            }
+        }
+        Err(WfcError::Contradiction)
+    }
+
+    /// Like [`Rules::generate`], but gives up on the first contradiction
+    /// instead of restarting.
+    pub fn generate_once(
+        &self,
+        rect: Rect,
+        pinned: &[(Pos, T)],
+        rng: &mut impl Rng,
+    ) -> Result<Vec<(Pos, T)>, WfcError> {
+        let constraints = self.constraints(rect, pinned)?;
+        self.attempt(rect, &constraints, rng)?
+            .ok_or(WfcError::Contradiction)
+    }
+
+    /// `pinned` as tile indices, checked against `rect` and the sample.
+    fn constraints(&self, rect: Rect, pinned: &[(Pos, T)]) -> Result<Vec<(Pos, usize)>, WfcError> {
+        if self.tiles.is_empty() {
+            return Err(WfcError::Contradiction);
+        }
+        pinned
+            .iter()
+            .map(|&(pos, tile)| {
+                if !rect.contains(pos) {
+                    return Err(WfcError::OutsideRect(pos));
+                }
+                let tile = self.index(tile).ok_or(WfcError::UnknownTile(pos))?;
+                Ok((pos, tile))
+            })
+            .collect()
+    }
+
+    /// One run of the collapse, or `None` if it ran into a contradiction.
+    fn attempt(
+        &self,
+        rect: Rect,
+        constraints: &[(Pos, usize)],
+        rng: &mut impl Rng,
+    ) -> Result<Option<Vec<(Pos, T)>>, WfcError> {
+        let mut wave = Wave::new(self, rect);
+        if !constraints
+            .iter()
+            .all(|&(pos, tile)| wave.restrict(pos, 1 << tile))
+        {
+            // pinned tiles that conflict will always conflict
+            return Err(WfcError::Contradiction);
+        }
+        if !wave.collapse(rng) {
+            return Ok(None);
+        }
+        Ok(Some(
+            rect.into_iter()
+                .map(|pos| {
+                    let cell = wave.cells[wave.index(pos)];
+                    (pos, self.tiles[cell.trailing_zeros() as usize])
+                })
+                .collect(),
+        ))
+    }
+
+    /// Like [`Rules::generate`], writing the result into `tile_map`.
+    pub fn fill(
+        &self,
+        tile_map: &mut TileMap<T>,
+        rect: Rect,
+        pinned: &[(Pos, T)],
+        rng: &mut impl Rng,
+    ) -> Result<(), WfcError> {
+        for (pos, tile) in self.generate(rect, pinned, rng)? {
+            tile_map[pos] = tile;
+        }
+        Ok(())
+    }
+}
+
+/// The tiles still possible at each position of a `Rect`, as bitsets.
+struct Wave<'a, T> {
+    rules: &'a Rules<T>,
+    rect: Rect,
+    cells: Vec<u64>,
+}
+
+impl<'a, T> Wave<'a, T> {
+    fn new(rules: &'a Rules<T>, rect: Rect) -> Self {
+        let all = u64::MAX >> (64 - rules.tiles.len());
+        Self {
+            rules,
+            rect,
+            cells: vec![all; (rect.width() * rect.height()) as usize],
+        }
+    }
+
+    fn index(&self, pos: Pos) -> usize {
+        ((pos.y - self.rect.y1) * self.rect.width() + (pos.x - self.rect.x1)) as usize
+    }
+
+    /// Narrows `pos` down to `options` and propagates that to the rest of
+    /// the wave. Returns false on a contradiction.
+    fn restrict(&mut self, pos: Pos, options: u64) -> bool {
+        let i = self.index(pos);
+        self.cells[i] &= options;
+        if self.cells[i] == 0 {
+            return false;
+        }
+        let mut stack = vec![pos];
+        while let Some(pos) = stack.pop() {
+            let cell = self.cells[self.index(pos)];
+            for (d, offset) in CARDINALS.iter().enumerate() {
+                let next = pos + *offset;
+                if !self.rect.contains(next) {
+                    continue;
+                }
+                let allowed = bits(cell).fold(0, |acc, t| acc | self.rules.allowed[d][t]);
+                let j = self.index(next);
+                let narrowed = self.cells[j] & allowed;
+                if narrowed != self.cells[j] {
+                    if narrowed == 0 {
+                        return false;
+                    }
+                    self.cells[j] = narrowed;
+                    stack.push(next);
+                }
+            }
+        }
+        true
+    }
+
+    /// Collapses positions one at a time until every one has a single tile.
+    /// Returns false on a contradiction.
+    fn collapse(&mut self, rng: &mut impl Rng) -> bool {
+        loop {
+            // The undecided position with the fewest options. Scanning from a
+            // random start and stopping at the first position with two
+            // options breaks ties at random without a full scan.
+            let start = rng.gen_range(0..self.cells.len());
+            let mut best: Option<(usize, u32)> = None;
+            for i in (start..self.cells.len()).chain(0..start) {
+                let options = self.cells[i].count_ones();
+                if options > 1 && best.is_none_or(|(_, fewest)| options < fewest) {
+                    best = Some((i, options));
+                    if options == 2 {
+                        break;
+                    }
+                }
+            }
+            let Some((i, _)) = best else {
+                return true;
+            };
+            let width = self.rect.width() as usize;
+            let pos = Pos::new(
+                self.rect.x1 + (i % width) as i32,
+                self.rect.y1 + (i / width) as i32,
+            );
+            let cell = self.cells[i];
+            let total: u32 = bits(cell).map(|t| self.rules.weights[t]).sum();
+            let mut roll = rng.gen_range(0..total);
+            let tile = bits(cell)
+                .find(|&t| {
+                    let weight = self.rules.weights[t];
+                    if roll < weight {
+                        true
+                    } else {
+                        roll -= weight;
+                        false
+                    }
+                })
+                .unwrap();
+            if !self.restrict(pos, 1 << tile) {
+                return false;
+            }
+        }
+    }
+}
+
+/// Indices of the set bits of `set`.
+fn bits(mut set: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        let i = set.trailing_zeros() as usize;
+        (set != 0).then(|| {
+            set &= set - 1;
+            i
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const SAMPLE: &str = "
+........
+.<=>.<>.
+.<>..<>.
+........
+";
+
+    #[test]
+    fn test_generate_follows_sample() {
+        let rules = Rules::from_ascii(SAMPLE).unwrap();
+        assert_eq!(rules.tiles(), &['.', '<', '=', '>']);
+        // `>` is only ever followed by `.` to the east
+        assert!(rules.allows('>', 2, '.'));
+        assert!(!rules.allows('>', 2, '='));
+
+        let rect = Rect::new(0, 19, 0, 9);
+        let pinned = [(Pos::new(0, 5), '.'), (Pos::new(19, 5), '>')];
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut map = TileMap::new(' ');
+            rules.fill(&mut map, rect, &pinned, &mut rng).unwrap();
+            for (pos, tile) in pinned {
+                assert_eq!(map[pos], tile);
+            }
+            for pos in rect {
+                for (d, offset) in CARDINALS.iter().enumerate() {
+                    let next = pos + *offset;
+                    if rect.contains(next) {
+                        assert!(
+                            rules.allows(map[pos], d, map[next]),
+                            "seed {seed} at {pos:?}"
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            rules.generate(rect, &[(Pos::new(0, 0), '#')], &mut rng),
+            Err(WfcError::UnknownTile(Pos::new(0, 0)))
+        );
+        assert_eq!(
+            rules.generate(rect, &[(Pos::new(20, 0), '.')], &mut rng),
+            Err(WfcError::OutsideRect(Pos::new(20, 0)))
+        );
+        // `<` can't be east of `<`
+        assert_eq!(
+            rules.generate(
+                rect,
+                &[(Pos::new(0, 0), '<'), (Pos::new(1, 0), '<')],
+                &mut rng
+            ),
+            Err(WfcError::Contradiction)
+        );
+        assert_eq!(
+            rules.generate_once(rect, &[(Pos::new(20, 0), '.')], &mut rng),
+            Err(WfcError::OutsideRect(Pos::new(20, 0)))
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            rules.generate_once(rect, &pinned, &mut rng.clone()),
+            rules.generate(rect, &pinned, &mut rng)
+        );
+    }
+}
@@ -2,11 +2,12 @@ use std::{collections::HashMap, path::Path};
 
 use bevy::{asset::embedded_asset, prelude::*};
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
+    bestiary::MobSprite,
     map::{ItemKind, TILE_HEIGHT, TILE_WIDTH, TileKind},
-    mob::MobKind,
     player::GunType,
-    spawn::Spawn,
 };
 
 pub static PRESS_START_2P_BYTES: &[u8] =
@@ -40,7 +41,7 @@ pub struct GameAssets {
     pub sfx: Sfx,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum SpriteSheet {
     Urizen,
     OryxAvatar,
@@ -49,10 +50,49 @@ pub enum SpriteSheet {
     OryxMonsters,
 }
 
+impl SpriteSheet {
+    pub const ALL: [SpriteSheet; 5] = [
+        SpriteSheet::Urizen,
+        SpriteSheet::OryxAvatar,
+        SpriteSheet::OryxTerrain,
+        SpriteSheet::OryxTerrainObjects,
+        SpriteSheet::OryxMonsters,
+    ];
+
+    /// Columns and rows of sprites on the sheet.
+    fn grid(self) -> (u32, u32) {
+        match self {
+            SpriteSheet::Urizen => (103, 50),
+            SpriteSheet::OryxAvatar => (6, 2),
+            SpriteSheet::OryxTerrain => (16, 11),
+            SpriteSheet::OryxTerrainObjects => (20, 12),
+            SpriteSheet::OryxMonsters => (19, 26),
+        }
+    }
+
+    fn layout(
+        self,
+        tile_size: UVec2,
+        padding: Option<UVec2>,
+        offset: Option<UVec2>,
+    ) -> TextureAtlasLayout {
+        let (columns, rows) = self.grid();
+        TextureAtlasLayout::from_grid(tile_size, columns, rows, padding, offset)
+    }
+
+    /// Number of sprites on the sheet.
+    pub fn sprite_count(self) -> usize {
+        let (columns, rows) = self.grid();
+        (columns * rows) as usize
+    }
+}
+
 #[derive(Debug)]
 pub enum SpriteKind {
     Player,
-    Spawn(Spawn),
+    Tile(TileKind),
+    Item(ItemKind),
+    Mob(MobSprite),
 }
 
 impl GameAssets {
@@ -74,98 +114,77 @@ impl GameAssets {
     pub fn get_sprite(&self, kind: SpriteKind) -> Sprite {
         let mut sprite = match kind {
             SpriteKind::Player => self.get_sprite_by_index(SpriteSheet::OryxAvatar, 1),
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Wall)) => {
+            SpriteKind::Mob(MobSprite { sheet, index, .. }) => {
+                self.get_sprite_by_index(sheet, index)
+            }
+            SpriteKind::Tile(TileKind::Wall) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrain, 0)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::ShippingContainer)) => {
+            SpriteKind::Tile(TileKind::ShippingContainer) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 7)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Crate)) => {
+            SpriteKind::Tile(TileKind::Crate) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 20 * 3 + 5)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Lever)) => {
+            SpriteKind::Tile(TileKind::Lever) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 20 * 7 + 9)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::LeverPulled)) => {
+            SpriteKind::Tile(TileKind::LeverPulled) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 20 * 7 + 10)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Bush)) => {
+            SpriteKind::Tile(TileKind::Bush) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 20 * 10 + 3)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Tree)) => {
+            SpriteKind::Tile(TileKind::Tree) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 20 * 6 + 6)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Door)) => {
+            SpriteKind::Tile(TileKind::Door) => {
                 self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 20 + 4)
             }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Zombie)) => {
-                self.get_sprite_by_index(SpriteSheet::OryxMonsters, 8 * 19 + 18)
-            }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Sculpture)) => {
-                self.get_sprite_by_index(SpriteSheet::OryxTerrainObjects, 20 * 3)
-            }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Hider)) => {
-                self.get_sprite_by_index(SpriteSheet::OryxMonsters, 4 * 19 + 2)
+            SpriteKind::Tile(TileKind::StairsDown) => {
+                self.get_sprite_by_index(SpriteSheet::Urizen, 103 + 23)
             }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Ghost)) => {
-                self.get_sprite_by_index(SpriteSheet::OryxMonsters, 16 * 19 + 2)
+            SpriteKind::Tile(TileKind::StairsUp) => {
+                self.get_sprite_by_index(SpriteSheet::Urizen, 103 + 24)
             }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::KoolAidMan)) => {
-                self.get_sprite_by_index(SpriteSheet::OryxMonsters, 14 * 19 + 15)
-            }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Eyeball)) => {
-                self.get_sprite_by_index(SpriteSheet::OryxMonsters, 14 * 19 + 7)
-            }
-            SpriteKind::Spawn(Spawn::Item(ItemKind::Ammo(GunType::Pistol, ..))) => {
+            SpriteKind::Item(ItemKind::Ammo(GunType::Pistol, ..)) => {
                 self.get_sprite_by_index(SpriteSheet::Urizen, 103 * 22 + 68)
             }
-            SpriteKind::Spawn(Spawn::Item(ItemKind::Ammo(GunType::Shotgun, ..))) => {
+            SpriteKind::Item(ItemKind::Ammo(GunType::Shotgun, ..)) => {
                 self.get_sprite_by_index(SpriteSheet::Urizen, 103 * 22 + 71)
             }
-            SpriteKind::Spawn(Spawn::Item(ItemKind::Gun(GunType::Pistol, ..))) => {
+            SpriteKind::Item(ItemKind::Gun(GunType::Pistol, ..)) => {
                 self.get_sprite_by_index(SpriteSheet::Urizen, 103 * 22 + 52)
             }
-            SpriteKind::Spawn(Spawn::Item(ItemKind::Gun(GunType::Shotgun, ..))) => {
+            SpriteKind::Item(ItemKind::Gun(GunType::Shotgun, ..)) => {
                 self.get_sprite_by_index(SpriteSheet::Urizen, 103 * 22 + 57)
             }
         };
         sprite.color = match kind {
             SpriteKind::Player => Color::LinearRgba(LinearRgba::WHITE),
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Wall)) => Color::LinearRgba(LinearRgba::WHITE),
-            SpriteKind::Spawn(Spawn::Tile(TileKind::ShippingContainer)) => {
+            SpriteKind::Mob(MobSprite {
+                color: (r, g, b), ..
+            }) => Color::srgb_u8(r, g, b),
+            SpriteKind::Tile(TileKind::Wall) => Color::LinearRgba(LinearRgba::WHITE),
+            SpriteKind::Tile(TileKind::ShippingContainer) => {
                 Color::srgba_u8(0xad, 0x4e, 0x37, 0xff)
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Crate)) => {
+            SpriteKind::Tile(TileKind::Crate) => {
                 Color::LinearRgba(bevy::color::palettes::basic::GRAY.into())
             }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Bush)) => Color::LinearRgba(LinearRgba::GREEN),
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Tree)) => {
-                Color::LinearRgba(LinearRgba::rgb(0.0, 0.5, 0.0))
-            }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Door)) => {
-                Color::srgba_u8(0xad, 0x4e, 0x37, 0xff)
-            }
-            SpriteKind::Spawn(Spawn::Tile(TileKind::Lever | TileKind::LeverPulled)) => {
+            SpriteKind::Tile(TileKind::Bush) => Color::LinearRgba(LinearRgba::GREEN),
+            SpriteKind::Tile(TileKind::Tree) => Color::LinearRgba(LinearRgba::rgb(0.0, 0.5, 0.0)),
+            SpriteKind::Tile(TileKind::Door) => Color::srgba_u8(0xad, 0x4e, 0x37, 0xff),
+            SpriteKind::Tile(TileKind::Lever | TileKind::LeverPulled) => {
                 Color::srgba_u8(0xad, 0x4e, 0x37, 0xff)
             }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Zombie)) => {
-                Color::LinearRgba(LinearRgba::rgb(1.0, 0.0, 1.0))
-            }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Sculpture)) => {
-                Color::srgba_u8(0xad, 0x4e, 0x37, 0xff)
-            }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Hider)) => {
-                Color::LinearRgba(bevy::color::palettes::basic::AQUA.into())
-            }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::KoolAidMan)) => {
-                Color::LinearRgba(LinearRgba::RED)
+            SpriteKind::Tile(TileKind::StairsDown | TileKind::StairsUp) => {
+                Color::LinearRgba(LinearRgba::WHITE)
             }
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Eyeball)) => Color::LinearRgba(LinearRgba::BLUE),
-            SpriteKind::Spawn(Spawn::Mob(MobKind::Ghost)) => Color::LinearRgba(LinearRgba::WHITE),
-            SpriteKind::Spawn(Spawn::Item(ItemKind::Ammo(..))) => {
+            SpriteKind::Item(ItemKind::Ammo(..)) => {
                 Color::LinearRgba(bevy::color::palettes::basic::GRAY.into())
             }
-            SpriteKind::Spawn(Spawn::Item(ItemKind::Gun(..))) => {
+            SpriteKind::Item(ItemKind::Gun(..)) => {
                 Color::LinearRgba(bevy::color::palettes::basic::GRAY.into())
             }
         };
@@ -173,6 +192,26 @@ impl GameAssets {
     }
 }
 
+#[cfg(test)]
+impl GameAssets {
+    /// Assets with nothing loaded behind the handles, enough to spawn with.
+    pub fn placeholder() -> Self {
+        Self {
+            font: default(),
+            pixel: default(),
+            white: default(),
+            sight_line: default(),
+            reload_indicator_mesh: default(),
+            reload_indicator_material: default(),
+            sheets: SpriteSheet::ALL
+                .into_iter()
+                .map(|sheet| (sheet, (default(), default())))
+                .collect(),
+            sfx: Sfx::default(),
+        }
+    }
+}
+
 fn add_audio_sources(
     base_dir: &Path,
     track_names: &[&'static str],
@@ -256,10 +295,8 @@ fn init_assets(
         SpriteSheet::Urizen,
         (
             asset_server.load("urizen_onebit_tileset__v1d1.png"),
-            texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
+            texture_atlas_layouts.add(SpriteSheet::Urizen.layout(
                 UVec2::splat(12),
-                103,
-                50,
                 Some(UVec2::splat(1)),
                 Some(UVec2::splat(1)),
             )),
@@ -269,10 +306,8 @@ fn init_assets(
         SpriteSheet::OryxAvatar,
         (
             asset_server.load("embedded://something_in_the_fog/../oryx_roguelike_2.0/Avatar.png"),
-            texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
+            texture_atlas_layouts.add(SpriteSheet::OryxAvatar.layout(
                 UVec2::new(16, 24),
-                6,
-                2,
                 None,
                 None,
             )),
@@ -282,10 +317,8 @@ fn init_assets(
         SpriteSheet::OryxTerrain,
         (
             asset_server.load("embedded://something_in_the_fog/../oryx_roguelike_2.0/Terrain.png"),
-            texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
+            texture_atlas_layouts.add(SpriteSheet::OryxTerrain.layout(
                 UVec2::new(16, 24),
-                16,
-                11,
                 None,
                 None,
             )),
@@ -296,10 +329,8 @@ fn init_assets(
         (
             asset_server
                 .load("embedded://something_in_the_fog/../oryx_roguelike_2.0/Terrain_Objects.png"),
-            texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
+            texture_atlas_layouts.add(SpriteSheet::OryxTerrainObjects.layout(
                 UVec2::new(16, 24),
-                20,
-                12,
                 None,
                 Some(UVec2::new(0, 1)),
             )),
@@ -309,10 +340,8 @@ fn init_assets(
         SpriteSheet::OryxMonsters,
         (
             asset_server.load("embedded://something_in_the_fog/../oryx_roguelike_2.0/Monsters.png"),
-            texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
+            texture_atlas_layouts.add(SpriteSheet::OryxMonsters.layout(
                 UVec2::new(16, 24),
-                19,
-                26,
                 None,
                 None,
             )),
@@ -351,6 +380,25 @@ fn init_assets(
     });
 }
 
+/// Reads a RON asset from `path` with `parse`, so it can be tweaked without
+/// recompiling. Falls back to `default`, the built-in copy, if the file can't
+/// be read or parsed; `what` names the asset in the log.
+pub fn load_ron_or_default<T, E: std::fmt::Display>(
+    path: &str,
+    what: &str,
+    parse: fn(&str) -> Result<T, E>,
+    default: fn() -> T,
+) -> T {
+    match std::fs::read_to_string(path) {
+        Ok(ron) => match parse(&ron) {
+            Ok(value) => return value,
+            Err(e) => warn!("failed to parse {path}: {e}"),
+        },
+        Err(e) => info!("using built-in {what}, couldn't read {path}: {e}"),
+    }
+    default()
+}
+
 #[derive(Default)]
 pub struct AssetsPlugin;
 
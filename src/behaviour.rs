@@ -0,0 +1,515 @@
+//! Mob behaviours. A bestiary entry lists a mob's behaviours in priority
+//! order, and whenever the mob is ready to move the first one with an opinion
+//! decides what it does. Behaviours that pass can still change how the later
+//! ones move, so a mob that freezes when watched sneaks when it chases. New
+//! kinds of mob are new combinations of these; `move_mobs` only carries out
+//! what they decide.
+use std::time::Duration;
+
+use bevy::prelude::*;
+use line_drawing::WalkGrid;
+use rand::{Rng, seq::SliceRandom};
+use rogue_algebra::dijkstra::DijkstraMap;
+use serde::{Deserialize, Serialize};
+
+use crate::map::{FovMap, HidingSpotMap, PlayerVisibilityMap, SightBlockedMap, WalkBlockedMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Behaviour {
+    /// Goes for wherever it last saw or heard the player.
+    Chase,
+    /// Keeps to cover, leaving it to later behaviours once the player is
+    /// within `chase_distance` tiles or hurts it in view.
+    Hide { chase_distance: i32 },
+    /// Charges at the player in a straight line, `overshoot` tiles past them
+    /// and through any walls in the way, then rests for `rest_ms`.
+    Charge { overshoot: usize, rest_ms: u64 },
+    /// Stands still while the player can see it, and otherwise keeps out of
+    /// sight and off doors.
+    FreezeWhenWatched,
+    /// Ambles to a random free neighbouring tile in its zone.
+    Wander,
+    /// Walks the loop of waypoints it was given when its zone was generated.
+    Patrol,
+    /// Sleeps until it hears a noise at least `wake_loudness` loud or gets
+    /// hurt, seeing nothing in the meantime.
+    Sleep { wake_loudness: i32 },
+}
+
+/// What a mob has decided to do with its move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intent {
+    Stay,
+    /// Head for a tile, finding the way there.
+    Toward(IVec2),
+    /// Step straight onto a neighbouring tile.
+    Step(IVec2),
+}
+
+/// How a mob gets about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Gait {
+    /// Stay out of the player's sight, and never step into it.
+    pub avoid_sight: bool,
+    /// Never step onto tiles that block sight, like doors.
+    pub avoid_doors: bool,
+    /// Steps go through walls, knocking them down.
+    pub through_walls: bool,
+}
+
+/// Where a charging mob is in its charge.
+#[derive(Component, Debug)]
+pub enum ChargeState {
+    Moving(Vec<IVec2>),
+    Resting(Timer),
+}
+
+impl Default for ChargeState {
+    fn default() -> Self {
+        Self::Resting(Timer::new(Duration::from_secs(0), TimerMode::Once))
+    }
+}
+
+impl ChargeState {
+    /// Called once the mob has taken the next step of its charge.
+    pub fn advance(&mut self) {
+        match self {
+            ChargeState::Moving(path) if !path.is_empty() => {
+                path.remove(0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The patrol loop a patrolling mob walks, and which waypoint it's headed for.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct PatrolRoute {
+    pub waypoints: Vec<IVec2>,
+    pub next: usize,
+}
+
+impl PatrolRoute {
+    /// Joins `waypoints` at the one nearest `pos`.
+    pub fn join(waypoints: Vec<IVec2>, pos: IVec2) -> Self {
+        let next = (0..waypoints.len())
+            .min_by_key(|&i| waypoints[i].distance_squared(pos))
+            .unwrap_or(0);
+        PatrolRoute { waypoints, next }
+    }
+}
+
+/// A mob that's asleep; see [`Behaviour::Sleep`].
+#[derive(Component, Debug)]
+pub struct Asleep;
+
+/// What a mob remembers between moves, for the behaviours that need it.
+#[derive(Default)]
+pub struct Memory<'a> {
+    pub charge: Option<&'a mut ChargeState>,
+    pub patrol: Option<&'a mut PatrolRoute>,
+}
+
+/// What a mob knows about where it is.
+pub struct Surroundings<'a> {
+    pub pos: IVec2,
+    /// The zone it's in, if any.
+    pub zone: Option<IRect>,
+    pub asleep: bool,
+    pub last_known_player_pos: Option<IVec2>,
+    pub hurt: bool,
+    pub walk_blocked_map: &'a WalkBlockedMap,
+    pub sight_blocked_map: &'a SightBlockedMap,
+    pub vis_map: &'a PlayerVisibilityMap,
+    pub fov_map: &'a FovMap,
+    pub hiding_spot_map: &'a HidingSpotMap,
+}
+
+/// Runs a mob's behaviours in order until one decides what it does. Mobs
+/// none of them have an opinion for stay put.
+pub fn decide(
+    behaviours: &[Behaviour],
+    surroundings: &Surroundings,
+    memory: Memory,
+    delta: Duration,
+    rng: &mut impl Rng,
+) -> (Intent, Gait) {
+    let Memory {
+        mut charge,
+        mut patrol,
+    } = memory;
+    let mut gait = Gait::default();
+    for behaviour in behaviours {
+        let intent = match *behaviour {
+            Behaviour::Chase => surroundings.last_known_player_pos.map(Intent::Toward),
+            Behaviour::Hide { chase_distance } => hide(surroundings, chase_distance),
+            Behaviour::Charge { overshoot, rest_ms } => charge.as_deref_mut().map(|charge| {
+                let rest = Duration::from_millis(rest_ms);
+                let intent = self::charge(surroundings, charge, overshoot, rest, delta);
+                gait.through_walls = matches!(intent, Intent::Step(_));
+                intent
+            }),
+            Behaviour::FreezeWhenWatched => {
+                gait.avoid_sight = true;
+                gait.avoid_doors = true;
+                surroundings
+                    .fov_map
+                    .0
+                    .contains(&surroundings.pos)
+                    .then_some(Intent::Stay)
+            }
+            Behaviour::Wander => wander(surroundings, rng),
+            Behaviour::Patrol => patrol
+                .as_deref_mut()
+                .and_then(|route| self::patrol(surroundings, route)),
+            Behaviour::Sleep { .. } => surroundings.asleep.then_some(Intent::Stay),
+        };
+        if let Some(intent) = intent {
+            return (intent, gait);
+        }
+    }
+    (Intent::Stay, gait)
+}
+
+fn hide(surroundings: &Surroundings, chase_distance: i32) -> Option<Intent> {
+    let Surroundings { pos, .. } = *surroundings;
+    let player_close = surroundings
+        .last_known_player_pos
+        .is_some_and(|p| p.distance_squared(pos) <= chase_distance * chase_distance);
+    let hurt_in_view = surroundings.hurt && surroundings.vis_map.0.contains(pos.into());
+    if surroundings.last_known_player_pos.is_some() && (player_close || hurt_in_view) {
+        return None;
+    }
+    // already hidden, or heading for the next tile towards cover
+    if surroundings.sight_blocked_map.0.contains(&pos) {
+        return Some(Intent::Stay);
+    }
+    let cover = step_down(
+        &surroundings.hiding_spot_map.0,
+        pos,
+        surroundings.walk_blocked_map,
+    )
+    .or_else(|| {
+        find_hiding_spot(
+            pos,
+            surroundings.walk_blocked_map,
+            surroundings.sight_blocked_map,
+        )
+    });
+    Some(cover.map_or(Intent::Stay, Intent::Toward))
+}
+
+fn charge(
+    surroundings: &Surroundings,
+    state: &mut ChargeState,
+    overshoot: usize,
+    rest: Duration,
+    delta: Duration,
+) -> Intent {
+    match state {
+        ChargeState::Moving(path) => match path.first() {
+            Some(&next) => Intent::Step(next),
+            None => {
+                *state = ChargeState::Resting(Timer::new(rest, TimerMode::Once));
+                Intent::Stay
+            }
+        },
+        ChargeState::Resting(timer) => {
+            timer.tick(delta);
+            let target = surroundings.last_known_player_pos;
+            if let Some(target) = target.filter(|_| timer.finished()) {
+                let source = surroundings.pos;
+                let mut path: Vec<IVec2> = WalkGrid::new(source.into(), target.into())
+                    .map(From::from)
+                    .collect();
+                let overshot = target + (target - source);
+                path.extend(
+                    WalkGrid::new(target.into(), overshot.into())
+                        .map(IVec2::from)
+                        .take(overshoot),
+                );
+                *state = ChargeState::Moving(path);
+            }
+            Intent::Stay
+        }
+    }
+}
+
+fn wander(surroundings: &Surroundings, rng: &mut impl Rng) -> Option<Intent> {
+    let free = rogue_algebra::Pos::from(surroundings.pos)
+        .adjacent_cardinal()
+        .map(IVec2::from)
+        .into_iter()
+        .filter(|p| {
+            !surroundings.walk_blocked_map.0.contains(p)
+                && surroundings.zone.is_none_or(|zone| zone.contains(*p))
+        })
+        .collect::<Vec<_>>();
+    free.choose(rng).copied().map(Intent::Step)
+}
+
+fn patrol(surroundings: &Surroundings, route: &mut PatrolRoute) -> Option<Intent> {
+    if route.waypoints.is_empty() {
+        return None;
+    }
+    route.next %= route.waypoints.len();
+    if route.waypoints[route.next] == surroundings.pos {
+        route.next = (route.next + 1) % route.waypoints.len();
+    }
+    Some(Intent::Toward(route.waypoints[route.next]))
+}
+
+/// The next step down `map` from `source`, if `source` is near enough for the
+/// map to cover it and no mob has moved into the way since the map was made.
+pub fn step_down(
+    map: &DijkstraMap,
+    source: IVec2,
+    walk_blocked_map: &WalkBlockedMap,
+) -> Option<IVec2> {
+    map.downhill(source.into())
+        .map(IVec2::from)
+        .filter(|p| !walk_blocked_map.0.contains(p))
+}
+
+fn find_hiding_spot(
+    source: IVec2,
+    walk_blocked_map: &WalkBlockedMap,
+    sight_blocked_map: &SightBlockedMap,
+) -> Option<IVec2> {
+    pathfinding::directed::bfs::bfs_reach(source, |&p| {
+        rogue_algebra::Pos::from(p)
+            .adjacent_cardinal()
+            .map(IVec2::from)
+            .into_iter()
+            .filter(|p| !walk_blocked_map.0.contains(p))
+    })
+    .find(|&p| sight_blocked_map.0.contains(&p))
+}
+
+#[cfg(test)]
+mod tests {
+    use rogue_algebra::bitgrid::BitGrid;
+
+    use super::*;
+
+    struct Maps {
+        walk_blocked: WalkBlockedMap,
+        sight_blocked: SightBlockedMap,
+        vis: PlayerVisibilityMap,
+        fov: FovMap,
+        hiding_spot: HidingSpotMap,
+    }
+
+    impl Maps {
+        /// A mob at the origin with cover 3 tiles east, in plain view of the
+        /// player.
+        fn new() -> Self {
+            let mut vis = BitGrid::new(rogue_algebra::Rect::new(-10, 10, -10, 10));
+            vis.insert(rogue_algebra::Pos::new(0, 0));
+            // fence the mob in so looking for cover doesn't go on forever
+            let walk_blocked = rogue_algebra::Rect::new(-1, 4, -1, 1)
+                .into_iter()
+                .map(IVec2::from)
+                .filter(|p| p.y != 0 || p.x == -1 || p.x == 4)
+                .collect();
+            Maps {
+                walk_blocked: WalkBlockedMap(walk_blocked),
+                sight_blocked: SightBlockedMap([IVec2::new(3, 0)].into()),
+                vis: PlayerVisibilityMap(vis),
+                fov: FovMap([IVec2::ZERO].into()),
+                hiding_spot: HidingSpotMap::default(),
+            }
+        }
+
+        fn surroundings(&self, player: Option<IVec2>, hurt: bool) -> Surroundings<'_> {
+            Surroundings {
+                pos: IVec2::ZERO,
+                zone: None,
+                asleep: false,
+                last_known_player_pos: player,
+                hurt,
+                walk_blocked_map: &self.walk_blocked,
+                sight_blocked_map: &self.sight_blocked,
+                vis_map: &self.vis,
+                fov_map: &self.fov,
+                hiding_spot_map: &self.hiding_spot,
+            }
+        }
+    }
+
+    #[test]
+    fn test_behaviours() {
+        use Behaviour::*;
+        let mut rng = rand::thread_rng();
+        let mut maps = Maps::new();
+        let far = IVec2::new(-20, 0);
+        let near = IVec2::new(-2, 0);
+        let mut decide = |behaviours: &[Behaviour], s: &Surroundings| {
+            decide(behaviours, s, Memory::default(), Duration::ZERO, &mut rng)
+        };
+
+        assert_eq!(
+            decide(&[Chase], &maps.surroundings(None, false)).0,
+            Intent::Stay
+        );
+        assert_eq!(
+            decide(&[Chase], &maps.surroundings(Some(far), false)),
+            (Intent::Toward(far), Gait::default())
+        );
+
+        // hiders make for cover until the player comes close or hurts them
+        let hider = [Hide { chase_distance: 5 }, Chase];
+        let cover = Intent::Toward(IVec2::new(3, 0));
+        assert_eq!(decide(&hider, &maps.surroundings(None, false)).0, cover);
+        assert_eq!(
+            decide(&hider, &maps.surroundings(Some(far), false)).0,
+            cover
+        );
+        assert_eq!(
+            decide(&hider, &maps.surroundings(Some(near), false)).0,
+            Intent::Toward(near)
+        );
+        assert_eq!(
+            decide(&hider, &maps.surroundings(Some(far), true)).0,
+            Intent::Toward(far)
+        );
+
+        // statues freeze while watched and sneak otherwise
+        let statue = [FreezeWhenWatched, Chase];
+        assert_eq!(
+            decide(&statue, &maps.surroundings(Some(far), false)).0,
+            Intent::Stay
+        );
+        maps.fov.0.clear();
+        let (intent, gait) = decide(&statue, &maps.surroundings(Some(far), false));
+        assert_eq!(intent, Intent::Toward(far));
+        assert!(gait.avoid_sight && gait.avoid_doors && !gait.through_walls);
+
+        assert_eq!(
+            decide(&[Wander], &maps.surroundings(Some(far), false)).0,
+            Intent::Step(IVec2::new(1, 0))
+        );
+    }
+
+    #[test]
+    fn test_idle_behaviours() {
+        use Behaviour::*;
+        let mut rng = rand::thread_rng();
+        let maps = Maps::new();
+        let player = Some(IVec2::new(-20, 0));
+
+        // sleepers ignore what they know until they wake up
+        let sleeper = [Sleep { wake_loudness: 10 }, Chase];
+        let mut surroundings = maps.surroundings(player, false);
+        surroundings.asleep = true;
+        let sleeping = decide(
+            &sleeper,
+            &surroundings,
+            Memory::default(),
+            Duration::ZERO,
+            &mut rng,
+        );
+        assert_eq!(sleeping.0, Intent::Stay);
+        surroundings.asleep = false;
+        let awake = decide(
+            &sleeper,
+            &surroundings,
+            Memory::default(),
+            Duration::ZERO,
+            &mut rng,
+        );
+        assert_eq!(awake.0, Intent::Toward(IVec2::new(-20, 0)));
+
+        // patrollers head on to the next waypoint once they reach one
+        let mut route = PatrolRoute::join(
+            vec![IVec2::new(5, 5), IVec2::ZERO, IVec2::new(2, 0)],
+            IVec2::ZERO,
+        );
+        assert_eq!(route.next, 1);
+        let memory = Memory {
+            patrol: Some(&mut route),
+            ..default()
+        };
+        let surroundings = maps.surroundings(None, false);
+        let patrolling = decide(
+            &[Chase, Patrol],
+            &surroundings,
+            memory,
+            Duration::ZERO,
+            &mut rng,
+        );
+        assert_eq!(patrolling.0, Intent::Toward(IVec2::new(2, 0)));
+        assert_eq!(route.next, 2);
+        // with no route they fall back on what comes next
+        let no_route = decide(
+            &[Patrol, Wander],
+            &surroundings,
+            Memory::default(),
+            Duration::ZERO,
+            &mut rng,
+        );
+        assert_eq!(no_route.0, Intent::Step(IVec2::new(1, 0)));
+
+        // wanderers stay in their zone
+        let mut surroundings = maps.surroundings(None, false);
+        surroundings.zone = Some(IRect::new(-5, -5, 0, 5));
+        let wandering = decide(
+            &[Wander],
+            &surroundings,
+            Memory::default(),
+            Duration::ZERO,
+            &mut rng,
+        );
+        assert_eq!(wandering.0, Intent::Stay);
+    }
+
+    #[test]
+    fn test_charge() {
+        let maps = Maps::new();
+        let mut rng = rand::thread_rng();
+        let charger = [Behaviour::Charge {
+            overshoot: 2,
+            rest_ms: 1000,
+        }];
+        let surroundings = maps.surroundings(Some(IVec2::new(3, 0)), false);
+        let mut state = ChargeState::default();
+        let mut decide = |state: &mut ChargeState| {
+            decide(
+                &charger,
+                &surroundings,
+                Memory {
+                    charge: Some(state),
+                    ..default()
+                },
+                Duration::ZERO,
+                &mut rng,
+            )
+        };
+
+        // lines up a charge, then takes it through the wall
+        assert_eq!(decide(&mut state).0, Intent::Stay);
+        let mut steps = Vec::new();
+        loop {
+            match decide(&mut state) {
+                (Intent::Step(step), gait) => {
+                    assert!(gait.through_walls);
+                    steps.push(step);
+                    state.advance();
+                }
+                (intent, _) => {
+                    assert_eq!(intent, Intent::Stay);
+                    break;
+                }
+            }
+        }
+        let expected = (0..=3)
+            .chain(3..=4)
+            .map(|x| IVec2::new(x, 0))
+            .collect::<Vec<_>>();
+        assert_eq!(steps, expected);
+        assert!(matches!(state, ChargeState::Resting(_)));
+        // and rests before the next one
+        assert_eq!(decide(&mut state).0, Intent::Stay);
+        assert!(matches!(state, ChargeState::Resting(_)));
+    }
+}
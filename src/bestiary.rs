@@ -0,0 +1,314 @@
+//! Mob definitions, read from `assets/bestiary.ron`: how tough and fast each
+//! kind of mob is, what it senses, what it looks like and how it behaves.
+//! Adding a monster is a matter of adding an entry there.
+use std::{collections::BTreeMap, time::Duration};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::{SpriteSheet, load_ron_or_default},
+    behaviour::Behaviour,
+    difficulty::Difficulty,
+    mob::{LightSensitivity, MobName},
+    sound::MusicLayer,
+    vision::Vision,
+};
+
+/// Bestiary shipped with the game.
+pub const DEFAULT_BESTIARY: &str = include_str!("../assets/bestiary.ron");
+
+/// Where the game reads the bestiary from at startup.
+pub const BESTIARY_PATH: &str = "assets/bestiary.ron";
+
+/// Which kind of monster a mob is: the index of its entry in the
+/// [`Bestiary`], so it's cheap to copy, compare and look up. Only the
+/// bestiary hands them out; see [`Bestiary::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MobKind(usize);
+
+/// How a mob eases from one tile to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MoveEase {
+    Linear,
+    BounceIn,
+    BounceOut,
+    CubicIn,
+    CircularInOut,
+}
+
+impl From<MoveEase> for EaseFunction {
+    fn from(ease: MoveEase) -> Self {
+        match ease {
+            MoveEase::Linear => EaseFunction::Linear,
+            MoveEase::BounceIn => EaseFunction::BounceIn,
+            MoveEase::BounceOut => EaseFunction::BounceOut,
+            MoveEase::CubicIn => EaseFunction::CubicIn,
+            MoveEase::CircularInOut => EaseFunction::CircularInOut,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MobSprite {
+    pub sheet: SpriteSheet,
+    pub index: usize,
+    /// sRGB tint.
+    pub color: (u8, u8, u8),
+}
+
+fn default_light_threshold() -> f32 {
+    1.0
+}
+
+/// One kind of mob.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MobDef {
+    /// Damage it takes to die on normal difficulty.
+    pub max_damage: i32,
+    /// Time between moves on normal difficulty.
+    pub move_delay_ms: u64,
+    pub light_sensitivity: LightSensitivity,
+    /// Seconds in the focused beam that kill a very light-sensitive mob.
+    #[serde(default = "default_light_threshold")]
+    pub light_threshold: f32,
+    /// Whether it notices the player by sound as well as by sight.
+    pub hears_player: bool,
+    #[serde(default)]
+    pub vision: Vision,
+    pub sprite: MobSprite,
+    pub ease: MoveEase,
+    /// What it does, in priority order; see [`Behaviour`].
+    pub behaviours: Vec<Behaviour>,
+    /// Whether killing it opens the way out.
+    #[serde(default)]
+    pub boss: bool,
+    /// Whether it's sent after players who shoot in zones set up for ambushes.
+    #[serde(default)]
+    pub ambusher: bool,
+    /// Soundtrack layer that plays while one is after the player.
+    #[serde(default)]
+    pub music: Option<MusicLayer>,
+    /// Whether the radio crackles when one is close.
+    #[serde(default)]
+    pub radio_static: bool,
+}
+
+impl MobDef {
+    pub fn move_delay(&self, difficulty: Difficulty) -> Duration {
+        difficulty.scale_mob_delay(Duration::from_millis(self.move_delay_ms))
+    }
+
+    pub fn max_damage(&self, difficulty: Difficulty) -> i32 {
+        difficulty.scale_mob_max_damage(self.max_damage)
+    }
+
+    pub fn ease_function(&self) -> EaseFunction {
+        self.ease.into()
+    }
+
+    /// Whether it charges, and so needs to keep track of its charge.
+    pub fn charges(&self) -> bool {
+        self.behaviours
+            .iter()
+            .any(|b| matches!(b, Behaviour::Charge { .. }))
+    }
+
+    /// Whether it patrols, and so needs a patrol route.
+    pub fn patrols(&self) -> bool {
+        self.behaviours.contains(&Behaviour::Patrol)
+    }
+
+    /// How loud a noise has to be to wake it, if it starts out asleep.
+    pub fn wake_loudness(&self) -> Option<i32> {
+        self.behaviours.iter().find_map(|b| match b {
+            Behaviour::Sleep { wake_loudness } => Some(*wake_loudness),
+            _ => None,
+        })
+    }
+}
+
+/// Every kind of mob there is, in name order.
+#[derive(Debug, Clone, PartialEq, Resource)]
+pub struct Bestiary {
+    names: Vec<MobName>,
+    defs: Vec<MobDef>,
+}
+
+impl Bestiary {
+    /// The kind of mob called `name`, if there is one.
+    pub fn kind(&self, name: &MobName) -> Option<MobKind> {
+        self.names.binary_search(name).ok().map(MobKind)
+    }
+
+    pub fn get(&self, name: &MobName) -> Option<&MobDef> {
+        self.kind(name).map(|kind| self.def(kind))
+    }
+
+    /// The definition of a kind of mob. Kinds only come from the bestiary, so
+    /// this can't miss.
+    pub fn def(&self, kind: MobKind) -> &MobDef {
+        &self.defs[kind.0]
+    }
+
+    pub fn name(&self, kind: MobKind) -> &MobName {
+        &self.names[kind.0]
+    }
+
+    /// Mob kinds, in name order.
+    pub fn kinds(&self) -> impl Iterator<Item = MobKind> + use<> {
+        (0..self.defs.len()).map(MobKind)
+    }
+}
+
+#[derive(Debug)]
+pub enum BestiaryError {
+    Ron(ron::error::SpannedError),
+    /// An entry with a stat that makes no sense, like a mob that can't be hurt
+    /// or a sprite past the end of its sheet.
+    BadEntry(MobName, &'static str),
+}
+
+impl std::fmt::Display for BestiaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BestiaryError::Ron(e) => write!(f, "{e}"),
+            BestiaryError::BadEntry(kind, why) => write!(f, "bestiary entry {kind}: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for BestiaryError {}
+
+/// Parses a bestiary, checking every entry's stats and sprite.
+pub fn parse_bestiary(ron: &str) -> Result<Bestiary, BestiaryError> {
+    let entries: BTreeMap<MobName, MobDef> = ron::from_str(ron).map_err(BestiaryError::Ron)?;
+    for (name, def) in &entries {
+        let bad = |why| Err(BestiaryError::BadEntry(name.clone(), why));
+        if def.max_damage < 1 {
+            return bad("max_damage must be at least 1");
+        }
+        if def.move_delay_ms == 0 {
+            return bad("move_delay_ms must be at least 1");
+        }
+        if def.vision.range < 0 || def.vision.cone_degrees <= 0.0 {
+            return bad("vision needs a cone and a range that isn't negative");
+        }
+        if def.sprite.index >= def.sprite.sheet.sprite_count() {
+            return bad("sprite index is past the end of its sheet");
+        }
+    }
+    let (names, defs) = entries.into_iter().unzip();
+    Ok(Bestiary { names, defs })
+}
+
+pub fn default_bestiary() -> Bestiary {
+    parse_bestiary(DEFAULT_BESTIARY).expect("default bestiary should parse")
+}
+
+/// Reads the bestiary from [`BESTIARY_PATH`], or the built-in one.
+pub fn load_bestiary() -> Bestiary {
+    load_ron_or_default(BESTIARY_PATH, "bestiary", parse_bestiary, default_bestiary)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::world::CommandQueue, render::view::RenderLayers};
+
+    use super::*;
+    use crate::{
+        Eyeball,
+        assets::GameAssets,
+        behaviour::{Asleep, ChargeState},
+        mob::{HearsPlayer, Mob},
+        spawn::{Spawn, spawn_entity},
+    };
+
+    #[test]
+    fn test_bestiary_entries_load_and_spawn() {
+        let bestiary = default_bestiary();
+        for name in [
+            MobName::ZOMBIE,
+            MobName::SLEEPING_ZOMBIE,
+            MobName::SCULPTURE,
+            MobName::HIDER,
+            MobName::GHOST,
+            MobName::KOOL_AID_MAN,
+            MobName::EYEBALL,
+        ] {
+            assert!(bestiary.kind(&name).is_some(), "{name} is missing");
+        }
+        assert!(bestiary.get(&MobName::KOOL_AID_MAN).unwrap().ambusher);
+
+        let assets = GameAssets::placeholder();
+        let mut world = World::new();
+        for kind in bestiary.kinds() {
+            let def = bestiary.def(kind);
+            let name = bestiary.name(kind);
+            assert_eq!(bestiary.kind(name), Some(kind));
+            let mut queue = CommandQueue::default();
+            let mut commands = Commands::new(&mut queue, &world);
+            let entity = spawn_entity(
+                &mut commands,
+                &assets,
+                &bestiary,
+                Difficulty::Normal,
+                IVec2::ZERO,
+                &Spawn::Mob(name.clone()),
+            )
+            .map(|e| e.id())
+            .unwrap_or_else(|| panic!("{name} didn't spawn"));
+            queue.apply(&mut world);
+            let entity = world.entity(entity);
+            assert_eq!(entity.get::<Mob>().map(|mob| mob.kind), Some(kind));
+            assert!(entity.contains::<Sprite>() && entity.contains::<RenderLayers>());
+            assert_eq!(entity.contains::<HearsPlayer>(), def.hears_player, "{name}");
+            assert_eq!(entity.contains::<ChargeState>(), def.charges(), "{name}");
+            assert_eq!(entity.contains::<Eyeball>(), def.boss, "{name}");
+            assert_eq!(
+                entity.contains::<Asleep>(),
+                def.wake_loudness().is_some(),
+                "{name}"
+            );
+        }
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let unknown = Spawn::Mob(MobName::new("Nobody"));
+        assert!(
+            spawn_entity(
+                &mut commands,
+                &assets,
+                &bestiary,
+                Difficulty::Normal,
+                IVec2::ZERO,
+                &unknown
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_bad_bestiary_is_an_error() {
+        let entry = |index| {
+            format!(
+                "{{\"Blob\": (max_damage: 1, move_delay_ms: 100, light_sensitivity: No, \
+                 hears_player: true, sprite: (sheet: OryxMonsters, index: {index}, \
+                 color: (0, 0, 0)), ease: Linear, behaviours: [Chase])}}"
+            )
+        };
+        let blob = parse_bestiary(&entry(0)).unwrap();
+        assert_eq!(
+            blob.get(&MobName::new("Blob")).unwrap().light_threshold,
+            1.0
+        );
+        assert!(matches!(
+            parse_bestiary(&entry(100_000)),
+            Err(BestiaryError::BadEntry(..))
+        ));
+        assert!(matches!(
+            parse_bestiary(r#"{"Blob": ()}"#),
+            Err(BestiaryError::Ron(..))
+        ));
+    }
+}
@@ -0,0 +1,60 @@
+//! Prints a generated map to the terminal and optionally saves it as a PNG.
+//!
+//! ```text
+//! cargo run --bin map_preview -- [--seed N] [--zones PATH] [--png OUT.png]
+//! ```
+use something_in_the_fog::{bestiary, map::ZONE_SPECS_PATH, mapgen, preview};
+
+const USAGE: &str = "usage: map_preview [--seed N] [--zones PATH] [--png OUT.png]";
+
+struct Args {
+    seed: u64,
+    zones: String,
+    png: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        seed: 0,
+        zones: ZONE_SPECS_PATH.to_owned(),
+        png: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let value = iter.next().ok_or_else(|| format!("{arg} needs a value"))?;
+        match arg.as_str() {
+            "--seed" => args.seed = value.parse().map_err(|e| format!("{arg}: {e}"))?,
+            "--zones" => args.zones = value,
+            "--png" => args.png = Some(value),
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+    Ok(args)
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}");
+        std::process::exit(2);
+    });
+    let specs = std::fs::read_to_string(&args.zones)
+        .map_err(|e| e.to_string())
+        .and_then(|ron| mapgen::parse_zone_specs(&ron).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| fail(format!("failed to load {}: {e}", args.zones)));
+    let result = mapgen::gen_map(args.seed, &specs)
+        .unwrap_or_else(|e| fail(format!("seed {}: {e}", args.seed)));
+
+    let bestiary = bestiary::default_bestiary();
+    print!("{}", preview::to_ansi(&result, &bestiary));
+    if let Some(path) = args.png {
+        let file = std::fs::File::create(&path)
+            .unwrap_or_else(|e| fail(format!("failed to create {path}: {e}")));
+        preview::write_png(&result, &bestiary, std::io::BufWriter::new(file))
+            .unwrap_or_else(|e| fail(format!("failed to write {path}: {e}")));
+    }
+}
@@ -0,0 +1,261 @@
+//! Generates many maps headlessly and prints per-zone statistics, so level
+//! balance can be tuned with numbers instead of by feel. A zone's floors above
+//! and below the ground count towards its statistics.
+//!
+//! ```text
+//! cargo run --release --bin mapgen_stats -- [--runs N] [--seed FIRST_SEED] [--zones PATH]
+//!     [--loot PATH] [--bestiary PATH] [--difficulty easy|normal|hard|nightmare]
+//! ```
+use std::collections::HashMap;
+
+use bevy::math::IVec2;
+use something_in_the_fog::{
+    bestiary::{self, BESTIARY_PATH},
+    difficulty::Difficulty,
+    loot::{self, LOOT_TABLES_PATH},
+    map::{ItemKind, ZONE_SPECS_PATH},
+    mapgen::{self, MapgenResult},
+    mob::MobName,
+    player::GunType,
+    spawn::Spawn,
+};
+
+const USAGE: &str = "usage: mapgen_stats [--runs N] [--seed FIRST_SEED] [--zones PATH] \
+    [--loot PATH] [--bestiary PATH] [--difficulty easy|normal|hard|nightmare]";
+
+/// Mean, minimum and maximum of a series of samples.
+#[derive(Default)]
+struct Summary {
+    sum: f64,
+    min: f64,
+    max: f64,
+    count: usize,
+}
+
+impl Summary {
+    fn add(&mut self, sample: f64) {
+        if self.count == 0 {
+            self.min = sample;
+            self.max = sample;
+        }
+        self.sum += sample;
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.count += 1;
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.count == 0 {
+            return write!(f, "-");
+        }
+        write!(
+            f,
+            "{:.1} ({:.1}-{:.1})",
+            self.sum / self.count as f64,
+            self.min,
+            self.max
+        )
+    }
+}
+
+#[derive(Default)]
+struct ZoneStats {
+    rooms: Summary,
+    walkable_percent: Summary,
+    /// Mobs per 100 walkable tiles, summed over all runs.
+    mob_density: HashMap<MobName, f64>,
+    /// Rounds lying around or loaded in guns, summed over all runs.
+    ammo: HashMap<GunType, f64>,
+}
+
+struct Args {
+    runs: u64,
+    seed: u64,
+    zones: String,
+    loot: String,
+    bestiary: String,
+    difficulty: Difficulty,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        runs: 100,
+        seed: 0,
+        zones: ZONE_SPECS_PATH.to_owned(),
+        loot: LOOT_TABLES_PATH.to_owned(),
+        bestiary: BESTIARY_PATH.to_owned(),
+        difficulty: Difficulty::Normal,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let value = iter.next().ok_or_else(|| format!("{arg} needs a value"))?;
+        let bad_number = |e| format!("{arg}: {e}");
+        match arg.as_str() {
+            "--runs" => args.runs = value.parse().map_err(bad_number)?,
+            "--seed" => args.seed = value.parse().map_err(bad_number)?,
+            "--zones" => args.zones = value,
+            "--loot" => args.loot = value,
+            "--bestiary" => args.bestiary = value,
+            "--difficulty" => {
+                args.difficulty = Difficulty::from_name(&value)
+                    .ok_or_else(|| format!("{arg}: unknown difficulty {value:?}"))?
+            }
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+    Ok(args)
+}
+
+fn blocks_movement(spawns: &[Spawn]) -> bool {
+    spawns
+        .iter()
+        .any(|spawn| matches!(spawn, Spawn::Tile(t) if t.blocks_movement()))
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}");
+        std::process::exit(2);
+    });
+    let specs = std::fs::read_to_string(&args.zones)
+        .map_err(|e| e.to_string())
+        .and_then(|ron| mapgen::parse_zone_specs(&ron).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("failed to load {}: {e}", args.zones);
+            std::process::exit(1);
+        });
+    let loot = std::fs::read_to_string(&args.loot)
+        .map_err(|e| e.to_string())
+        .and_then(|ron| loot::parse_loot_tables(&ron).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("failed to load {}: {e}", args.loot);
+            std::process::exit(1);
+        });
+    let bestiary = std::fs::read_to_string(&args.bestiary)
+        .map_err(|e| e.to_string())
+        .and_then(|ron| bestiary::parse_bestiary(&ron).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("failed to load {}: {e}", args.bestiary);
+            std::process::exit(1);
+        });
+
+    let mut zone_stats: Vec<ZoneStats> = specs.iter().map(|_| ZoneStats::default()).collect();
+    let mut boss_path = Summary::default();
+    let mut boss_unreachable = 0;
+    let mut failed = 0;
+    for seed in args.seed..args.seed + args.runs {
+        let generated =
+            mapgen::gen_map_with_room_counts(seed, &specs, args.difficulty, &loot, &bestiary);
+        let (result, room_counts) = match generated {
+            Ok(generated) => generated,
+            Err(e) => {
+                eprintln!("seed {seed}: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        let MapgenResult {
+            spawns,
+            zones,
+            floors,
+        } = &result;
+
+        let distances = mapgen::walk_distances(&result);
+        let floor_distances = mapgen::floor_walk_distances(&result, &distances);
+        let all_floors = std::iter::once((0, spawns, &distances)).chain(
+            floors
+                .iter()
+                .map(|(floor, spawns)| (*floor, spawns, &floor_distances[floor])),
+        );
+        let boss_distance = all_floors
+            .flat_map(|(_, spawns, distances)| {
+                spawns
+                    .iter()
+                    .filter(|(_, s)| {
+                        s.iter().any(|spawn| {
+                            matches!(spawn, Spawn::Mob(name) if bestiary.get(name).is_some_and(|def| def.boss))
+                        })
+                    })
+                    .filter_map(|(pos, _)| distances.get(pos))
+            })
+            .min();
+        match boss_distance {
+            Some(dist) => boss_path.add(*dist as f64),
+            None => boss_unreachable += 1,
+        }
+
+        for ((zone, stats), rooms) in zones.iter().zip(&mut zone_stats).zip(room_counts) {
+            if let Some(rooms) = rooms {
+                stats.rooms.add(rooms as f64);
+            }
+            // the zone's own floors count as part of it
+            let zone_floors = std::iter::once(spawns).chain(
+                zone.spec
+                    .floors
+                    .iter()
+                    .filter_map(|floor| floors.get(&floor.floor)),
+            );
+            let rect = zone.rect;
+            let mut walkable = 0;
+            let mut area = 0;
+            let mut mobs: HashMap<MobName, usize> = HashMap::new();
+            for spawns in zone_floors {
+                let positions = (rect.min.y..=rect.max.y)
+                    .flat_map(|y| (rect.min.x..=rect.max.x).map(move |x| IVec2::new(x, y)));
+                for pos in positions {
+                    area += 1;
+                    let here = spawns.get(&pos).map_or(&[][..], Vec::as_slice);
+                    if !blocks_movement(here) {
+                        walkable += 1;
+                    }
+                    for spawn in here {
+                        match spawn {
+                            Spawn::Mob(name) => *mobs.entry(name.clone()).or_default() += 1,
+                            Spawn::Item(
+                                ItemKind::Ammo(gun, rounds) | ItemKind::Gun(gun, rounds),
+                            ) => {
+                                *stats.ammo.entry(*gun).or_default() += *rounds as f64;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            stats
+                .walkable_percent
+                .add(100.0 * walkable as f64 / area as f64);
+            for (kind, count) in mobs {
+                *stats.mob_density.entry(kind).or_default() +=
+                    100.0 * count as f64 / walkable.max(1) as f64;
+            }
+        }
+    }
+
+    let runs = (args.runs - failed) as f64;
+    println!(
+        "{} runs of {} from seed {} ({failed} failed)",
+        args.runs, args.zones, args.seed
+    );
+    println!("start -> boss: {boss_path} tiles ({boss_unreachable} unreachable)");
+    for (spec, stats) in specs.iter().zip(zone_stats) {
+        println!();
+        println!(
+            "{} ({:?} {}x{})",
+            spec.name, spec.generator, spec.width, spec.height
+        );
+        println!("  rooms: {}", stats.rooms);
+        println!("  walkable %: {}", stats.walkable_percent);
+        let mut mobs = stats.mob_density.into_iter().collect::<Vec<_>>();
+        mobs.sort_by_key(|(kind, _)| format!("{kind:?}"));
+        for (kind, density) in mobs {
+            println!("  {kind:?} per 100 walkable tiles: {:.2}", density / runs);
+        }
+        let mut ammo = stats.ammo.into_iter().collect::<Vec<_>>();
+        ammo.sort_by_key(|(gun, _)| gun.to_string());
+        for (gun, rounds) in ammo {
+            println!("  {gun} rounds: {:.1}", rounds / runs);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Difficulty of a run, picked on the start screen or with `--difficulty
+//! <name>` at launch. It scales how many mobs are generated, how fast and
+//! tough they are, how much the player can take, how fast the flashlight
+//! drains and how much ammo is found.
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{map::ItemKind, player::PLAYER_MAX_DAMAGE};
+
+/// Whether the run has started. The map and the player are only made once a
+/// difficulty is picked, and the game's systems only run while playing.
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RunState {
+    #[default]
+    PickingDifficulty,
+    Playing,
+}
+
+/// Starts the run straight away, for a difficulty given at launch.
+pub(crate) fn skip_start_screen(mut next_state: ResMut<NextState<RunState>>) {
+    next_state.set(RunState::Playing);
+}
+
+/// Mobs with at least this much health can't be killed, whatever the difficulty.
+pub const INVULNERABLE_DAMAGE: i32 = 99;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize, Resource)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Nightmare,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+        Difficulty::Nightmare,
+    ];
+
+    /// Looks a difficulty up by its lowercase name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+            Difficulty::Nightmare => "nightmare",
+        }
+    }
+
+    /// Takes the difficulty from a `--difficulty <name>` launch argument, if
+    /// there is one.
+    pub(crate) fn from_args() -> Option<Self> {
+        let name = crate::map::launch_arg("--difficulty")?;
+        let difficulty = Self::from_name(&name);
+        if difficulty.is_none() {
+            warn!("unknown difficulty {name:?}, pick one on the start screen");
+        }
+        difficulty
+    }
+
+    /// Multiplies the number of mobs a zone spawns.
+    pub fn mob_count_scale(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.6,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.3,
+            Difficulty::Nightmare => 1.6,
+        }
+    }
+
+    /// How many of a mob a zone spawns when it asks for `count`. A lone mob,
+    /// like the sculpture, stays alone.
+    pub fn scale_mob_count(self, count: usize) -> usize {
+        if count <= 1 {
+            return count;
+        }
+        ((count as f64 * self.mob_count_scale()).round() as usize).max(1)
+    }
+
+    /// Multiplies the time mobs wait between moves.
+    pub fn mob_delay_scale(self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.25,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 0.85,
+            Difficulty::Nightmare => 0.7,
+        }
+    }
+
+    /// The time a mob waits between moves when it waits `delay` on normal.
+    pub fn scale_mob_delay(self, delay: Duration) -> Duration {
+        delay.mul_f32(self.mob_delay_scale())
+    }
+
+    /// How much damage a mob that takes `max_damage` on normal takes to die.
+    pub fn scale_mob_max_damage(self, max_damage: i32) -> i32 {
+        if max_damage >= INVULNERABLE_DAMAGE {
+            return max_damage;
+        }
+        let scale = match self {
+            Difficulty::Easy => 0.7,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.35,
+            Difficulty::Nightmare => 1.7,
+        };
+        ((max_damage as f32 * scale).round() as i32).max(1)
+    }
+
+    /// How much damage the player takes to die.
+    pub fn player_max_damage(self) -> i32 {
+        match self {
+            Difficulty::Easy => PLAYER_MAX_DAMAGE + 4,
+            Difficulty::Normal => PLAYER_MAX_DAMAGE,
+            Difficulty::Hard => PLAYER_MAX_DAMAGE - 2,
+            Difficulty::Nightmare => PLAYER_MAX_DAMAGE - 4,
+        }
+    }
+
+    /// Multiplies how fast the focused flashlight drains its battery.
+    pub fn flashlight_drain_scale(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.25,
+            Difficulty::Nightmare => 1.5,
+        }
+    }
+
+    /// Multiplies the rounds of ammo rolled from loot tables.
+    pub fn ammo_scale(self) -> f64 {
+        match self {
+            Difficulty::Easy => 1.5,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 0.75,
+            Difficulty::Nightmare => 0.5,
+        }
+    }
+
+    /// `item` with its ammo scaled, if it's a box of ammo. Guns keep what's
+    /// loaded in them.
+    pub fn scale_loot(self, item: ItemKind) -> ItemKind {
+        match item {
+            ItemKind::Ammo(gun_type, n) => {
+                let n = ((n as f64 * self.ammo_scale()).round() as usize).max(1);
+                ItemKind::Ammo(gun_type, n)
+            }
+            ItemKind::Gun(..) => item,
+        }
+    }
+}
+
+impl std::fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
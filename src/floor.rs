@@ -0,0 +1,226 @@
+//! Floors above and below the ground, connected by stairs. Only the floor the
+//! player is on is simulated. The entities of floors the player has left are
+//! kept whole but taken off the map (see [`OffFloor`]), and floors the player
+//! hasn't been to yet are kept as spawns.
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::prelude::*;
+
+use crate::{
+    bestiary::Bestiary,
+    difficulty::RunState,
+    map::{Map, MapPos, Tile, TileKind},
+    player::{Player, PlayerMoveEvent},
+    spawn::{Spawn, SpawnEvent},
+};
+
+/// The floor the player is on. 0 is the ground.
+#[derive(Resource, Default)]
+pub struct CurrentFloor(pub i32);
+
+/// An entity on a floor the player isn't on. Its [`MapPos`] is taken away
+/// while it's there, which leaves it out of the map and out of every system
+/// that simulates things on it, and is given back when the player returns.
+/// Everything else about it, like a mob's memory of the player, is kept.
+#[derive(Component)]
+pub struct OffFloor {
+    pub floor: i32,
+    pub pos: IVec2,
+}
+
+/// Tiles, items, and mobs of the floors the player hasn't been to yet.
+#[derive(Resource, Default)]
+pub struct StashedFloors(pub BTreeMap<i32, HashMap<IVec2, Vec<Spawn>>>);
+
+impl StashedFloors {
+    pub fn new(floors: BTreeMap<i32, HashMap<IVec2, Vec<Spawn>>>) -> Self {
+        Self(floors)
+    }
+
+    /// Whether a boss is waiting to be spawned on one of the floors.
+    pub fn has_boss(&self, bestiary: &Bestiary) -> bool {
+        self.0
+            .values()
+            .flat_map(|floor| floor.values())
+            .flatten()
+            .any(
+                |s| matches!(s, Spawn::Mob(name) if bestiary.get(name).is_some_and(|def| def.boss)),
+            )
+    }
+
+    /// Spawns `spawns` right away if they're on the current floor, or keeps
+    /// them for when the player gets to their floor.
+    pub fn add_spawns(
+        &mut self,
+        floor: i32,
+        current: &CurrentFloor,
+        spawns: HashMap<IVec2, Vec<Spawn>>,
+        ev_spawn: &mut EventWriter<SpawnEvent>,
+    ) {
+        if floor == current.0 {
+            for (pos, spawn_list) in spawns {
+                for spawn in spawn_list {
+                    ev_spawn.send(SpawnEvent(pos, spawn));
+                }
+            }
+        } else {
+            let stashed = self.0.entry(floor).or_default();
+            for (pos, spawn_list) in spawns {
+                stashed.entry(pos).or_default().extend(spawn_list);
+            }
+        }
+    }
+
+    /// Forgets the spawns at or left of column `x`.
+    pub fn drop_up_to(&mut self, x: i32) {
+        for floor in self.0.values_mut() {
+            floor.retain(|pos, _| pos.x > x);
+        }
+    }
+}
+
+/// Swaps floors when the player steps onto stairs: everything on the floor
+/// they leave is taken off the map, and the floor they arrive on is put back
+/// on it, or spawned if it's their first time there.
+#[allow(clippy::complexity)]
+fn take_stairs(
+    mut commands: Commands,
+    map: Res<Map>,
+    mut current: ResMut<CurrentFloor>,
+    mut stash: ResMut<StashedFloors>,
+    mut ev_player_move: EventReader<PlayerMoveEvent>,
+    mut ev_spawn: EventWriter<SpawnEvent>,
+    q_tiles: Query<&Tile>,
+    q_on_floor: Query<(Entity, &MapPos), Without<Player>>,
+    q_off_floor: Query<(Entity, &OffFloor)>,
+) {
+    let Some(PlayerMoveEvent { dest, .. }) = ev_player_move.read().last() else {
+        return;
+    };
+    let Some(to) = q_tiles
+        .iter_many(map.get(dest.0))
+        .find_map(|Tile(kind)| match kind {
+            TileKind::StairsDown => Some(current.0 - 1),
+            TileKind::StairsUp => Some(current.0 + 1),
+            _ => None,
+        })
+    else {
+        return;
+    };
+
+    for (entity, MapPos(pos)) in q_on_floor.iter() {
+        commands.entity(entity).remove::<MapPos>().insert((
+            OffFloor {
+                floor: current.0,
+                pos: *pos,
+            },
+            Visibility::Hidden,
+        ));
+    }
+    for (entity, off_floor) in q_off_floor.iter() {
+        if off_floor.floor == to {
+            commands
+                .entity(entity)
+                .remove::<OffFloor>()
+                .insert(MapPos(off_floor.pos));
+        }
+    }
+    current.0 = to;
+
+    for (pos, spawn_list) in stash.0.remove(&to).unwrap_or_default() {
+        for spawn in spawn_list {
+            ev_spawn.send(SpawnEvent(pos, spawn));
+        }
+    }
+}
+
+pub struct FloorPlugin;
+
+impl Plugin for FloorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CurrentFloor>();
+        app.init_resource::<StashedFloors>();
+        app.add_systems(
+            Update,
+            take_stairs
+                .before(crate::spawn::spawn)
+                .run_if(in_state(RunState::Playing)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bestiary::default_bestiary,
+        map::{SightBlockedMap, WalkBlockedMap, update_spatial_index},
+        mob::MobName,
+        vision::Facing,
+    };
+
+    #[test]
+    fn test_floors_keep_their_entities() {
+        let mut app = App::new();
+        app.init_resource::<Map>();
+        app.init_resource::<SightBlockedMap>();
+        app.init_resource::<WalkBlockedMap>();
+        app.init_resource::<CurrentFloor>();
+        app.insert_resource(StashedFloors::new(BTreeMap::from([(
+            -1,
+            HashMap::from([(IVec2::new(5, 0), vec![Spawn::Mob(MobName::ZOMBIE)])]),
+        )])));
+        app.add_event::<PlayerMoveEvent>();
+        app.add_event::<SpawnEvent>();
+        app.add_systems(Update, (update_spatial_index, take_stairs).chain());
+        let take_stairs = |app: &mut App| {
+            app.world_mut().send_event(PlayerMoveEvent {
+                source: MapPos(IVec2::new(1, 0)),
+                dest: MapPos(IVec2::ZERO),
+            });
+            app.update();
+        };
+
+        let world = app.world_mut();
+        world.spawn((MapPos(IVec2::ZERO), Tile(TileKind::StairsDown)));
+        let mob = world
+            .spawn((MapPos(IVec2::new(3, 0)), Facing(IVec2::X)))
+            .id();
+        take_stairs(&mut app);
+        let world = app.world_mut();
+        assert_eq!(world.resource::<CurrentFloor>().0, -1);
+        assert!(world.get::<MapPos>(mob).is_none());
+        assert_eq!(world.get::<OffFloor>(mob).unwrap().floor, 0);
+        let spawned = world
+            .resource_mut::<Events<SpawnEvent>>()
+            .drain()
+            .map(|SpawnEvent(pos, spawn)| (pos, spawn))
+            .collect::<Vec<_>>();
+        assert_eq!(spawned, [(IVec2::new(5, 0), Spawn::Mob(MobName::ZOMBIE))]);
+
+        // the floor below has its own stairs back up
+        world.spawn((MapPos(IVec2::ZERO), Tile(TileKind::StairsUp)));
+        take_stairs(&mut app);
+        app.update();
+        let world = app.world();
+        assert_eq!(world.resource::<CurrentFloor>().0, 0);
+        assert_eq!(world.get::<MapPos>(mob).unwrap().0, IVec2::new(3, 0));
+        assert_eq!(world.get::<Facing>(mob), Some(&Facing(IVec2::X)));
+        assert_eq!(world.resource::<Map>().get(IVec2::new(3, 0)).count(), 1);
+        assert!(world.resource::<StashedFloors>().0.is_empty());
+    }
+
+    #[test]
+    fn test_stashed_boss() {
+        let bestiary = default_bestiary();
+        let floors = |mob| {
+            StashedFloors::new(BTreeMap::from([(
+                -1,
+                HashMap::from([(IVec2::ZERO, vec![Spawn::Mob(mob)])]),
+            )]))
+        };
+        assert!(floors(MobName::EYEBALL).has_boss(&bestiary));
+        assert!(!floors(MobName::ZOMBIE).has_boss(&bestiary));
+        assert!(!floors(MobName::new("Nobody")).has_boss(&bestiary));
+    }
+}
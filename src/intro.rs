@@ -1,66 +1,69 @@
 use rand::prelude::*;
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use bevy::prelude::*;
 
 use crate::{
     animation::TextEvent,
-    map::{self, MapPos},
-    player::Player,
+    map::{MapPos, Zones},
+    player::PlayerMoveEvent,
 };
 
 pub struct IntroPlugin;
 
-const TEXT_DISTANCE: f32 = 250.0;
-const INTRO_TEXT: [&str; 9] = [
-    "I left this town\nlong ago",
-    "I was cold\nand hungry,\nfilled with regret",
-    "There is nothing\nleft here for you",
-    "Go back to\nyour car",
-    "start the engine",
-    "and drive as far\nfrom here as you can",
-    "There is something\nin these woods",
-    "It knows you\nare here",
-    "Something in the fog",
-];
+/// Player moves between one line of the intro and the next.
+const TEXT_STEPS: usize = 8;
 
-#[derive(Component)]
-pub struct IntroText(f32);
+/// Progress through the intro lines of each zone.
+#[derive(Component, Default)]
+pub struct IntroText {
+    /// Lines shown so far, by zone name.
+    shown: HashMap<String, usize>,
+    /// Player moves since the last line.
+    steps: usize,
+}
 
+/// Shows the next intro line of the zone the player is in every few moves.
 fn intro_system_update(
     mut ev_text: EventWriter<TextEvent>,
-    player_query: Query<(&Transform, &map::MapPos), With<Player>>,
+    mut ev_player_move: EventReader<PlayerMoveEvent>,
+    zones: Res<Zones>,
     mut intro_text: Query<&mut IntroText>,
 ) {
     let mut rng = rand::thread_rng();
 
-    let Ok((player, player_pos)) = player_query.get_single() else {
-        return;
-    };
     let Ok(mut intro_text) = intro_text.get_single_mut() else {
         return;
     };
 
-    let intro_text_x = (intro_text.0 / TEXT_DISTANCE) as usize;
-    let player_x = (player.translation.x / TEXT_DISTANCE) as usize;
-
-    if intro_text_x < player_x && player_x <= INTRO_TEXT.len() {
+    for PlayerMoveEvent { dest, .. } in ev_player_move.read() {
+        intro_text.steps += 1;
+        let Some(zone) = zones.get(dest.0) else {
+            continue;
+        };
+        let shown = intro_text.shown.get(&zone.spec.name).copied().unwrap_or(0);
+        let Some(line) = zone.spec.intro.get(shown) else {
+            continue;
+        };
+        if intro_text.steps < TEXT_STEPS {
+            continue;
+        }
         ev_text.send(TextEvent {
-            text: INTRO_TEXT[player_x - 1].into(),
-            position: MapPos(player_pos.0 + IVec2::new(rng.gen_range(-5..5), rng.gen_range(-5..5)))
+            text: line.clone(),
+            position: MapPos(dest.0 + IVec2::new(rng.gen_range(-5..5), rng.gen_range(-5..5)))
                 .to_vec2(),
             duration: Duration::from_secs(10),
             teletype: Duration::from_secs(3),
             font_size: 15.0,
             ..default()
         });
-
-        intro_text.0 = player.translation.x;
+        intro_text.shown.insert(zone.spec.name.clone(), shown + 1);
+        intro_text.steps = 0;
     }
 }
 
 fn setup(mut commands: Commands) {
-    commands.spawn(IntroText(0.0));
+    commands.spawn(IntroText::default());
 }
 
 impl Plugin for IntroPlugin {
@@ -0,0 +1,420 @@
+//! Plain-text level format, for saving generated maps and hand-authoring
+//! test arenas.
+//!
+//! A level file is a header followed by a character grid:
+//!
+//! ```text
+//! // comments start with two slashes
+//! origin: -2 2
+//! zone: (rect: (-2, -2, 2, 2), spec: (name: "arena", generator: Field, width: 5, height: 5, fog: 100.0))
+//! legend: '1' = [Item(Ammo(Pistol, 15))]
+//! map:
+//! #####
+//! #z.1#
+//! #.@.#
+//! #..g#
+//! #####
+//! ```
+//!
+//! `origin` is the world position of the top-left character; rows go south
+//! from there. If it's left out, the map is placed so that `@` lands on the
+//! player start. `@` and `.` are otherwise empty. Single tiles and mobs have
+//! built-in characters (see [`DEFAULT_LEGEND`]); anything else, such as items
+//! or a mob standing in a doorway, needs a `legend` line listing its spawns.
+//! A `zone` line can also list the zone's patrol loops, as
+//! `patrols: [[(x, y), ...], ...]`; patrolling mobs walk the nearest.
+//!
+//! The map is the ground floor. Other floors follow it, each as a `floor: <n>`
+//! line and a grid placed at the same origin, e.g. `floor: -1` for a basement.
+use std::collections::HashMap;
+
+use bevy::math::{IRect, IVec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::TileKind,
+    mapgen::{MapgenResult, Zone},
+    mob::MobName,
+    spawn::Spawn,
+};
+
+const EMPTY: char = '.';
+const PLAYER_START: char = '@';
+
+/// Characters that need no legend entry.
+pub const DEFAULT_LEGEND: [(char, Spawn); 16] = [
+    ('#', Spawn::Tile(TileKind::Wall)),
+    ('+', Spawn::Tile(TileKind::Door)),
+    ('"', Spawn::Tile(TileKind::Bush)),
+    ('T', Spawn::Tile(TileKind::Tree)),
+    ('=', Spawn::Tile(TileKind::Crate)),
+    ('C', Spawn::Tile(TileKind::ShippingContainer)),
+    ('/', Spawn::Tile(TileKind::Lever)),
+    ('\\', Spawn::Tile(TileKind::LeverPulled)),
+    ('>', Spawn::Tile(TileKind::StairsDown)),
+    ('<', Spawn::Tile(TileKind::StairsUp)),
+    ('z', Spawn::Mob(MobName::ZOMBIE)),
+    ('s', Spawn::Mob(MobName::SCULPTURE)),
+    ('h', Spawn::Mob(MobName::HIDER)),
+    ('g', Spawn::Mob(MobName::GHOST)),
+    ('k', Spawn::Mob(MobName::KOOL_AID_MAN)),
+    ('E', Spawn::Mob(MobName::EYEBALL)),
+];
+
+/// Characters handed out to spawn combinations without a built-in character.
+const EXTRA_CHARS: &str = "0123456789abcdefijlmnopqrtuvwxyABDFGHIJKLMNOPQRSUVWXYZ!$%&*-:;?^_|~";
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// A header line that isn't `origin:`, `zone:` or `legend:`.
+    UnknownHeader(usize),
+    /// A header value that failed to parse.
+    BadValue(usize, String),
+    /// No `map:` line was found.
+    MissingMap,
+    /// A grid character with no legend entry.
+    UnknownChar(usize, char),
+    /// Neither an `origin` line nor an `@` in the grid.
+    MissingOrigin,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnknownHeader(line) => write!(f, "line {line}: unknown header"),
+            ParseError::BadValue(line, e) => write!(f, "line {line}: {e}"),
+            ParseError::MissingMap => write!(f, "no `map:` line"),
+            ParseError::UnknownChar(line, c) => {
+                write!(f, "line {line}: {c:?} is not in the legend")
+            }
+            ParseError::MissingOrigin => write!(f, "no `origin:` line and no `@` in the map"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, PartialEq)]
+pub enum ExportError {
+    /// More distinct spawn combinations than there are legend characters.
+    LegendFull,
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::LegendFull => write!(
+                f,
+                "more than {} spawn combinations need a legend entry",
+                EXTRA_CHARS.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+#[derive(Serialize, Deserialize)]
+struct ZoneLine {
+    rect: (i32, i32, i32, i32),
+    spec: crate::mapgen::ZoneSpec,
+    /// Patrol loops, as lists of waypoints.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    patrols: Vec<Vec<(i32, i32)>>,
+}
+
+/// Draws one floor as grid rows, adding characters to `legend` as needed.
+fn to_rows(
+    spawns: &HashMap<IVec2, Vec<Spawn>>,
+    bounds: IRect,
+    ground: bool,
+    legend: &mut Vec<(char, Vec<Spawn>)>,
+    extra_chars: &mut impl Iterator<Item = char>,
+) -> Result<Vec<String>, ExportError> {
+    let mut rows = Vec::new();
+    for y in (bounds.min.y..=bounds.max.y).rev() {
+        let mut row = String::new();
+        for x in bounds.min.x..=bounds.max.x {
+            let pos = IVec2::new(x, y);
+            let c = match spawns.get(&pos).filter(|s| !s.is_empty()) {
+                None if ground && pos == IVec2::ZERO => PLAYER_START,
+                None => EMPTY,
+                Some(spawns) => match legend.iter().find(|(_, s)| s == spawns) {
+                    Some((c, _)) => *c,
+                    None => {
+                        let c = extra_chars.next().ok_or(ExportError::LegendFull)?;
+                        legend.push((c, spawns.clone()));
+                        c
+                    }
+                },
+            };
+            row.push(c);
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Writes `result` out as a level file.
+pub fn to_ascii(result: &MapgenResult) -> Result<String, ExportError> {
+    let bounds = result.bounds();
+
+    let mut legend: Vec<(char, Vec<Spawn>)> = DEFAULT_LEGEND
+        .iter()
+        .map(|(c, spawn)| (*c, vec![spawn.clone()]))
+        .collect();
+    let num_default = legend.len();
+    let mut extra_chars = EXTRA_CHARS.chars();
+    let rows = to_rows(&result.spawns, bounds, true, &mut legend, &mut extra_chars)?;
+    let floors = result
+        .floors
+        .iter()
+        .map(|(floor, spawns)| {
+            let rows = to_rows(spawns, bounds, false, &mut legend, &mut extra_chars)?;
+            Ok((*floor, rows))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut out = String::new();
+    out.push_str(&format!("origin: {} {}\n", bounds.min.x, bounds.max.y));
+    for Zone {
+        rect,
+        spec,
+        patrols,
+    } in &result.zones
+    {
+        let line = ZoneLine {
+            rect: (rect.min.x, rect.min.y, rect.max.x, rect.max.y),
+            spec: spec.clone(),
+            patrols: patrols
+                .iter()
+                .map(|route| route.iter().map(|p| (p.x, p.y)).collect())
+                .collect(),
+        };
+        out.push_str(&format!("zone: {}\n", ron::to_string(&line).unwrap()));
+    }
+    for (c, spawns) in &legend[num_default..] {
+        out.push_str(&format!(
+            "legend: {} = {}\n",
+            ron::to_string(c).unwrap(),
+            ron::to_string(spawns).unwrap()
+        ));
+    }
+    out.push_str("map:\n");
+    for row in rows {
+        out.push_str(&row);
+        out.push('\n');
+    }
+    for (floor, rows) in floors {
+        out.push_str(&format!("floor: {floor}\n"));
+        for row in rows {
+            out.push_str(&row);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+/// Reads a level file written by [`to_ascii`] or by hand.
+pub fn from_ascii(s: &str) -> Result<MapgenResult, ParseError> {
+    let mut legend: HashMap<char, Vec<Spawn>> = DEFAULT_LEGEND
+        .iter()
+        .map(|(c, spawn)| (*c, vec![spawn.clone()]))
+        .collect();
+    let mut origin = None;
+    let mut zones = Vec::new();
+    let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let mut found_map = false;
+    for (n, line) in lines.by_ref() {
+        let line = line.trim();
+        let bad_value = |e: &dyn std::fmt::Display| ParseError::BadValue(n, e.to_string());
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        } else if line == "map:" {
+            found_map = true;
+            break;
+        } else if let Some(value) = line.strip_prefix("origin:") {
+            let coords = value
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<i32>, _>>()
+                .map_err(|e| bad_value(&e))?;
+            let [x, y] = coords[..] else {
+                return Err(bad_value(&"expected `origin: <x> <y>`"));
+            };
+            origin = Some(IVec2::new(x, y));
+        } else if let Some(value) = line.strip_prefix("zone:") {
+            let ZoneLine {
+                rect,
+                spec,
+                patrols,
+            } = ron::from_str(value).map_err(|e| bad_value(&e))?;
+            let (x1, y1, x2, y2) = rect;
+            zones.push(Zone {
+                rect: IRect::new(x1, y1, x2, y2),
+                spec,
+                patrols: patrols
+                    .into_iter()
+                    .map(|route| route.into_iter().map(|(x, y)| IVec2::new(x, y)).collect())
+                    .collect(),
+            });
+        } else if let Some(value) = line.strip_prefix("legend:") {
+            let (c, spawns) = value
+                .split_once('=')
+                .ok_or_else(|| bad_value(&"expected `legend: '<c>' = [<spawns>]`"))?;
+            let c: char = ron::from_str(c).map_err(|e| bad_value(&e))?;
+            let spawns: Vec<Spawn> = ron::from_str(spawns).map_err(|e| bad_value(&e))?;
+            legend.insert(c, spawns);
+        } else {
+            return Err(ParseError::UnknownHeader(n));
+        }
+    }
+    if !found_map {
+        return Err(ParseError::MissingMap);
+    }
+
+    // The ground floor grid, then any `floor:` lines and their grids.
+    let mut grid = Vec::new();
+    let mut floor_grids: Vec<(i32, Vec<(usize, &str)>)> = Vec::new();
+    for (n, line) in lines {
+        let line = line.trim_end();
+        if let Some(floor) = line.strip_prefix("floor:") {
+            let floor = floor
+                .trim()
+                .parse()
+                .map_err(|e| ParseError::BadValue(n, format!("{e}")))?;
+            floor_grids.push((floor, Vec::new()));
+        } else if let Some((_, floor_grid)) = floor_grids.last_mut() {
+            floor_grid.push((n, line));
+        } else {
+            grid.push((n, line));
+        }
+    }
+    let start = grid.iter().enumerate().find_map(|(row, (_, line))| {
+        line.chars()
+            .position(|c| c == PLAYER_START)
+            .map(|col| IVec2::new(col as i32, row as i32))
+    });
+    let origin = match (origin, start) {
+        (Some(origin), _) => origin,
+        // place the map so that the start is at (0, 0)
+        (None, Some(start)) => IVec2::new(-start.x, start.y),
+        (None, None) => return Err(ParseError::MissingOrigin),
+    };
+
+    let read_grid = |grid: &[(usize, &str)]| {
+        let mut spawns = HashMap::new();
+        for (row, (n, line)) in grid.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                if c == EMPTY || c == PLAYER_START {
+                    continue;
+                }
+                let pos = origin + IVec2::new(col as i32, -(row as i32));
+                let spawn_list = legend.get(&c).ok_or(ParseError::UnknownChar(*n, c))?;
+                spawns.insert(pos, spawn_list.clone());
+            }
+        }
+        Ok(spawns)
+    };
+    let spawns = read_grid(&grid)?;
+    let floors = floor_grids
+        .iter()
+        .map(|(floor, grid)| Ok((*floor, read_grid(grid)?)))
+        .collect::<Result<_, ParseError>>()?;
+    Ok(MapgenResult {
+        spawns,
+        zones,
+        floors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        map::ItemKind,
+        mapgen::{default_zone_specs, gen_map},
+        player::GunType,
+    };
+
+    #[test]
+    fn test_roundtrip_generated_map() {
+        let specs = default_zone_specs();
+        for seed in [0, 1234] {
+            let result = gen_map(seed, &specs).unwrap();
+            let ascii = to_ascii(&result).unwrap();
+            assert_eq!(from_ascii(&ascii).unwrap(), result);
+        }
+    }
+
+    #[test]
+    fn test_too_many_combinations() {
+        let mut result = MapgenResult::default();
+        for n in 0..=EXTRA_CHARS.len() {
+            result.spawns.insert(
+                IVec2::new(n as i32, 0),
+                vec![Spawn::Item(ItemKind::Ammo(GunType::Pistol, n))],
+            );
+        }
+        assert_eq!(to_ascii(&result), Err(ExportError::LegendFull));
+        result.spawns.remove(&IVec2::ZERO);
+        assert!(to_ascii(&result).is_ok());
+    }
+
+    #[test]
+    fn test_hand_authored_arena() {
+        let arena = "\
+// a sculpture behind a door
+legend: '1' = [Tile(Door), Mob(\"Sculpture\")]
+map:
+#####
+#z@1#
+#####";
+        let MapgenResult { spawns, zones, .. } = from_ascii(arena).unwrap();
+        assert!(zones.is_empty());
+        assert_eq!(spawns.len(), 14);
+        assert_eq!(
+            spawns[&IVec2::new(-1, 0)],
+            vec![Spawn::Mob(MobName::ZOMBIE)]
+        );
+        assert_eq!(
+            spawns[&IVec2::new(1, 0)],
+            vec![Spawn::Tile(TileKind::Door), Spawn::Mob(MobName::SCULPTURE)]
+        );
+        assert_eq!(spawns[&IVec2::new(2, 1)], vec![Spawn::Tile(TileKind::Wall)]);
+        assert!(!spawns.contains_key(&IVec2::ZERO));
+
+        assert!(matches!(
+            from_ascii("origin: 0 0\nmap:\n#?#\n"),
+            Err(ParseError::UnknownChar(3, '?'))
+        ));
+        assert!(matches!(
+            from_ascii("map:\n###\n"),
+            Err(ParseError::MissingOrigin)
+        ));
+    }
+
+    #[test]
+    fn test_floors() {
+        let level = "\
+map:
+#@>#
+floor: -1
+#z<#";
+        let MapgenResult { spawns, floors, .. } = from_ascii(level).unwrap();
+        assert_eq!(
+            spawns[&IVec2::new(1, 0)],
+            vec![Spawn::Tile(TileKind::StairsDown)]
+        );
+        assert_eq!(floors.keys().copied().collect::<Vec<_>>(), vec![-1]);
+        assert_eq!(
+            floors[&-1][&IVec2::new(1, 0)],
+            vec![Spawn::Tile(TileKind::StairsUp)]
+        );
+        assert_eq!(floors[&-1][&IVec2::ZERO], vec![Spawn::Mob(MobName::ZOMBIE)]);
+        assert!(matches!(
+            from_ascii("map:\n@\nfloor: up\n"),
+            Err(ParseError::BadValue(3, _))
+        ));
+    }
+}
@@ -19,11 +19,57 @@ pub use mat::LightingMaterial;
 const LIGHTING_ORDER_OFFSET: isize = 20;
 pub const LIGHTING_LAYER: usize = 4;
 pub const UI_LAYER: usize = 4;
-pub const FOG: [f32; 6] = [130.0, 50.0, 130.0, 60.0, 80.0, 100.0];
 
 #[derive(Component)]
 pub struct RenderPlane;
 
+/// Fog density outside of any zone, and before the player has been in one.
+const DEFAULT_FOG: f32 = 130.0;
+/// How far into a zone the player walks before its fog has fully replaced
+/// the fog of the zone they came from.
+const FOG_BLEND_DISTANCE: f32 = 30.0;
+
+/// Fog blending from the zone the player came from into the one they're in.
+pub struct FogBlend {
+    /// The zone the player is in.
+    zone: Option<IRect>,
+    /// Where the player entered it.
+    entered_at: IVec2,
+    /// Fog density when they did.
+    from: f32,
+    /// Fog density right now.
+    current: f32,
+}
+
+impl Default for FogBlend {
+    fn default() -> Self {
+        Self {
+            zone: None,
+            entered_at: IVec2::ZERO,
+            from: DEFAULT_FOG,
+            current: DEFAULT_FOG,
+        }
+    }
+}
+
+impl FogBlend {
+    /// Updates the blend for the player at `pos`, returning the fog density.
+    fn update(&mut self, zones: &Zones, pos: IVec2) -> f32 {
+        let Some(zone) = zones.get(pos) else {
+            return self.current;
+        };
+        if self.zone != Some(zone.rect) {
+            self.zone = Some(zone.rect);
+            self.entered_at = pos;
+            self.from = self.current;
+        }
+        let walked = (pos - self.entered_at).abs().max_element() as f32;
+        let alpha = (walked / FOG_BLEND_DISTANCE).min(1.0);
+        self.current = self.from.lerp(zone.spec.fog, alpha);
+        self.current
+    }
+}
+
 pub fn get_mouse_location(
     pointers: Query<&PointerInteraction>,
     mut mouse_writer: EventWriter<PlaneMouseMovedEvent>,
@@ -48,6 +94,7 @@ pub fn update_lighting_pass(
     mut player_injury: Query<&mut WobbleEffects, With<Player>>,
     player_location: Query<&MapPos, With<Player>>,
     zones: Res<Zones>,
+    mut fog_blend: Local<FogBlend>,
     primary_camera_query: Query<&Transform, With<PrimaryCamera>>,
     settings: ResMut<UiSettings>,
     flashlight_info: Res<FlashlightInfo>,
@@ -89,18 +136,9 @@ pub fn update_lighting_pass(
     let player_light_center = Vec4::new(0.5, 0.5, 0.11, 0.0);
 
     let fog_density: f32 = if let Ok(pos) = player_location.get_single() {
-        let mut fog = 130.0;
-        for (i, zone) in zones.0.iter().enumerate() {
-            if zone.contains(pos.0) {
-                let fog_i = FOG[i];
-                let fog_prev = if i > 0 { FOG[i - 1] } else { FOG[i] };
-                let alpha = ((pos.0.x - zone.min.x) as f32) / ((zone.max.x - zone.min.x) as f32);
-                fog = alpha * fog_i + (1.0 - alpha) * fog_prev;
-            }
-        }
-        fog
+        fog_blend.update(&zones, pos.0)
     } else {
-        130.0
+        DEFAULT_FOG
     };
 
     let player_light_color = if let Some(injury) = player_injury
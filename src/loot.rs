@@ -0,0 +1,310 @@
+//! Weighted loot tables, read from `assets/loot.ron`. Map generation rolls
+//! them to scatter items over zones; in game they're rolled for mobs that
+//! die and crates the player searches.
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+use rand::{Rng, seq::SliceRandom as _};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::TextEvent,
+    assets::load_ron_or_default,
+    difficulty::{Difficulty, RunState},
+    map::{ItemKind, Map, Tile, TileKind},
+    mob::MobName,
+    player::{GunType, Inventory, PlayerMoveEvent},
+};
+
+/// Loot tables shipped with the game, used when `assets/loot.ron` can't be read.
+pub const DEFAULT_LOOT_TABLES: &str = include_str!("../assets/loot.ron");
+
+pub const LOOT_TABLES_PATH: &str = "assets/loot.ron";
+
+/// An item a loot table can drop, without its amount of ammo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum LootItem {
+    Ammo(GunType),
+    Gun(GunType),
+}
+
+impl LootItem {
+    pub fn with_quantity(self, quantity: usize) -> ItemKind {
+        match self {
+            LootItem::Ammo(gun_type) => ItemKind::Ammo(gun_type, quantity),
+            LootItem::Gun(gun_type) => ItemKind::Gun(gun_type, quantity),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LootEntry {
+    /// Chance of this entry being picked, relative to the table's other entries.
+    pub weight: u32,
+    pub item: LootItem,
+    /// Fewest and most rounds of ammo in the item.
+    pub quantity: (usize, usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LootTable {
+    /// Fewest and most items dropped by a roll of the table.
+    pub count: (usize, usize),
+    pub entries: Vec<LootEntry>,
+}
+
+impl LootTable {
+    /// Picks the items for one roll of the table.
+    pub fn roll(&self, rng: &mut impl Rng) -> Vec<ItemKind> {
+        let count = rng.gen_range(self.count.0..=self.count.1);
+        (0..count)
+            .filter_map(|_| {
+                let entry = self.entries.choose_weighted(rng, |e| e.weight).ok()?;
+                let quantity = rng.gen_range(entry.quantity.0..=entry.quantity.1);
+                Some(entry.item.with_quantity(quantity))
+            })
+            .collect()
+    }
+
+    /// Average rounds of ammo of each item a roll of the table drops.
+    pub fn expected_totals(&self) -> HashMap<LootItem, f64> {
+        let total_weight: u32 = self.entries.iter().map(|e| e.weight).sum();
+        let mean = |(min, max): (usize, usize)| (min + max) as f64 / 2.0;
+        let mut totals = HashMap::new();
+        for entry in &self.entries {
+            let share = entry.weight as f64 / total_weight as f64;
+            *totals.entry(entry.item).or_default() +=
+                mean(self.count) * share * mean(entry.quantity);
+        }
+        totals
+    }
+
+    fn check(&self, name: &str) -> Result<(), LootError> {
+        let bad = self.count.0 > self.count.1
+            || (self.count.1 > 0 && self.entries.iter().all(|e| e.weight == 0))
+            || self.entries.iter().any(|e| e.quantity.0 > e.quantity.1);
+        if bad {
+            return Err(LootError::BadTable(name.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Every loot table, by name, and which of them mobs and crates drop.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Resource)]
+pub struct LootTables {
+    pub tables: HashMap<String, LootTable>,
+    /// Table rolled when a mob of each kind dies.
+    #[serde(default)]
+    pub mobs: HashMap<MobName, String>,
+    /// Table rolled when a crate is searched.
+    #[serde(default)]
+    pub crates: Option<String>,
+}
+
+impl LootTables {
+    pub fn get(&self, name: &str) -> Result<&LootTable, LootError> {
+        self.tables
+            .get(name)
+            .ok_or_else(|| LootError::UnknownTable(name.to_owned()))
+    }
+
+    /// Items dropped by a mob called `name` when it dies.
+    pub fn roll_mob(&self, name: &MobName, rng: &mut impl Rng) -> Vec<ItemKind> {
+        self.mobs
+            .get(name)
+            .and_then(|name| self.tables.get(name))
+            .map_or_else(Vec::new, |table| table.roll(rng))
+    }
+
+    /// Items found in a crate.
+    pub fn roll_crate(&self, rng: &mut impl Rng) -> Vec<ItemKind> {
+        self.crates
+            .as_ref()
+            .and_then(|name| self.tables.get(name))
+            .map_or_else(Vec::new, |table| table.roll(rng))
+    }
+}
+
+#[derive(Debug)]
+pub enum LootError {
+    Ron(ron::error::SpannedError),
+    /// A table name that isn't in the list.
+    UnknownTable(String),
+    /// A table with a minimum above its maximum, or with nothing to pick from.
+    BadTable(String),
+}
+
+impl std::fmt::Display for LootError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LootError::Ron(e) => write!(f, "{e}"),
+            LootError::UnknownTable(name) => write!(f, "no loot table named {name:?}"),
+            LootError::BadTable(name) => write!(f, "loot table {name:?} has a bad range"),
+        }
+    }
+}
+
+impl std::error::Error for LootError {}
+
+/// Parses loot tables, checking their ranges and that mobs and crates name
+/// tables that exist.
+pub fn parse_loot_tables(ron: &str) -> Result<LootTables, LootError> {
+    let loot: LootTables = ron::from_str(ron).map_err(LootError::Ron)?;
+    for (name, table) in &loot.tables {
+        table.check(name)?;
+    }
+    for name in loot.mobs.values().chain(&loot.crates) {
+        loot.get(name)?;
+    }
+    Ok(loot)
+}
+
+pub fn default_loot_tables() -> LootTables {
+    parse_loot_tables(DEFAULT_LOOT_TABLES).expect("default loot tables should parse")
+}
+
+/// Reads the loot tables from [`LOOT_TABLES_PATH`], or the built-in ones.
+pub fn load_loot_tables() -> LootTables {
+    load_ron_or_default(
+        LOOT_TABLES_PATH,
+        "loot tables",
+        parse_loot_tables,
+        default_loot_tables,
+    )
+}
+
+/// A crate that has already been searched.
+#[derive(Component)]
+pub struct Searched;
+
+/// Searches crates the player steps onto, putting whatever is in them
+/// straight into the inventory.
+#[allow(clippy::too_many_arguments)]
+fn search_crates(
+    mut commands: Commands,
+    mut ev_player_move: EventReader<PlayerMoveEvent>,
+    mut ev_text: EventWriter<TextEvent>,
+    map: Res<Map>,
+    loot: Res<LootTables>,
+    difficulty: Res<Difficulty>,
+    mut inventory: ResMut<Inventory>,
+    q_crates: Query<(Entity, &Tile), Without<Searched>>,
+) {
+    let mut rng = rand::thread_rng();
+    for PlayerMoveEvent { dest, .. } in ev_player_move.read() {
+        for (entity, Tile(kind)) in q_crates.iter_many(map.get(dest.0)) {
+            if *kind != TileKind::Crate {
+                continue;
+            }
+            commands.entity(entity).insert(Searched);
+            let items = loot
+                .roll_crate(&mut rng)
+                .into_iter()
+                .map(|item| difficulty.scale_loot(item))
+                .collect::<Vec<_>>();
+            let text = if items.is_empty() {
+                "the crate is empty".to_owned()
+            } else {
+                let found = items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>();
+                format!("found {}!", found.join(", "))
+            };
+            for item in items {
+                inventory.add(item);
+            }
+            ev_text.send(TextEvent {
+                text,
+                position: dest.to_vec2(),
+                duration: Duration::from_secs(5),
+                ..default()
+            });
+        }
+    }
+}
+
+pub struct LootPlugin;
+
+impl Plugin for LootPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_loot_tables());
+        app.add_systems(Update, search_crates.run_if(in_state(RunState::Playing)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    #[test]
+    fn test_loot_table_totals() {
+        let loot = default_loot_tables();
+        // one pistol magazine two times in three, else four shells
+        let house = loot.get("house").unwrap();
+        let totals = house.expected_totals();
+        assert!((totals[&LootItem::Ammo(GunType::Pistol)] - 10.0).abs() < 1e-9);
+        assert!((totals[&LootItem::Ammo(GunType::Shotgun)] - 4.0 / 3.0).abs() < 1e-9);
+
+        let warehouse = loot.get("warehouse").unwrap();
+        let expected = warehouse.expected_totals();
+        let mut rng = StdRng::seed_from_u64(0);
+        const ROLLS: usize = 10_000;
+        let mut totals: HashMap<LootItem, f64> = HashMap::new();
+        for _ in 0..ROLLS {
+            let items = warehouse.roll(&mut rng);
+            assert!((12..=16).contains(&items.len()));
+            for item in items {
+                let (item, quantity) = match item {
+                    ItemKind::Ammo(gun_type, n) => (LootItem::Ammo(gun_type), n),
+                    ItemKind::Gun(gun_type, n) => (LootItem::Gun(gun_type), n),
+                };
+                *totals.entry(item).or_default() += quantity as f64;
+            }
+        }
+        for (item, expected) in expected {
+            let average = totals[&item] / ROLLS as f64;
+            assert!(
+                (average - expected).abs() < expected * 0.02,
+                "{item:?}: {average} vs {expected}"
+            );
+        }
+
+        // zone tables drop, on average, the ammo zones used to place by hand
+        for (name, pistol, shotgun) in [
+            ("woods", 150.0, 0.0),
+            ("warehouse", 105.0, 28.0),
+            ("cellar", 60.0, 8.0),
+            ("deep woods", 90.0, 90.0),
+            ("railyard", 105.0, 14.0),
+            ("attic", 0.0, 12.0),
+            ("farmstead", 45.0, 16.0),
+            ("marsh", 0.0, 16.0),
+        ] {
+            let totals = loot.get(name).unwrap().expected_totals();
+            let total = |gun_type| totals.get(&LootItem::Ammo(gun_type)).copied();
+            assert!(
+                (total(GunType::Pistol).unwrap_or(0.0) - pistol).abs() < 1e-9,
+                "{name}"
+            );
+            assert!(
+                (total(GunType::Shotgun).unwrap_or(0.0) - shotgun).abs() < 1e-9,
+                "{name}"
+            );
+        }
+
+        let bad = r#"(tables: {"x": (count: (2, 1), entries: [])})"#;
+        assert!(matches!(
+            parse_loot_tables(bad),
+            Err(LootError::BadTable(_))
+        ));
+        let bad = r#"(tables: {}, mobs: {"Zombie": "x"})"#;
+        assert!(matches!(
+            parse_loot_tables(bad),
+            Err(LootError::UnknownTable(_))
+        ));
+    }
+}
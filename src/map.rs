@@ -4,10 +4,22 @@ use std::{
 };
 
 use bevy::prelude::*;
+use rogue_algebra::{
+    bitgrid::BitGrid,
+    dijkstra::DijkstraMap,
+    fov::{FovAlgorithm, FovOpts, RadiusMetric},
+};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    assets::load_ron_or_default,
+    bestiary::Bestiary,
+    difficulty::{Difficulty, RunState},
+    floor::{CurrentFloor, OffFloor, StashedFloors},
+    loot::LootTables,
+    mapgen::{MapgenResult, Zone, ZoneSpec, ZoneStream},
     player::{FlashlightInfo, GunType, MouseWorldCoords, Player},
-    spawn::SpawnEvent,
+    spawn::{Spawn, SpawnEvent},
     ui::UiSettings,
 };
 
@@ -52,7 +64,7 @@ pub struct BlocksSight;
 #[derive(Component)]
 pub struct Tile(pub TileKind);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ItemKind {
     Ammo(GunType, usize),
     Gun(GunType, usize),
@@ -77,35 +89,385 @@ impl Map {
     pub fn get(&self, pos: IVec2) -> impl Iterator<Item = &Entity> {
         self.0.get(&pos).into_iter().flatten()
     }
+    fn remove(&mut self, pos: IVec2, entity: Entity) {
+        if let Some(entities) = self.0.get_mut(&pos) {
+            entities.retain(|&e| e != entity);
+            if entities.is_empty() {
+                self.0.remove(&pos);
+            }
+        }
+    }
     pub fn get_nearby(&self, center: IVec2, radius: i32) -> impl Iterator<Item = &Entity> {
-        (center.x - radius..center.x + radius)
-            .flat_map(move |x| (center.y - radius..center.y + radius).map(move |y| (x, y)))
+        (center.x - radius..=center.x + radius)
+            .flat_map(move |x| (center.y - radius..=center.y + radius).map(move |y| (x, y)))
             .filter_map(|(x, y)| self.0.get(&IVec2 { x, y }))
             .flatten()
     }
 }
 
-fn update_tilemap(mut tile_map: ResMut<Map>, query: Query<(Entity, &MapPos)>) {
-    tile_map.0.clear();
-    for (entity, MapPos(vec2)) in query.iter() {
-        tile_map.0.entry(*vec2).or_default().push(entity);
+/// How many frames apart debug builds check [`update_spatial_index`] against
+/// a full rebuild.
+#[cfg(debug_assertions)]
+const INDEX_CHECK_INTERVAL: u32 = 30;
+
+/// Every entity on the map, with whether it blocks sight and movement.
+type BlockersQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static MapPos,
+        Has<BlocksSight>,
+        Has<BlocksMovement>,
+    ),
+>;
+
+/// Keeps [`Map`], [`SightBlockedMap`] and [`WalkBlockedMap`] up to date with
+/// the entities that were spawned, moved, despawned, or gained or lost a
+/// blocking component since the last frame, instead of rebuilding them from
+/// every entity. Only the tiles those entities left or arrived on are looked
+/// at again. Debug builds check the result against a full rebuild every
+/// [`INDEX_CHECK_INTERVAL`] frames.
+#[allow(clippy::complexity)]
+pub fn update_spatial_index(
+    mut tile_map: ResMut<Map>,
+    mut sight_blocked_map: ResMut<SightBlockedMap>,
+    mut walk_blocked_map: ResMut<WalkBlockedMap>,
+    q_moved: Query<(Entity, &MapPos), Changed<MapPos>>,
+    q_new_blockers: Query<&MapPos, Or<(Added<BlocksSight>, Added<BlocksMovement>)>>,
+    q_blocks: BlockersQuery,
+    mut removed_pos: RemovedComponents<MapPos>,
+    mut removed_sight: RemovedComponents<BlocksSight>,
+    mut removed_walk: RemovedComponents<BlocksMovement>,
+    mut positions: Local<HashMap<Entity, IVec2>>,
+    mut dirty: Local<HashSet<IVec2>>,
+    #[cfg(debug_assertions)] mut frames: Local<u32>,
+) {
+    dirty.clear();
+    for entity in removed_sight.read().chain(removed_walk.read()) {
+        if let Some(&pos) = positions.get(&entity) {
+            dirty.insert(pos);
+        }
+    }
+    for entity in removed_pos.read() {
+        if let Some(pos) = positions.remove(&entity) {
+            tile_map.remove(pos, entity);
+            dirty.insert(pos);
+        }
     }
+    for (entity, &MapPos(pos)) in q_moved.iter() {
+        let old = positions.insert(entity, pos);
+        if old == Some(pos) {
+            continue;
+        }
+        if let Some(old) = old {
+            tile_map.remove(old, entity);
+            dirty.insert(old);
+        }
+        tile_map.0.entry(pos).or_default().push(entity);
+        dirty.insert(pos);
+    }
+    for MapPos(pos) in q_new_blockers.iter() {
+        dirty.insert(*pos);
+    }
+    for &pos in dirty.iter() {
+        let (mut blocks_sight, mut blocks_movement) = (false, false);
+        for (_, _, sight, walk) in q_blocks.iter_many(tile_map.get(pos)) {
+            blocks_sight |= sight;
+            blocks_movement |= walk;
+        }
+        // only touch the sets when they change, so change detection means something
+        if blocks_sight != sight_blocked_map.0.contains(&pos) {
+            if blocks_sight {
+                sight_blocked_map.0.insert(pos);
+            } else {
+                sight_blocked_map.0.remove(&pos);
+            }
+        }
+        if blocks_movement != walk_blocked_map.0.contains(&pos) {
+            if blocks_movement {
+                walk_blocked_map.0.insert(pos);
+            } else {
+                walk_blocked_map.0.remove(&pos);
+            }
+        }
+    }
+    // rebuilding on every frame would cost debug builds more than the index
+    // saves, so only check every so often
+    #[cfg(debug_assertions)]
+    {
+        if *frames % INDEX_CHECK_INTERVAL == 0 {
+            assert!(
+                index_matches_rebuild(&tile_map, &sight_blocked_map, &walk_blocked_map, &q_blocks),
+                "spatial index drifted from a full rebuild"
+            );
+        }
+        *frames = frames.wrapping_add(1);
+    }
+}
+
+/// Whether the spatial index holds exactly what rebuilding it from scratch
+/// would.
+#[cfg(any(debug_assertions, test))]
+fn index_matches_rebuild(
+    tile_map: &Map,
+    sight_blocked_map: &SightBlockedMap,
+    walk_blocked_map: &WalkBlockedMap,
+    q_blocks: &BlockersQuery,
+) -> bool {
+    let mut tiles = HashMap::<IVec2, Vec<Entity>>::new();
+    let mut sight = HashSet::new();
+    let mut walk = HashSet::new();
+    for (entity, MapPos(pos), blocks_sight, blocks_movement) in q_blocks.iter() {
+        tiles.entry(*pos).or_default().push(entity);
+        if blocks_sight {
+            sight.insert(*pos);
+        }
+        if blocks_movement {
+            walk.insert(*pos);
+        }
+    }
+    let sorted = |tiles: &HashMap<IVec2, Vec<Entity>>| {
+        tiles
+            .iter()
+            .map(|(&pos, entities)| {
+                let mut entities = entities.clone();
+                entities.sort();
+                (pos, entities)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+    sorted(&tiles) == sorted(&tile_map.0)
+        && sight == sight_blocked_map.0
+        && walk == walk_blocked_map.0
+}
+
+#[derive(Resource, Default)]
+pub struct Zones(pub Vec<Zone>);
+
+impl Zones {
+    /// Returns the zone containing `pos`, if any.
+    pub fn get(&self, pos: IVec2) -> Option<&Zone> {
+        self.0.iter().find(|zone| zone.rect.contains(pos))
+    }
+}
+
+pub const ZONE_SPECS_PATH: &str = "assets/zones.ron";
+
+/// Reads the zone list from [`ZONE_SPECS_PATH`], or the built-in one.
+fn load_zone_specs() -> Vec<ZoneSpec> {
+    load_ron_or_default(
+        ZONE_SPECS_PATH,
+        "zones",
+        crate::mapgen::parse_zone_specs,
+        crate::mapgen::default_zone_specs,
+    )
 }
 
+/// Seed the current map was generated from.
 #[derive(Resource)]
-pub struct Zones(pub Vec<IRect>);
+pub struct MapSeed(pub u64);
+
+impl MapSeed {
+    /// Takes the seed from a `--seed <n>` launch argument, or picks one at random.
+    fn from_args() -> Self {
+        let seed = launch_arg("--seed")
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        Self(seed)
+    }
+}
+
+/// Returns the value following `name` on the command line, if any.
+pub(crate) fn launch_arg(name: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == name)?;
+    args.next()
+}
+
+/// Whether `name` was passed on the command line.
+fn launch_flag(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
 
-fn startup(mut commands: Commands, mut ev_spawn: EventWriter<SpawnEvent>) {
-    let crate::mapgen::MapgenResult { spawns, zones } = crate::mapgen::gen_map();
+/// Loads the level file given with `--level <path>`, if any.
+fn load_level_arg() -> Option<MapgenResult> {
+    let path = launch_arg("--level")?;
+    let level = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| crate::level::from_ascii(&s).map_err(|e| e.to_string()));
+    match level {
+        Ok(level) => Some(level),
+        Err(e) => {
+            error!("failed to load level {path}: {e}");
+            None
+        }
+    }
+}
+
+fn send_spawns(ev_spawn: &mut EventWriter<SpawnEvent>, spawns: HashMap<IVec2, Vec<Spawn>>) {
     for (pos, spawn_list) in spawns {
         for spawn in spawn_list.into_iter() {
             ev_spawn.send(SpawnEvent(pos, spawn));
         }
     }
+}
+
+fn startup(
+    mut commands: Commands,
+    mut ev_spawn: EventWriter<SpawnEvent>,
+    seed: Res<MapSeed>,
+    difficulty: Res<Difficulty>,
+    loot: Res<LootTables>,
+    bestiary: Res<Bestiary>,
+) {
+    if launch_flag("--endless") {
+        start_endless(commands, ev_spawn, seed.0, *difficulty, &loot, &bestiary);
+        return;
+    }
+    let level = load_level_arg().unwrap_or_else(|| {
+        info!("generating {} map with seed {}", *difficulty, seed.0);
+        crate::mapgen::gen_map_on(seed.0, &load_zone_specs(), *difficulty, &loot, &bestiary)
+            .or_else(|e| {
+                error!("failed to generate map from {ZONE_SPECS_PATH}: {e}");
+                crate::mapgen::gen_map_on(
+                    seed.0,
+                    &crate::mapgen::default_zone_specs(),
+                    *difficulty,
+                    &loot,
+                    &bestiary,
+                )
+            })
+            .expect("default zone specs should generate")
+    });
+    let report = crate::mapgen::validate(&level, &bestiary);
+    if !report.is_ok() {
+        warn!("level has {report}");
+    }
+    if let Some(path) = launch_arg("--export-level") {
+        let written = crate::level::to_ascii(&level)
+            .map_err(|e| e.to_string())
+            .and_then(|ascii| std::fs::write(&path, ascii).map_err(|e| e.to_string()));
+        match written {
+            Ok(()) => info!("exported level to {path}"),
+            Err(e) => error!("failed to export level to {path}: {e}"),
+        }
+    }
+    let MapgenResult {
+        spawns,
+        zones,
+        floors,
+    } = level;
+    send_spawns(&mut ev_spawn, spawns);
     commands.insert_resource(Zones(zones));
+    commands.insert_resource(StashedFloors::new(floors));
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Zone generator for endless mode, started with `--endless`.
+#[derive(Resource)]
+pub struct EndlessMode(pub ZoneStream);
+
+/// How close the player gets to the right edge of the last zone before the
+/// next one is generated.
+const ENDLESS_LOOKAHEAD: i32 = 40;
+/// Zones further behind the player's zone than this are despawned.
+const ENDLESS_ZONES_BEHIND: usize = 1;
+
+fn start_endless(
+    mut commands: Commands,
+    mut ev_spawn: EventWriter<SpawnEvent>,
+    seed: u64,
+    difficulty: Difficulty,
+    loot: &LootTables,
+    bestiary: &Bestiary,
+) {
+    info!("starting {difficulty} endless mode with seed {seed}");
+    let mut stream = ZoneStream::new(seed, &load_zone_specs(), difficulty, loot, bestiary)
+        .or_else(|e| {
+            error!("can't use {ZONE_SPECS_PATH} for endless mode: {e}");
+            ZoneStream::new(
+                seed,
+                &crate::mapgen::default_zone_specs(),
+                difficulty,
+                loot,
+                bestiary,
+            )
+        })
+        .expect("default zone specs should stream");
+    let level = stream.next_zone();
+    send_spawns(&mut ev_spawn, level.spawns);
+    commands.insert_resource(Zones(level.zones));
+    commands.insert_resource(StashedFloors::new(level.floors));
+    commands.insert_resource(EndlessMode(stream));
+}
+
+/// Generates the next endless zone once the player nears the right edge of
+/// the last one, and despawns zones the player has left far behind, walling
+/// off the gap they leave.
+#[allow(clippy::complexity)]
+fn stream_zones(
+    mut commands: Commands,
+    mut endless: ResMut<EndlessMode>,
+    mut zones: ResMut<Zones>,
+    current_floor: Res<CurrentFloor>,
+    mut floors: ResMut<StashedFloors>,
+    mut ev_spawn: EventWriter<SpawnEvent>,
+    q_player: Query<&MapPos, With<Player>>,
+    q_map_pos: Query<(Entity, &MapPos), Without<Player>>,
+    q_off_floor: Query<(Entity, &OffFloor)>,
+) {
+    let Ok(&MapPos(player_pos)) = q_player.get_single() else {
+        return;
+    };
+
+    let edge = zones.0.last().map_or(i32::MIN, |zone| zone.rect.max.x);
+    if player_pos.x + ENDLESS_LOOKAHEAD > edge {
+        let MapgenResult {
+            spawns,
+            zones: new_zones,
+            floors: new_floors,
+        } = endless.0.next_zone();
+        info!("generated endless zone {}", endless.0.depth());
+        floors.add_spawns(0, &current_floor, spawns, &mut ev_spawn);
+        for (floor, spawns) in new_floors {
+            floors.add_spawns(floor, &current_floor, spawns, &mut ev_spawn);
+        }
+        zones.0.extend(new_zones);
+    }
+
+    let Some(current) = zones
+        .0
+        .iter()
+        .position(|zone| zone.rect.contains(player_pos))
+    else {
+        return;
+    };
+    if current <= ENDLESS_ZONES_BEHIND {
+        return;
+    }
+    let kept = zones.0[current - ENDLESS_ZONES_BEHIND].rect;
+    // The last column of the dropped zones becomes a wall along the kept one.
+    let cutoff = kept.min.x - 1;
+    let off_floor = q_off_floor.iter().map(|(entity, off)| (entity, &off.pos));
+    for (entity, pos) in q_map_pos
+        .iter()
+        .map(|(e, MapPos(pos))| (e, pos))
+        .chain(off_floor)
+    {
+        if pos.x <= cutoff {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    floors.drop_up_to(cutoff);
+    endless.0.forget_left_of(cutoff);
+    let wall = (kept.min.y - 1..=kept.max.y + 1)
+        .map(|y| (IVec2::new(cutoff, y), vec![Spawn::Tile(TileKind::Wall)]))
+        .collect();
+    floors.add_spawns(0, &current_floor, wall, &mut ev_spawn);
+    zones.0.drain(..current - ENDLESS_ZONES_BEHIND);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum TileKind {
     Wall,
     Door,
@@ -115,6 +477,8 @@ pub enum TileKind {
     ShippingContainer,
     Lever,
     LeverPulled,
+    StairsDown,
+    StairsUp,
 }
 
 impl TileKind {
@@ -122,14 +486,25 @@ impl TileKind {
         use TileKind::*;
         match self {
             Wall | Tree | ShippingContainer => true,
-            Bush | Crate | Door | Lever | LeverPulled => false,
+            Bush | Crate | Door | Lever | LeverPulled | StairsDown | StairsUp => false,
         }
     }
     pub fn blocks_sight(&self) -> bool {
         use TileKind::*;
         match self {
             Wall | Tree | Bush | Crate | Door | ShippingContainer => true,
-            Lever | LeverPulled => false,
+            Lever | LeverPulled | StairsDown | StairsUp => false,
+        }
+    }
+    /// Loudness a noise loses passing through the tile, on top of what it
+    /// loses crossing any tile.
+    pub fn muffling(&self) -> i32 {
+        use TileKind::*;
+        match self {
+            Wall | ShippingContainer => 8,
+            Door => 4,
+            Bush | Tree | Crate => 2,
+            Lever | LeverPulled | StairsDown | StairsUp => 0,
         }
     }
 }
@@ -137,13 +512,31 @@ impl TileKind {
 #[derive(Default, Resource)]
 pub struct SightBlockedMap(pub HashSet<IVec2>);
 
+/// The tiles in [`SightBlockedMap`], in a grid just big enough to hold them.
+/// Only marked as changed when one of them appears, disappears or moves.
+#[derive(Default, Resource)]
+pub struct OpacityMap(pub BitGrid);
+
 pub fn update_visibility(
-    query: Query<&MapPos, With<BlocksSight>>,
-    mut vis_map: ResMut<SightBlockedMap>,
+    vis_map: Res<SightBlockedMap>,
+    mut opacity_map: ResMut<OpacityMap>,
+    mut scratch: Local<BitGrid>,
 ) {
-    vis_map.0.clear();
-    for pos in query.iter() {
-        vis_map.0.insert(pos.0);
+    if !vis_map.is_changed() {
+        return;
+    }
+    if vis_map.0.is_empty() {
+        *scratch = BitGrid::default();
+    } else {
+        let min = vis_map.0.iter().fold(IVec2::MAX, |a, &b| a.min(b));
+        let max = vis_map.0.iter().fold(IVec2::MIN, |a, &b| a.max(b));
+        scratch.reset(rogue_algebra::Rect::new(min.x, max.x, min.y, max.y));
+        for &pos in vis_map.0.iter() {
+            scratch.insert(pos.into());
+        }
+    }
+    if opacity_map.0 != *scratch {
+        std::mem::swap(&mut opacity_map.0, &mut scratch);
     }
 }
 
@@ -175,36 +568,44 @@ pub fn path(
     .map(|(path, _cost)| path)
 }
 
-pub fn update_walkability(
-    query: Query<&MapPos, With<BlocksMovement>>,
-    mut walk_map: ResMut<WalkBlockedMap>,
-) {
-    walk_map.0.clear();
-    for pos in query.iter() {
-        walk_map.0.insert(pos.0);
-    }
-}
+/// How the player's field of view is cast: recursive shadowcasting, as it
+/// always has been. It isn't symmetric, so it says nothing about which mobs
+/// see the player; they cast their own, see [`crate::vision::Vision`].
+pub const PLAYER_FOV: FovOpts = FovOpts {
+    algorithm: FovAlgorithm::Recursive,
+    radius: 99,
+    metric: RadiusMetric::Chebyshev,
+};
 
 #[derive(Default, Resource)]
-pub struct PlayerVisibilityMap(pub HashSet<IVec2>);
+pub struct PlayerVisibilityMap(pub BitGrid);
 
+/// Recomputes what the player can see, only when they've moved to another
+/// tile or something that blocks sight has changed.
 pub fn update_player_visibility(
     mut player_vis_map: ResMut<PlayerVisibilityMap>,
     q_player: Query<&MapPos, With<Player>>,
-    sight_blocked_map: Res<SightBlockedMap>,
+    opacity_map: Res<OpacityMap>,
+    mut fov_origin: Local<Option<IVec2>>,
 ) {
-    player_vis_map.0.clear();
-    let player_pos = q_player.single();
-    for pos in rogue_algebra::fov::calculate_fov(player_pos.0.into(), 99, |pos| {
-        sight_blocked_map.0.contains(&pos.into())
-    }) {
-        player_vis_map.0.insert(pos.into());
+    let player_pos = q_player.single().0;
+    if *fov_origin == Some(player_pos) && !opacity_map.is_changed() {
+        return;
     }
+    *fov_origin = Some(player_pos);
+    rogue_algebra::fov::calculate_fov_grid(
+        player_pos.into(),
+        PLAYER_FOV,
+        &opacity_map.0,
+        &mut player_vis_map.0,
+    );
 }
 
-#[derive(Default, Resource)]
+#[derive(Default, Resource, PartialEq)]
 pub struct FlashlightMap(pub HashSet<IVec2>);
 
+/// Recomputes the tiles in the flashlight beam, only marking the map changed
+/// when they're different.
 pub fn update_flashlight_map(
     player_vis_map: Res<PlayerVisibilityMap>,
     mut flashlight_map: ResMut<FlashlightMap>,
@@ -212,17 +613,76 @@ pub fn update_flashlight_map(
     q_player: Query<&Transform, With<Player>>,
     flashlight_info: Res<FlashlightInfo>,
 ) {
-    flashlight_map.0.clear();
+    let mut lit = HashSet::new();
     let player_pos = q_player.single().translation.xy();
     let flashlight_dir = mouse_world_coords.0 - player_pos;
     let allowed_angle_radians = flashlight_info.cone_width_degrees * (PI / 180.0);
-    for &p in player_vis_map.0.iter() {
+    for p in player_vis_map.0.iter().map(IVec2::from) {
         for world_pos in MapPos(p).corners() {
             if (world_pos - player_pos).angle_to(flashlight_dir).abs() <= allowed_angle_radians {
-                flashlight_map.0.insert(p);
+                lit.insert(p);
             }
         }
     }
+    flashlight_map.set_if_neq(FlashlightMap(lit));
+}
+
+/// How far from the player, in each direction, the navigation maps reach.
+/// Mobs further away find their own paths.
+pub const NAVIGATION_RADIUS: i32 = 50;
+
+/// Cost of walking to the player from each tile near them. Mobs chasing the
+/// player step downhill on it instead of each searching for a path.
+#[derive(Default, Resource)]
+pub struct PlayerDistanceMap(pub DijkstraMap);
+
+/// Cost of walking from each tile near the player to the nearest free tile
+/// that blocks sight, where a mob could hide.
+#[derive(Default, Resource)]
+pub struct HidingSpotMap(pub DijkstraMap);
+
+/// Rebuilds the navigation maps when the player moves to another tile or
+/// what they're built from changes. Tiles in the flashlight beam cost more to
+/// cross, so mobs go around it when they can.
+pub fn update_navigation_maps(
+    q_player: Query<&MapPos, With<Player>>,
+    walk_blocked_map: Res<WalkBlockedMap>,
+    sight_blocked_map: Res<SightBlockedMap>,
+    flashlight_map: Res<FlashlightMap>,
+    mut player_distance_map: ResMut<PlayerDistanceMap>,
+    mut hiding_spot_map: ResMut<HidingSpotMap>,
+    mut built_at: Local<Option<IVec2>>,
+) {
+    let player_pos = q_player.single().0;
+    if *built_at == Some(player_pos)
+        && !walk_blocked_map.is_changed()
+        && !sight_blocked_map.is_changed()
+        && !flashlight_map.is_changed()
+    {
+        return;
+    }
+    *built_at = Some(player_pos);
+    let size = 2 * NAVIGATION_RADIUS + 1;
+    let bound = rogue_algebra::Rect::new_centered(player_pos.into(), size, size);
+    let cost = |p: rogue_algebra::Pos| {
+        let p = IVec2::from(p);
+        if walk_blocked_map.0.contains(&p) {
+            None
+        } else if flashlight_map.0.contains(&p) {
+            Some(2)
+        } else {
+            Some(1)
+        }
+    };
+    player_distance_map.0 = DijkstraMap::new(bound, &[player_pos.into()], cost);
+    let hiding_spots = sight_blocked_map
+        .0
+        .iter()
+        .filter(|p| !walk_blocked_map.0.contains(p))
+        .map(|&p| rogue_algebra::Pos::from(p))
+        .filter(|&p| bound.contains(p))
+        .collect::<Vec<_>>();
+    hiding_spot_map.0 = DijkstraMap::new(bound, &hiding_spots, cost);
 }
 
 #[derive(Default, Component)]
@@ -265,7 +725,7 @@ pub fn update_fov_map(
     let player_pos = q_player.single().translation.xy();
     let flashlight_dir = mouse_world_coords.0 - player_pos;
     let allowed_angle_radians = FOV_CONE_DEGREES * (PI / 180.0);
-    for &p in vis_map.0.iter() {
+    for p in vis_map.0.iter().map(IVec2::from) {
         for world_pos in MapPos(p).corners() {
             if (world_pos - player_pos).angle_to(flashlight_dir).abs() <= allowed_angle_radians {
                 fov_map.0.insert(p);
@@ -282,7 +742,7 @@ pub fn apply_visibility(
     settings: Res<UiSettings>,
 ) {
     for (map_pos, mut visibility) in query.iter_mut() {
-        *visibility = if settings.show_visibility && !player_vis_map.0.contains(&map_pos.0)
+        *visibility = if settings.show_visibility && !player_vis_map.0.contains(map_pos.0.into())
             || settings.show_flashlight && !flashlight_map.0.contains(&map_pos.0)
             || settings.show_fov && !fov_map.0.contains(&map_pos.0)
         {
@@ -300,26 +760,201 @@ impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Map>();
         app.init_resource::<SightBlockedMap>();
+        app.init_resource::<OpacityMap>();
         app.init_resource::<WalkBlockedMap>();
         app.init_resource::<PlayerVisibilityMap>();
         app.init_resource::<FlashlightMap>();
+        app.init_resource::<PlayerDistanceMap>();
+        app.init_resource::<HidingSpotMap>();
         app.init_resource::<FovMap>();
-        app.add_systems(Startup, startup);
+        app.insert_resource(MapSeed::from_args());
+        app.init_resource::<Zones>();
+        app.init_state::<RunState>();
+        if let Some(difficulty) = Difficulty::from_args() {
+            app.insert_resource(difficulty);
+            app.add_systems(Startup, crate::difficulty::skip_start_screen);
+        }
+        app.add_systems(OnEnter(RunState::Playing), startup);
         app.add_systems(
             Update,
             (
-                update_tilemap,
+                update_spatial_index,
                 update_visibility,
-                update_walkability,
                 update_player_visibility,
                 update_flashlight_map,
+                update_navigation_maps,
                 update_fov_map,
                 apply_visibility,
                 update_lit,
             )
                 .chain()
-                .after(crate::spawn::spawn),
+                .after(crate::spawn::spawn)
+                .run_if(in_state(RunState::Playing)),
+        );
+        app.add_systems(
+            Update,
+            stream_zones
+                .run_if(resource_exists::<EndlessMode>)
+                .before(crate::spawn::spawn),
         );
         app.add_event::<SpawnEvent>();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut v: Vec<IVec2>) -> Vec<IVec2> {
+        v.sort_by_key(|p| (p.x, p.y));
+        v
+    }
+
+    /// Asserts that the index holds what it would if rebuilt from scratch.
+    fn assert_index_matches_rebuild(app: &mut App) {
+        let mut system_state = bevy::ecs::system::SystemState::<(
+            Res<Map>,
+            Res<SightBlockedMap>,
+            Res<WalkBlockedMap>,
+            BlockersQuery,
+        )>::new(app.world_mut());
+        let (tile_map, sight, walk, q_blocks) = system_state.get(app.world());
+        assert!(
+            index_matches_rebuild(&tile_map, &sight, &walk, &q_blocks),
+            "spatial index drifted from a full rebuild"
+        );
+    }
+
+    #[test]
+    fn test_spatial_index() {
+        let mut app = App::new();
+        app.init_resource::<Map>();
+        app.init_resource::<SightBlockedMap>();
+        app.init_resource::<WalkBlockedMap>();
+        app.add_systems(Update, update_spatial_index);
+
+        let world = app.world_mut();
+        let wall = world
+            .spawn((MapPos(IVec2::new(0, 0)), BlocksMovement, BlocksSight))
+            .id();
+        let door = world.spawn((MapPos(IVec2::new(1, 0)), BlocksSight)).id();
+        let mob = world.spawn((MapPos(IVec2::new(2, 0)), BlocksMovement)).id();
+        world.spawn(MapPos(IVec2::new(2, 0)));
+        app.update();
+        let world = app.world();
+        assert_eq!(
+            sorted(
+                world
+                    .resource::<SightBlockedMap>()
+                    .0
+                    .iter()
+                    .copied()
+                    .collect()
+            ),
+            [IVec2::new(0, 0), IVec2::new(1, 0)]
+        );
+        assert_eq!(world.resource::<Map>().get(IVec2::new(2, 0)).count(), 2);
+        assert_index_matches_rebuild(&mut app);
+
+        let world = app.world_mut();
+        world.entity_mut(mob).insert(MapPos(IVec2::new(1, 0)));
+        world.entity_mut(door).remove::<BlocksSight>();
+        world.despawn(wall);
+        app.update();
+        let world = app.world();
+        assert!(world.resource::<SightBlockedMap>().0.is_empty());
+        assert_eq!(
+            sorted(
+                world
+                    .resource::<WalkBlockedMap>()
+                    .0
+                    .iter()
+                    .copied()
+                    .collect()
+            ),
+            [IVec2::new(1, 0)]
+        );
+        assert_eq!(world.resource::<Map>().get(IVec2::new(0, 0)).count(), 0);
+        assert_eq!(world.resource::<Map>().get(IVec2::new(1, 0)).count(), 2);
+
+        assert_index_matches_rebuild(&mut app);
+
+        app.world_mut().entity_mut(door).insert(BlocksSight);
+        app.update();
+        assert!(
+            app.world()
+                .resource::<SightBlockedMap>()
+                .0
+                .contains(&IVec2::new(1, 0))
+        );
+        assert_index_matches_rebuild(&mut app);
+    }
+
+    #[test]
+    fn test_navigation_maps_rebuild_only_on_change() {
+        let mut app = App::new();
+        app.init_resource::<SightBlockedMap>();
+        app.init_resource::<WalkBlockedMap>();
+        app.init_resource::<FlashlightMap>();
+        app.init_resource::<PlayerDistanceMap>();
+        app.init_resource::<HidingSpotMap>();
+        app.add_systems(Update, update_navigation_maps);
+        app.world_mut()
+            .resource_mut::<SightBlockedMap>()
+            .0
+            .insert(IVec2::new(3, 0));
+        let player = app
+            .world_mut()
+            .spawn((
+                Player {
+                    damage: 0,
+                    max_damage: 1,
+                },
+                MapPos(IVec2::ZERO),
+            ))
+            .id();
+        app.update();
+        let distance = |app: &App| app.world().resource::<PlayerDistanceMap>().0.clone();
+        let hiding = |app: &App| app.world().resource::<HidingSpotMap>().0.clone();
+        assert_eq!(distance(&app).get(IVec2::new(2, 0).into()), Some(2));
+        assert!(hiding(&app).get(IVec2::new(2, 0).into()).is_some());
+
+        // nothing changed, so nothing is rebuilt
+        app.world_mut()
+            .insert_resource(PlayerDistanceMap::default());
+        app.world_mut().insert_resource(HidingSpotMap::default());
+        app.update();
+        assert_eq!(distance(&app), DijkstraMap::default());
+        assert_eq!(hiding(&app), DijkstraMap::default());
+
+        app.world_mut()
+            .resource_mut::<FlashlightMap>()
+            .0
+            .insert(IVec2::new(1, 0));
+        app.update();
+        assert_eq!(distance(&app).get(IVec2::new(2, 0).into()), Some(3));
+        assert_eq!(hiding(&app).get(IVec2::new(0, 0).into()), Some(4));
+
+        app.world_mut()
+            .entity_mut(player)
+            .insert(MapPos(IVec2::new(0, 1)));
+        app.update();
+        assert_eq!(distance(&app).get(IVec2::new(0, 0).into()), Some(1));
+        assert!(hiding(&app).get(IVec2::new(2, 0).into()).is_some());
+    }
+
+    #[test]
+    fn test_get_nearby_is_centered() {
+        let mut map = Map::default();
+        let mut world = World::new();
+        for x in -2..=2 {
+            for y in -2..=2 {
+                let entity = world.spawn_empty().id();
+                map.0.entry(IVec2::new(x, y)).or_default().push(entity);
+            }
+        }
+        assert_eq!(map.get_nearby(IVec2::ZERO, 1).count(), 9);
+        assert_eq!(map.get_nearby(IVec2::ZERO, 2).count(), 25);
+        assert_eq!(map.get_nearby(IVec2::new(2, 2), 1).count(), 4);
+    }
+}
@@ -1,275 +1,124 @@
 #![allow(unused)]
-use bevy::math::{IRect, IVec2};
-use rand::{Rng, rngs::ThreadRng, seq::SliceRandom as _};
-use rogue_algebra::{Offset, Pos, Rect, TileMap};
-use std::collections::{HashMap, HashSet};
+use bevy::{
+    log::warn,
+    math::{IRect, IVec2},
+};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom as _};
+use rogue_algebra::{
+    Offset, Pos, Rect, TileMap,
+    path::dfs,
+    procgen::{
+        BspSplitOpts, ProcgenError, carve_line_drunk, gen_bsp_tree, gen_cellular_automata,
+        get_connecting_wall,
+    },
+    wfc::{Rules, WfcError},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
+};
 
 use crate::{
+    bestiary::{Bestiary, MobDef},
+    difficulty::Difficulty,
+    loot::LootTables,
     map::{ItemKind, TileKind},
-    mob::MobKind,
-    player::GunType,
+    mob::MobName,
+    player::{GunType, PLAYER_START},
+    prefab::Prefab,
+    sound::MusicLayer,
     spawn::Spawn,
 };
 
-fn get_connecting_wall(room1: Rect, room2: Rect) -> Option<Rect> {
-    // one-tile-wall between them
-    for (room1, room2) in &[(room1, room2), (room2, room1)] {
-        // room2 right of room1
-        if room1.x2 + 2 == room2.x1 {
-            let y1 = room1.y1.max(room2.y1);
-            let y2 = room1.y2.min(room2.y2);
-            if y1 <= y2 {
-                return Some(Rect {
-                    x1: room1.x2 + 1,
-                    x2: room1.x2 + 1,
-                    y1,
-                    y2,
-                });
-            }
-        }
-        // room2 under room1
-        if room1.y2 + 2 == room2.y1 {
-            let x1 = room1.x1.max(room2.x1);
-            let x2 = room1.x2.min(room2.x2);
-            if x1 <= x2 {
-                return Some(Rect {
-                    x1,
-                    x2,
-                    y1: room1.y2 + 1,
-                    y2: room1.y2 + 1,
-                });
-            }
-        }
-    }
-    None
-}
-
-#[derive(Clone, Copy, Debug)]
-pub struct BspSplitOpts {
-    max_width: i32,
-    max_height: i32,
-    min_width: i32,
-    min_height: i32,
-}
+/// How many times a zone generator is rerolled before falling back to an
+/// open layout.
+const MAX_LAYOUT_ATTEMPTS: usize = 100;
+/// How many seeds a map is tried with before its zones are laid out in a
+/// chain instead, when some linked zones can't be joined.
+const MAX_CONNECT_ATTEMPTS: usize = 10;
 
-pub enum BspTree {
-    Split(Box<BspTree>, Box<BspTree>),
-    Room(Rect),
-}
+/// Chance that a BSP room gets a prefab, if one fits.
+const PREFAB_CHANCE: f64 = 0.3;
+/// Sample the railyard's container stacks are grown from: `<=>` is a
+/// container, `-` and `|` are aisles meeting at `+`, and `.` is a gap.
+const RAILYARD_SAMPLE: &str = include_str!("../assets/railyard.txt");
+/// Rows between the aisles pinned across the railyard.
+const RAILYARD_AISLE_SPACING: usize = 12;
+/// Loot table rolled for the items left behind in each town house.
+const HOUSE_LOOT: &str = "house";
+/// Waypoints in each patrol loop, counting where the patroller spawns.
+const PATROL_WAYPOINTS: usize = 4;
 
-impl BspTree {
-    fn into_room_graph(self) -> RoomGraph {
-        match self {
-            BspTree::Room(rect) => {
-                let mut graph = RoomGraph::new();
-                graph.add_room(rect);
-                graph
-            }
-            BspTree::Split(tree1, tree2) => {
-                let mut rooms1 = tree1.into_room_graph();
-                let rooms2 = tree2.into_room_graph();
-                // now figure out how to bridge the trees
-                rooms1.extend_bridged(rooms2);
-                rooms1
-            }
-        }
-    }
-}
+/// Zones narrower or shorter than this are rejected up front; the generators
+/// assume there is room for at least a building and its walls.
+pub const MIN_ZONE_SIZE: i32 = 10;
 
-struct RoomGraph {
-    pub room_adj: HashMap<Rect, Vec<Rect>>,
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapgenError {
+    /// A zone spec is smaller than [`MIN_ZONE_SIZE`] in some dimension.
+    ZoneTooSmall(String),
+    /// A zone asked for more spawns than it has free tiles.
+    NotEnoughRoom {
+        zone: String,
+        wanted: usize,
+        free: usize,
+    },
+    /// BSP rooms couldn't be laid out.
+    Procgen(ProcgenError),
+    /// A generated layout doesn't connect the left and right edges of its zone.
+    Disconnected,
+    /// A room is too cramped for what has to be placed in it.
+    CrampedRoom,
+    /// Endless mode was given no zones it can generate.
+    NoZoneTemplates,
+    /// Wave function collapse couldn't fill a zone.
+    Wfc(WfcError),
+    /// A zone is placed against or connected to a zone that doesn't exist,
+    /// or isn't placed before it.
+    UnknownZone(String),
+    /// A zone, or the row of trees above or below it, overlaps another zone.
+    ZoneOverlap(String),
+    /// Two connected zones don't share a side.
+    NotAdjacent(String, String),
+    /// A zone or floor names a loot table that doesn't exist.
+    UnknownLootTable(String),
+    /// A zone or floor spawns a mob that isn't in the bestiary.
+    UnknownMob(MobName),
 }
 
-impl RoomGraph {
-    fn len(&self) -> usize {
-        self.room_adj.len()
-    }
-    fn get_adj(&self, rect: Rect) -> Option<&[Rect]> {
-        self.room_adj.get(&rect).map(|v| v.as_slice())
-    }
-    fn choose(&self, rng: &mut impl rand::Rng) -> Option<Rect> {
-        if self.room_adj.is_empty() {
-            return None;
-        }
-        let idx = rng.gen_range(0..self.room_adj.len());
-        self.room_adj.keys().nth(idx).cloned()
-    }
-    fn find_spatially_adjacent(&'_ self, rect: Rect) -> impl Iterator<Item = Rect> + '_ {
-        self.room_adj
-            .keys()
-            .cloned()
-            .filter(move |room| get_connecting_wall(rect, *room).is_some())
-    }
-    fn extend_bridged(&mut self, mut other: RoomGraph) {
-        let mut bridged = false;
-        'loop1: for (room1, ref mut adj1) in &mut self.room_adj {
-            for (room2, ref mut adj2) in &mut other.room_adj {
-                if get_connecting_wall(*room1, *room2).is_some() {
-                    bridged = true;
-                    adj1.push(*room2);
-                    adj2.push(*room1);
-                    break 'loop1;
-                }
-            }
-        }
-        assert!(bridged);
-        self.room_adj.extend(other.room_adj);
-    }
-    fn new() -> Self {
-        Self {
-            room_adj: HashMap::new(),
-        }
-    }
-    fn add_room(&mut self, room: Rect) {
-        self.room_adj.insert(room, vec![]);
-    }
-    fn add_connection(&mut self, room1: Rect, room2: Rect) {
-        assert!(get_connecting_wall(room1, room2).is_some());
-        assert!(self.room_adj.contains_key(&room1));
-        assert!(self.room_adj.contains_key(&room2));
-        self.room_adj.get_mut(&room2).unwrap().push(room1);
-        self.room_adj.get_mut(&room1).unwrap().push(room2);
-    }
-    fn iter(&'_ self) -> impl Iterator<Item = Rect> + '_ {
-        self.room_adj.keys().copied()
-    }
-    fn add_extra_loops(&mut self, num_loops: usize, rng: &mut impl Rng) {
-        for _ in 0..num_loops {
-            let room1 = self.choose(rng).unwrap();
-            let room2 = self.choose(rng).unwrap();
-            if get_connecting_wall(room1, room2).is_some() {
-                self.add_connection(room1, room2);
+impl std::fmt::Display for MapgenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapgenError::ZoneTooSmall(zone) => write!(
+                f,
+                "zone {zone:?} is smaller than {MIN_ZONE_SIZE}x{MIN_ZONE_SIZE}"
+            ),
+            MapgenError::NotEnoughRoom { zone, wanted, free } => write!(
+                f,
+                "zone {zone:?} wants {wanted} spawns but only has {free} free tiles"
+            ),
+            MapgenError::Procgen(e) => write!(f, "{e}"),
+            MapgenError::Disconnected => write!(f, "layout is not connected"),
+            MapgenError::CrampedRoom => write!(f, "room is too cramped"),
+            MapgenError::NoZoneTemplates => write!(f, "no zones to generate"),
+            MapgenError::Wfc(e) => write!(f, "{e}"),
+            MapgenError::UnknownZone(zone) => write!(f, "no zone {zone:?} to place against"),
+            MapgenError::ZoneOverlap(zone) => write!(f, "zone {zone:?} overlaps another zone"),
+            MapgenError::NotAdjacent(a, b) => {
+                write!(f, "zones {a:?} and {b:?} are connected but don't touch")
             }
+            MapgenError::UnknownLootTable(name) => write!(f, "no loot table named {name:?}"),
+            MapgenError::UnknownMob(name) => write!(f, "no mob named {name} in the bestiary"),
         }
     }
 }
 
-// returns (rooms, walls between connected rooms in the bsp tree)
-pub fn gen_bsp_tree(rect: Rect, opts: BspSplitOpts, rng: &mut impl rand::Rng) -> BspTree {
-    assert!(opts.min_width * 2 < opts.max_width);
-    assert!(opts.min_height * 2 < opts.max_height);
-    #[derive(Clone, Copy, Debug)]
-    enum Split {
-        X,
-        Y,
-        None,
-    }
-    let too_wide = (rect.x2 - rect.x1) > opts.max_width;
-    let too_tall = (rect.y2 - rect.y1) > opts.max_height;
-    let split = match (too_wide, too_tall) {
-        (true, true) => *[Split::X, Split::Y].choose(rng).unwrap(),
-        (true, false) => Split::X,
-        (false, true) => Split::Y,
-        _ => Split::None,
-    };
-    match split {
-        Split::X => {
-            let split_x = rng.gen_range(rect.x1 + opts.min_width..(rect.x2 - opts.min_width));
-            let left = Rect::new(rect.x1, split_x - 1, rect.y1, rect.y2);
-            let right = Rect::new(split_x + 1, rect.x2, rect.y1, rect.y2);
-            BspTree::Split(
-                Box::new(gen_bsp_tree(left, opts, rng)),
-                Box::new(gen_bsp_tree(right, opts, rng)),
-            )
-        }
-        Split::Y => {
-            let split_y = rng.gen_range(rect.y1 + opts.min_height..(rect.y2 - opts.min_height));
-            let top = Rect::new(rect.x1, rect.x2, rect.y1, split_y - 1);
-            let bottom = Rect::new(rect.x1, rect.x2, split_y + 1, rect.y2);
-            BspTree::Split(
-                Box::new(gen_bsp_tree(top, opts, rng)),
-                Box::new(gen_bsp_tree(bottom, opts, rng)),
-            )
-        }
-        Split::None => BspTree::Room(rect),
-    }
-}
-
-pub fn carve_line_drunk(
-    tile_map: &mut TileMap<Option<TileKind>>,
-    start: Pos,
-    end: Pos,
-    rng: &mut impl Rng,
-    waviness: f64,
-    tile: Option<TileKind>,
-    bound: Rect,
-) {
-    let mut pos = start;
-    while pos != end {
-        let dir = if rng.gen_bool(waviness) {
-            *rogue_algebra::CARDINALS.choose(rng).unwrap()
-        } else {
-            (end - pos).nearest_cardinal()
-        };
-        if !bound.contains(pos + dir) {
-            continue;
-        }
-        pos += dir;
-        tile_map[pos] = tile;
-    }
-}
-
-pub fn gen_cellular_automata(
-    room: Rect,
-    iterations: usize,
-    noise: f64,
-    rng: &mut impl Rng,
-) -> HashSet<Pos> {
-    let mut state = room
-        .into_iter()
-        .filter(|_| rng.gen_bool(noise))
-        .collect::<HashSet<Pos>>();
-    for _ in 0..iterations {
-        state = room
-            .into_iter()
-            .filter(|pos| {
-                (1..=4).contains(
-                    &rogue_algebra::DIRECTIONS
-                        .iter()
-                        .copied()
-                        .filter(|dir| !state.contains(&(*pos + *dir)))
-                        .count(),
-                )
-            })
-            .collect();
-    }
-    state
-}
-
-pub fn dfs(starts: &[Pos], reachable: impl FnMut(Pos) -> Vec<Pos>) -> impl Iterator<Item = Pos> {
-    Dfs {
-        stack: starts.to_vec(),
-        visited: starts.iter().cloned().collect::<HashSet<_>>(),
-        reachable,
-        to_emit: starts.to_vec(),
-    }
-}
-
-struct Dfs<R: FnMut(Pos) -> Vec<Pos>> {
-    stack: Vec<Pos>,
-    visited: HashSet<Pos>,
-    reachable: R,
-    to_emit: Vec<Pos>,
-}
+impl std::error::Error for MapgenError {}
 
-impl<R: FnMut(Pos) -> Vec<Pos>> Iterator for Dfs<R> {
-    type Item = Pos;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if let Some(p) = self.to_emit.pop() {
-                return Some(p);
-            }
-            if let Some(p) = self.stack.pop() {
-                let mut reachable = (self.reachable)(p);
-                reachable.retain(|p| !self.visited.contains(p));
-                self.visited.extend(reachable.iter());
-                self.stack.extend(reachable.iter());
-                self.to_emit.extend(reachable);
-            } else {
-                return None;
-            }
-        }
+impl From<ProcgenError> for MapgenError {
+    fn from(e: ProcgenError) -> Self {
+        MapgenError::Procgen(e)
     }
 }
 
@@ -295,9 +144,32 @@ pub fn gen_forest_room(
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct MapgenResult {
+    /// Spawns on the ground floor.
     pub spawns: HashMap<IVec2, Vec<Spawn>>,
-    pub zones: Vec<IRect>,
+    pub zones: Vec<Zone>,
+    /// Spawns on the floors above (positive) and below (negative) the ground,
+    /// which share the ground floor's coordinates.
+    pub floors: BTreeMap<i32, HashMap<IVec2, Vec<Spawn>>>,
+}
+
+impl MapgenResult {
+    /// Smallest rectangle covering every spawn on every floor, every zone and
+    /// the player start.
+    pub fn bounds(&self) -> IRect {
+        let mut bounds = IRect::from_corners(PLAYER_START, PLAYER_START);
+        let positions = self
+            .spawns
+            .keys()
+            .chain(self.floors.values().flat_map(|spawns| spawns.keys()))
+            .copied()
+            .chain(self.zones.iter().flat_map(|z| [z.rect.min, z.rect.max]));
+        for pos in positions {
+            bounds = bounds.union_point(pos);
+        }
+        bounds
+    }
 }
 
 pub fn get_random_empty_tile(
@@ -312,18 +184,118 @@ pub fn get_random_empty_tile(
         .cloned()
 }
 
+fn railyard_rules() -> Rules<char> {
+    Rules::from_ascii(RAILYARD_SAMPLE).expect("railyard sample should parse")
+}
+
+/// Aisles (`-`) run the full width of the yard, so pinning a few to the left
+/// edge of `boxes_zone` makes sure the yard can be crossed.
+fn railyard_aisles(boxes_zone: Rect) -> Vec<(Pos, char)> {
+    (boxes_zone.y1 + 2..boxes_zone.y2 - 1)
+        .step_by(RAILYARD_AISLE_SPACING)
+        .map(|y| (Pos::new(boxes_zone.x1, y), '-'))
+        .collect()
+}
+
 pub struct Mapgen {
-    rng: ThreadRng,
+    rng: StdRng,
+    tile_map: TileMap<Option<TileKind>>,
+    mob_spawns: HashMap<Pos, MobName>,
+    item_spawns: HashMap<Pos, ItemKind>,
+    /// Rooms in the room graph of the zone being generated, if it has one.
+    room_count: Option<usize>,
+    prefabs: Vec<Prefab>,
+    /// Names of the unique prefabs stamped in the zone being generated.
+    stamped_unique: HashSet<String>,
+    loot: LootTables,
+    bestiary: Bestiary,
+    difficulty: Difficulty,
+    /// Floors other than the one being generated, swapped in by [`Mapgen::swap_floor`].
+    floors: BTreeMap<i32, FloorPlan>,
+}
+
+/// Tiles and spawns of one floor.
+struct FloorPlan {
     tile_map: TileMap<Option<TileKind>>,
-    mob_spawns: HashMap<Pos, MobKind>,
+    mob_spawns: HashMap<Pos, MobName>,
+    item_spawns: HashMap<Pos, ItemKind>,
+}
+
+impl FloorPlan {
+    fn new() -> Self {
+        FloorPlan {
+            tile_map: TileMap::new(Some(TileKind::Wall)),
+            mob_spawns: HashMap::new(),
+            item_spawns: HashMap::new(),
+        }
+    }
+
+    /// Spawns for every tile, mob and item, emptying the plan's spawn lists.
+    fn take_spawns(&mut self) -> HashMap<IVec2, Vec<Spawn>> {
+        let mut spawns: HashMap<IVec2, Vec<Spawn>> = HashMap::new();
+        for (pos, tile) in self.tile_map.iter() {
+            if let Some(tile) = tile {
+                spawns
+                    .entry(pos.into())
+                    .or_default()
+                    .push(Spawn::Tile(tile));
+            }
+        }
+        add_mob_and_item_spawns(
+            &mut spawns,
+            std::mem::take(&mut self.mob_spawns),
+            std::mem::take(&mut self.item_spawns),
+        );
+        spawns
+    }
+}
+
+fn add_mob_and_item_spawns(
+    spawns: &mut HashMap<IVec2, Vec<Spawn>>,
+    mob_spawns: HashMap<Pos, MobName>,
     item_spawns: HashMap<Pos, ItemKind>,
+) {
+    for (pos, mob_kind) in mob_spawns {
+        spawns
+            .entry(pos.into())
+            .or_default()
+            .push(Spawn::Mob(mob_kind));
+    }
+    for (pos, item_kind) in item_spawns {
+        spawns
+            .entry(pos.into())
+            .or_default()
+            .push(Spawn::Item(item_kind));
+    }
 }
 
 impl Mapgen {
-    pub fn populate(&mut self, rect: Rect, spawns: Vec<(usize, Spawn)>) {
-        let total: usize = spawns.iter().map(|(c, _)| c).sum();
-        let free = rect
-            .into_iter()
+    pub fn new(seed: u64, difficulty: Difficulty, loot: &LootTables, bestiary: &Bestiary) -> Self {
+        Mapgen {
+            rng: StdRng::seed_from_u64(seed),
+            tile_map: TileMap::new(Some(TileKind::Wall)),
+            mob_spawns: HashMap::new(),
+            item_spawns: HashMap::new(),
+            room_count: None,
+            prefabs: crate::prefab::default_prefabs(),
+            stamped_unique: HashSet::new(),
+            loot: loot.clone(),
+            bestiary: bestiary.clone(),
+            difficulty,
+            floors: BTreeMap::new(),
+        }
+    }
+
+    /// Exchanges the floor being generated with `plan`.
+    fn swap_floor(&mut self, plan: &mut FloorPlan) {
+        std::mem::swap(&mut self.tile_map, &mut plan.tile_map);
+        std::mem::swap(&mut self.mob_spawns, &mut plan.mob_spawns);
+        std::mem::swap(&mut self.item_spawns, &mut plan.item_spawns);
+    }
+
+    /// Tiles of `rect` with nothing on them that blocks movement or sight.
+    fn free_tiles(&self, rect: Rect) -> Vec<Pos> {
+        rect.into_iter()
             .filter(|p| {
                 self.tile_map[*p]
                     .filter(|t| t.blocks_movement() || t.blocks_sight())
@@ -331,7 +303,39 @@ impl Mapgen {
                     && !self.mob_spawns.contains_key(p)
                     && !self.item_spawns.contains_key(p)
             })
-            .collect::<Vec<Pos>>();
+            .collect()
+    }
+
+    pub fn populate(
+        &mut self,
+        zone: &str,
+        rect: Rect,
+        spawns: Vec<(usize, Spawn)>,
+    ) -> Result<(), MapgenError> {
+        for (_, spawn) in &spawns {
+            match spawn {
+                Spawn::Mob(name) if self.bestiary.get(name).is_none() => {
+                    return Err(MapgenError::UnknownMob(name.clone()));
+                }
+                _ => {}
+            }
+        }
+        let spawns = spawns
+            .into_iter()
+            .map(|(count, spawn)| match spawn {
+                Spawn::Mob(_) => (self.difficulty.scale_mob_count(count), spawn),
+                _ => (count, spawn),
+            })
+            .collect::<Vec<_>>();
+        let total: usize = spawns.iter().map(|(c, _)| c).sum();
+        let free = self.free_tiles(rect);
+        if free.len() < total {
+            return Err(MapgenError::NotEnoughRoom {
+                zone: zone.to_owned(),
+                wanted: total,
+                free: free.len(),
+            });
+        }
         let mut chosen = free.choose_multiple(&mut self.rng, total);
         for (count, spawn) in spawns.into_iter() {
             for _ in 0..count {
@@ -340,8 +344,8 @@ impl Mapgen {
                     Spawn::Tile(t) => {
                         self.tile_map[*pos] = Some(t);
                     }
-                    Spawn::Mob(m) => {
-                        self.mob_spawns.insert(*pos, m);
+                    Spawn::Mob(ref m) => {
+                        self.mob_spawns.insert(*pos, m.clone());
                     }
                     Spawn::Item(it) => {
                         self.item_spawns.insert(*pos, it);
@@ -349,210 +353,295 @@ impl Mapgen {
                 }
             }
         }
+        Ok(())
     }
 
-    fn dig_rect_cellular_automata(&mut self, rect: Rect, iterations: usize, noise: f64) {
-        loop {
-            let walkable = gen_cellular_automata(rect, 100, 0.8, &mut self.rng);
-            let starts: Vec<Pos> = rect.left_edge().into_iter().collect();
-            let reachable = |p: Pos| {
-                p.adjacent_cardinal()
-                    .iter()
-                    .cloned()
-                    .filter(|p| walkable.contains(p))
-                    .collect::<Vec<Pos>>()
-            };
-            if dfs(&starts, reachable).any(|p| rect.right_edge().contains(p)) {
-                for p in dfs(&starts, reachable) {
-                    self.tile_map[p] = None;
-                }
-                break;
+    /// Rolls the loot table named `name`.
+    fn roll_loot(&mut self, name: &str) -> Result<Vec<ItemKind>, MapgenError> {
+        let table = self
+            .loot
+            .get(name)
+            .map_err(|_| MapgenError::UnknownLootTable(name.to_owned()))?;
+        let items = table.roll(&mut self.rng);
+        Ok(items
+            .into_iter()
+            .map(|item| self.difficulty.scale_loot(item))
+            .collect())
+    }
+
+    /// `population` plus an item for each roll of the loot table, if any.
+    fn with_loot(
+        &mut self,
+        population: &[(usize, Spawn)],
+        loot: Option<&str>,
+    ) -> Result<Vec<(usize, Spawn)>, MapgenError> {
+        let mut spawns = population.to_vec();
+        if let Some(name) = loot {
+            spawns.extend(
+                self.roll_loot(name)?
+                    .into_iter()
+                    .map(|item| (1, Spawn::Item(item))),
+            );
+        }
+        Ok(spawns)
+    }
+
+    fn dig_rect_cellular_automata(
+        &mut self,
+        rect: Rect,
+        iterations: usize,
+        noise: f64,
+    ) -> Result<(), MapgenError> {
+        let walkable = gen_cellular_automata(rect, iterations, noise, &mut self.rng);
+        let starts: Vec<Pos> = rect.left_edge().into_iter().collect();
+        let reachable = |p: Pos| {
+            p.adjacent_cardinal()
+                .iter()
+                .cloned()
+                .filter(|p| walkable.contains(p))
+                .collect::<Vec<Pos>>()
+        };
+        if !dfs(&starts, reachable).any(|p| rect.right_edge().contains(p)) {
+            return Err(MapgenError::Disconnected);
+        }
+        for p in dfs(&starts, reachable) {
+            self.tile_map[p] = None;
+        }
+        Ok(())
+    }
+
+    fn gen_field(&mut self, rect: Rect) {
+        // field is empty and surrounded by trees on 3 sides.
+        for pos in rect.into_iter() {
+            self.tile_map[pos] = if self.rng.gen_bool(0.1) {
+                Some(TileKind::Bush)
+            } else {
+                None
             }
         }
+        self.tile_map
+            .set_rect(rect.top_edge(), Some(TileKind::Tree));
+        self.tile_map
+            .set_rect(rect.left_edge(), Some(TileKind::Tree));
+        self.tile_map
+            .set_rect(rect.bottom_edge(), Some(TileKind::Tree));
     }
-}
 
-pub fn gen_map() -> MapgenResult {
-    let mut rng = rand::thread_rng();
-    let mut tile_map = TileMap::<Option<TileKind>>::new(Some(TileKind::Wall));
+    fn gen_forest(&mut self, rect: Rect) -> Result<(), MapgenError> {
+        self.tile_map
+            .set_rect(rect.expand_y(1), Some(TileKind::Tree));
+        self.dig_rect_cellular_automata(rect, 100, 0.8)
+    }
 
-    let mob_spawns = HashMap::new();
-    let item_spawns = HashMap::new();
-    let mut mapgen = Mapgen {
-        rng,
-        tile_map,
-        mob_spawns,
-        item_spawns,
-    };
+    /// Returns the building, which is where the zone's population goes.
+    fn gen_warehouse(&mut self, zone_rect: Rect) -> Result<Rect, MapgenError> {
+        self.tile_map
+            .set_rect(zone_rect.expand_y(1), Some(TileKind::Tree));
 
-    let start = Pos::new(0, 0);
-    let field_rect = Rect::new_centered(start, 16, 24);
-    let forest_rect = Rect::new_centered(start, 60, 24).shift_to_right_of(field_rect);
-    let warehouse_zone_rect = Rect::new_centered(start, 60, 36).shift_to_right_of(forest_rect);
-    let forest2_rect = Rect::new_centered(start, 60, 44).shift_to_right_of(warehouse_zone_rect);
-    let railyard_rect = Rect::new_centered(start, 60, 44).shift_to_right_of(forest2_rect);
-    let final_rect = Rect::new_centered(start, 20, 20).shift_to_right_of(railyard_rect);
-
-    // field
-    // field is empty and surrounded by trees on 3 sides.
-    for pos in field_rect.into_iter() {
-        mapgen.tile_map[pos] = if mapgen.rng.gen_bool(0.1) {
-            Some(TileKind::Bush)
-        } else {
-            None
-        }
-    }
-    mapgen
-        .tile_map
-        .set_rect(field_rect.top_edge(), Some(TileKind::Tree));
-    mapgen
-        .tile_map
-        .set_rect(field_rect.left_edge(), Some(TileKind::Tree));
-    mapgen
-        .tile_map
-        .set_rect(field_rect.bottom_edge(), Some(TileKind::Tree));
-
-    // forest
-    mapgen
-        .tile_map
-        .set_rect(forest_rect.expand_y(1), Some(TileKind::Tree));
-
-    mapgen.dig_rect_cellular_automata(forest_rect, 100, 0.8);
-    mapgen.populate(
-        forest_rect,
-        vec![
-            (15, Spawn::Mob(MobKind::Zombie)),
-            (10, Spawn::Item(ItemKind::Ammo(GunType::Pistol, 15))),
-        ],
-    );
+        self.tile_map.set_rect(zone_rect, None);
+        // clearing with one big building in it
+        let warehouse_rect = Rect {
+            x1: zone_rect.x1 + 5,
+            x2: zone_rect.x2 - 1,
+            y1: zone_rect.y1,
+            y2: zone_rect.y2,
+        };
+        self.tile_map.set_rect(warehouse_rect, Some(TileKind::Wall));
 
-    // warehouse
-    mapgen
-        .tile_map
-        .set_rect(warehouse_zone_rect.expand_y(1), Some(TileKind::Tree));
-
-    mapgen.tile_map.set_rect(warehouse_zone_rect, None);
-    // clearing with one big building in it
-    let warehouse_rect = Rect {
-        x1: warehouse_zone_rect.x1 + 5,
-        x2: warehouse_zone_rect.x2 - 1,
-        y1: warehouse_zone_rect.y1,
-        y2: warehouse_zone_rect.y2,
-    };
-    mapgen
-        .tile_map
-        .set_rect(warehouse_rect, Some(TileKind::Wall));
-
-    let warehouse_bsp_opts = BspSplitOpts {
-        min_width: 5,
-        min_height: 5,
-        max_width: 11,
-        max_height: 11,
-    };
-    let warehouse_bsp_tree = gen_bsp_tree(
-        warehouse_rect.shrink(1),
-        warehouse_bsp_opts,
-        &mut mapgen.rng,
-    );
-    let mut warehouse_room_graph = warehouse_bsp_tree.into_room_graph();
-    // Carve out rooms, including doors between each two adjacent rooms.
-    for room1 in warehouse_room_graph.iter() {
-        mapgen.tile_map.set_rect(room1, None);
-        // throw some crates in here
-        for p in room1 {
-            if mapgen.rng.gen_bool(0.02) {
-                mapgen.tile_map[p] = Some(TileKind::Crate);
-            }
-        }
-        for room2 in warehouse_room_graph.find_spatially_adjacent(room1) {
-            // avoid double counting
-            if room1.topleft() < room2.topleft() {
-                let adj_wall = get_connecting_wall(room1, room2).unwrap();
-                let door = adj_wall.choose(&mut mapgen.rng);
-                mapgen.tile_map[door] = Some(TileKind::Door);
-            }
-        }
-    }
-    // add doors to the outside
-    for room in warehouse_room_graph.iter() {
-        let exterior_door = if room.x1 == warehouse_rect.x1 + 1 {
-            Some(room.left_edge().choose(&mut mapgen.rng) + rogue_algebra::Offset::new(-1, 0))
-        } else if room.x2 == warehouse_rect.x2 - 1 {
-            Some(room.right_edge().choose(&mut mapgen.rng) + rogue_algebra::Offset::new(1, 0))
-        } else if room.y1 == warehouse_rect.y1 + 1 {
-            Some(room.bottom_edge().choose(&mut mapgen.rng) + rogue_algebra::Offset::new(0, -1))
-        } else if room.y2 == warehouse_rect.y2 - 1 {
-            Some(room.top_edge().choose(&mut mapgen.rng) + rogue_algebra::Offset::new(0, 1))
+        let warehouse_bsp_opts = BspSplitOpts {
+            min_width: 5,
+            min_height: 5,
+            max_width: 11,
+            max_height: 11,
+        };
+        let warehouse_bsp_tree =
+            gen_bsp_tree(warehouse_rect.shrink(1), warehouse_bsp_opts, &mut self.rng)?;
+        let mut warehouse_room_graph = warehouse_bsp_tree.into_room_graph()?;
+        self.room_count = Some(warehouse_room_graph.len());
+        // Carve out rooms, including doors between each two adjacent rooms.
+        for room1 in warehouse_room_graph.iter() {
+            self.tile_map.set_rect(room1, None);
+            if !self.maybe_stamp_prefab(room1, ZoneGenerator::BspBuilding) {
+                // throw some crates in here
+                for p in room1 {
+                    if self.rng.gen_bool(0.02) {
+                        self.tile_map[p] = Some(TileKind::Crate);
+                    }
+                }
+            }
+            for room2 in warehouse_room_graph.find_spatially_adjacent(room1) {
+                // avoid double counting
+                if room1.topleft() < room2.topleft() {
+                    let adj_wall = get_connecting_wall(room1, room2).unwrap();
+                    let door = adj_wall.choose(&mut self.rng);
+                    self.tile_map[door] = Some(TileKind::Door);
+                }
+            }
+        }
+        // add doors to the outside
+        for room in warehouse_room_graph.iter() {
+            let exterior_door = if room.x1 == warehouse_rect.x1 + 1 {
+                Some(room.left_edge().choose(&mut self.rng) + rogue_algebra::Offset::new(-1, 0))
+            } else if room.x2 == warehouse_rect.x2 - 1 {
+                Some(room.right_edge().choose(&mut self.rng) + rogue_algebra::Offset::new(1, 0))
+            } else if room.y1 == warehouse_rect.y1 + 1 {
+                Some(room.bottom_edge().choose(&mut self.rng) + rogue_algebra::Offset::new(0, -1))
+            } else if room.y2 == warehouse_rect.y2 - 1 {
+                Some(room.top_edge().choose(&mut self.rng) + rogue_algebra::Offset::new(0, 1))
+            } else {
+                None
+            };
+            if let Some(door) = exterior_door {
+                self.tile_map[door] = Some(TileKind::Door);
+            }
+        }
+        // a sculpture gallery prefab already has the sculpture in it
+        let gallery = warehouse_room_graph.iter().find(|room| {
+            room.into_iter()
+                .any(|p| self.mob_spawns.get(&p) == Some(&MobName::SCULPTURE))
+        });
+        let sculpture_room = match gallery {
+            Some(room) => room,
+            None => warehouse_room_graph
+                .choose(&mut self.rng)
+                .ok_or(MapgenError::CrampedRoom)?,
+        };
+        self.place_sculpture(sculpture_room)?;
+        Ok(warehouse_rect)
+    }
+
+    /// Town blocks separated by roads, with a small house on each block big
+    /// enough for one. Every block is bordered by road, and the roads run
+    /// into the zone's left and right edges.
+    fn gen_town(&mut self, rect: Rect) -> Result<(), MapgenError> {
+        self.tile_map
+            .set_rect(rect.expand_y(1), Some(TileKind::Tree));
+        self.tile_map.set_rect(rect, None);
+        let block_opts = BspSplitOpts {
+            min_width: 7,
+            min_height: 7,
+            max_width: 16,
+            max_height: 16,
+        };
+        // Shrinking the blocks leaves three-tile roads between them and
+        // two-tile roads around the edge.
+        let blocks = gen_bsp_tree(rect.shrink(1), block_opts, &mut self.rng)?.into_room_graph()?;
+        let mut room_count = 0;
+        for block in blocks.iter().map(|block| block.shrink(1)) {
+            if block.width() < 5 || block.height() < 5 || self.rng.gen_bool(0.15) {
+                // an overgrown empty lot
+                for p in block {
+                    if self.rng.gen_bool(0.3) {
+                        self.tile_map[p] = Some(TileKind::Bush);
+                    }
+                }
+                continue;
+            }
+            // front doors face the middle of the zone
+            let faces_north = block.center().y < rect.center().y;
+            room_count += self.gen_house(block, faces_north)?;
+        }
+        self.room_count = Some(room_count);
+        Ok(())
+    }
+
+    /// Fills `house` with a walled BSP building with crates and loot inside
+    /// and its front door on its north or south wall. Returns its room count.
+    fn gen_house(&mut self, house: Rect, faces_north: bool) -> Result<usize, MapgenError> {
+        self.tile_map.set_rect(house, Some(TileKind::Wall));
+        let opts = BspSplitOpts {
+            min_width: 3,
+            min_height: 3,
+            max_width: 7,
+            max_height: 7,
+        };
+        let rooms = gen_bsp_tree(house.shrink(1), opts, &mut self.rng)?.into_room_graph()?;
+        for room in rooms.iter() {
+            self.tile_map.set_rect(room, None);
+            if !self.maybe_stamp_prefab(room, ZoneGenerator::Town) {
+                for p in room {
+                    if self.rng.gen_bool(0.08) {
+                        self.tile_map[p] = Some(TileKind::Crate);
+                    }
+                }
+            }
+            for adj in rooms.get_adj(room).unwrap() {
+                if room.topleft() < adj.topleft() {
+                    let wall = get_connecting_wall(room, *adj).unwrap();
+                    self.tile_map[wall.choose(&mut self.rng)] = Some(TileKind::Door);
+                }
+            }
+        }
+
+        let front_rooms = rooms
+            .iter()
+            .filter(|room| {
+                if faces_north {
+                    room.y2 == house.y2 - 1
+                } else {
+                    room.y1 == house.y1 + 1
+                }
+            })
+            .collect::<Vec<_>>();
+        let front_room = *front_rooms
+            .choose(&mut self.rng)
+            .ok_or(MapgenError::CrampedRoom)?;
+        let front_door = if faces_north {
+            front_room.top_edge().choose(&mut self.rng) + Offset::new(0, 1)
         } else {
-            None
+            front_room.bottom_edge().choose(&mut self.rng) + Offset::new(0, -1)
         };
-        if let Some(door) = exterior_door {
-            mapgen.tile_map[door] = Some(TileKind::Door);
+        self.tile_map[front_door] = Some(TileKind::Door);
+
+        let loot_room = rooms
+            .choose(&mut self.rng)
+            .ok_or(MapgenError::CrampedRoom)?;
+        let free = self.free_tiles(loot_room);
+        let loot = self.roll_loot(HOUSE_LOOT)?;
+        for (pos, item) in free.choose_multiple(&mut self.rng, loot.len()).zip(loot) {
+            self.item_spawns.insert(*pos, item);
         }
+        Ok(rooms.len())
     }
-    let sculpture_room = warehouse_room_graph.choose(&mut mapgen.rng).unwrap();
-    let sculpture_room_free_spots = sculpture_room
-        .into_iter()
-        .filter(|p| {
-            mapgen.tile_map[*p]
-                .filter(|t| t.blocks_movement())
-                .is_none()
-        })
-        .collect::<Vec<_>>();
-    let [sculpture_pos, shotgun_pos] = sculpture_room_free_spots
-        .choose_multiple(&mut mapgen.rng, 2)
-        .collect::<Vec<_>>()
-        .try_into()
-        .unwrap();
-    mapgen.mob_spawns.insert(*sculpture_pos, MobKind::Sculpture);
-    mapgen.item_spawns.insert(
-        *shotgun_pos,
-        ItemKind::Gun(GunType::Shotgun, GunType::Shotgun.get_info().max_load),
-    );
-    mapgen.populate(
-        warehouse_rect,
-        vec![
-            (20, Spawn::Mob(MobKind::Hider)),
-            (7, Spawn::Item(ItemKind::Ammo(GunType::Pistol, 15))),
-            (7, Spawn::Item(ItemKind::Ammo(GunType::Shotgun, 4))),
-            (1, Spawn::Item(ItemKind::Gun(GunType::Shotgun, 4))),
-        ],
-    );
 
-    // another forest, but with spiders and ghosts
-    mapgen
-        .tile_map
-        .set_rect(forest2_rect.expand_y(1), Some(TileKind::Tree));
-    mapgen.dig_rect_cellular_automata(forest2_rect, 100, 0.8);
-    mapgen.populate(
-        forest2_rect,
-        vec![
-            (75, Spawn::Tile(TileKind::Bush)),
-            (30, Spawn::Mob(MobKind::Hider)),
-            (30, Spawn::Mob(MobKind::Ghost)),
-            (1, Spawn::Mob(MobKind::Sculpture)),
-            (6, Spawn::Item(ItemKind::Ammo(GunType::Pistol, 15))),
-            (6, Spawn::Item(ItemKind::Ammo(GunType::Shotgun, 15))),
-        ],
-    );
+    /// Puts the sculpture and the shotgun it guards on two free tiles of
+    /// `room`. If a prefab already put a sculpture in the room, only the
+    /// shotgun is added.
+    fn place_sculpture(&mut self, room: Rect) -> Result<(), MapgenError> {
+        let has_sculpture = room
+            .into_iter()
+            .any(|p| self.mob_spawns.get(&p) == Some(&MobName::SCULPTURE));
+        let free_spots = room
+            .into_iter()
+            .filter(|p| {
+                self.tile_map[*p].filter(|t| t.blocks_movement()).is_none()
+                    && !self.mob_spawns.contains_key(p)
+            })
+            .collect::<Vec<_>>();
+        let mut spots = free_spots.choose_multiple(&mut self.rng, 2);
+        let shotgun_pos = *spots.next().ok_or(MapgenError::CrampedRoom)?;
+        if !has_sculpture {
+            let sculpture_pos = *spots.next().ok_or(MapgenError::CrampedRoom)?;
+            self.mob_spawns.insert(sculpture_pos, MobName::SCULPTURE);
+        }
+        self.item_spawns.insert(
+            shotgun_pos,
+            ItemKind::Gun(GunType::Shotgun, GunType::Shotgun.get_info().max_load),
+        );
+        Ok(())
+    }
 
-    // Railyard. Wide open but with large shipping containers obscuring vision.
-    let mut boxes_zone = railyard_rect;
-    boxes_zone.x1 += 1;
-    boxes_zone.x2 -= 1;
-    mapgen
-        .tile_map
-        .set_rect(boxes_zone, Some(TileKind::ShippingContainer));
-    loop {
+    /// Wide open but with large shipping containers obscuring vision.
+    fn gen_railyard(&mut self, railyard_rect: Rect) -> Result<(), MapgenError> {
+        let mut boxes_zone = railyard_rect;
+        boxes_zone.x1 += 1;
+        boxes_zone.x2 -= 1;
+        let layout = railyard_rules()
+            .generate(boxes_zone, &railyard_aisles(boxes_zone), &mut self.rng)
+            .map_err(MapgenError::Wfc)?;
         let mut walkable = railyard_rect.into_iter().collect::<HashSet<_>>();
-        for _ in 0..80 {
-            let center = boxes_zone.choose(&mut mapgen.rng);
-            let width = mapgen.rng.gen_range(1..=8);
-            let height = mapgen.rng.gen_range(1..=8);
-            let box_rect = Rect::new_centered(center, width, height)
-                .intersect(&boxes_zone)
-                .unwrap();
-            for p in box_rect {
+        for (p, c) in layout {
+            if matches!(c, '<' | '=' | '>') {
                 walkable.remove(&p);
             }
         }
@@ -566,105 +655,1555 @@ pub fn gen_map() -> MapgenResult {
                 .collect::<Vec<Pos>>()
         };
 
-        // flood fill to verify connectivity
-        walkable = walkable
-            .union(&dfs(&starts, reachable).collect::<HashSet<_>>())
-            .cloned()
-            .collect();
-        if boxes_zone
+        // flood fill to verify connectivity; pockets stay filled in
+        let walkable = dfs(&starts, reachable).collect::<HashSet<_>>();
+        if !boxes_zone
             .right_edge()
             .into_iter()
             .any(|p| walkable.contains(&p))
         {
-            for p in walkable {
-                mapgen.tile_map[p] = None;
-            }
-            break;
-        }
-    }
-    mapgen.populate(
-        railyard_rect,
-        vec![
-            (11, Spawn::Mob(MobKind::Ghost)),
-            (11, Spawn::Mob(MobKind::KoolAidMan)),
-            (11, Spawn::Mob(MobKind::Zombie)),
-            (7, Spawn::Item(ItemKind::Ammo(GunType::Pistol, 15))),
-            (7, Spawn::Item(ItemKind::Ammo(GunType::Shotgun, 2))),
-            (1, Spawn::Item(ItemKind::Gun(GunType::Shotgun, 2))),
-        ],
-    );
+            return Err(MapgenError::Disconnected);
+        }
+        self.tile_map
+            .set_rect(boxes_zone, Some(TileKind::ShippingContainer));
+        for p in walkable {
+            self.tile_map[p] = None;
+        }
+        Ok(())
+    }
 
-    // final zone: boss room
-    // mapgen.tile_map.set_rect(final_rect, None);
-    let bsp = gen_bsp_tree(
-        final_rect,
-        BspSplitOpts {
-            max_width: 8,
-            max_height: 8,
-            min_width: 3,
-            min_height: 3,
-        },
-        &mut mapgen.rng,
-    );
-    // normal bsp
-    let room_graph = bsp.into_room_graph();
-    for room in room_graph.iter() {
-        mapgen.tile_map.set_rect(room, None);
-        for adj in room_graph.get_adj(room).unwrap() {
-            if room.topleft() < adj.topleft() {
-                let wall = get_connecting_wall(room, *adj).unwrap();
-                mapgen.tile_map[wall.choose(&mut mapgen.rng)] = Some(TileKind::Door);
+    fn gen_boss_arena(&mut self, final_rect: Rect) -> Result<(), MapgenError> {
+        let bsp = gen_bsp_tree(
+            final_rect,
+            BspSplitOpts {
+                max_width: 8,
+                max_height: 8,
+                min_width: 3,
+                min_height: 3,
+            },
+            &mut self.rng,
+        )?;
+        // normal bsp
+        let room_graph = bsp.into_room_graph()?;
+        self.room_count = Some(room_graph.len());
+        for room in room_graph.iter() {
+            self.tile_map.set_rect(room, None);
+            self.maybe_stamp_prefab(room, ZoneGenerator::BossArena);
+            for adj in room_graph.get_adj(room).unwrap() {
+                if room.topleft() < adj.topleft() {
+                    let wall = get_connecting_wall(room, *adj).unwrap();
+                    self.tile_map[wall.choose(&mut self.rng)] = Some(TileKind::Door);
+                }
+            }
+            if room.x1 == final_rect.x1 + 1 {
+                let left_door = room.left_edge().choose(&mut self.rng) + Offset::new(-1, 0);
+                self.tile_map[left_door] = Some(TileKind::Door);
             }
         }
-        if room.x1 == final_rect.x1 + 1 {
-            let left_door = room.left_edge().choose(&mut mapgen.rng) + Offset::new(-1, 0);
-            mapgen.tile_map[left_door] = Some(TileKind::Door);
+        // ruin it a bit
+        for pos in final_rect.into_iter() {
+            if self.rng.gen_bool(0.2) {
+                self.tile_map[pos] = None;
+            }
         }
+        // clear out a room in the center
+        let center_rect = Rect::new_centered(final_rect.center(), 8, 8);
+        self.tile_map.set_rect(center_rect, None);
+        self.mob_spawns
+            .insert(center_rect.center(), MobName::EYEBALL);
+        Ok(())
     }
-    // ruin it a bit
-    for pos in final_rect.into_iter() {
-        if mapgen.rng.gen_bool(0.2) {
-            mapgen.tile_map[pos] = None;
+
+    /// With [`PREFAB_CHANCE`], stamps a random prefab for `generator` that fits
+    /// in `room` with a ring of floor around it, so doors in the room's walls
+    /// stay connected. Returns whether a prefab was stamped.
+    fn maybe_stamp_prefab(&mut self, room: Rect, generator: ZoneGenerator) -> bool {
+        if !self.rng.gen_bool(PREFAB_CHANCE) {
+            return false;
+        }
+        let fitting = self
+            .prefabs
+            .iter()
+            .filter(|prefab| {
+                prefab.generators.contains(&generator)
+                    && !self.stamped_unique.contains(&prefab.name)
+                    && prefab.width() <= room.width() - 2
+                    && prefab.height() <= room.height() - 2
+            })
+            .collect::<Vec<_>>();
+        let Some(prefab) = fitting.choose(&mut self.rng).map(|p| (*p).clone()) else {
+            return false;
+        };
+        if prefab.unique {
+            self.stamped_unique.insert(prefab.name.clone());
+        }
+        let topleft = Pos::new(
+            self.rng.gen_range(room.x1 + 1..=room.x2 - prefab.width()),
+            self.rng.gen_range(room.y1 + prefab.height()..=room.y2 - 1),
+        );
+        let cells = prefab.cells().expect("prefabs are checked when parsed");
+        for (offset, spawns) in cells {
+            let pos = topleft + offset;
+            self.tile_map[pos] = None;
+            for spawn in spawns {
+                match spawn {
+                    Spawn::Tile(t) => self.tile_map[pos] = Some(t),
+                    Spawn::Mob(m) => {
+                        self.mob_spawns.insert(pos, m);
+                    }
+                    Spawn::Item(it) => {
+                        self.item_spawns.insert(pos, it);
+                    }
+                }
+            }
         }
+        true
     }
-    // clear out a room in the center
-    let center_rect = Rect::new_centered(final_rect.center(), 8, 8);
-    mapgen.tile_map.set_rect(center_rect, None);
-    mapgen
-        .mob_spawns
-        .insert(center_rect.center(), MobKind::Eyeball);
-    mapgen.populate(final_rect, vec![(10, Spawn::Mob(MobKind::Ghost))]);
 
-    let mut spawns: HashMap<IVec2, Vec<Spawn>> = HashMap::new();
-    for (pos, tile) in mapgen.tile_map.iter() {
-        if let Some(tile) = tile {
-            spawns
-                .entry(pos.into())
-                .or_default()
-                .push(Spawn::Tile(tile));
+    /// Open ground bordered by trees, used when a zone's generator keeps
+    /// failing. Still places the zone's landmark so the run can be finished.
+    fn gen_fallback(&mut self, rect: Rect, generator: ZoneGenerator) {
+        self.tile_map
+            .set_rect(rect.expand_y(1), Some(TileKind::Tree));
+        self.tile_map.set_rect(rect, None);
+        match generator {
+            ZoneGenerator::BspBuilding => {
+                // an open zone always has room for two spawns
+                self.place_sculpture(rect).unwrap();
+            }
+            ZoneGenerator::BossArena => {
+                self.mob_spawns.insert(rect.center(), MobName::EYEBALL);
+            }
+            _ => {}
         }
     }
-    for (pos, mob_kind) in mapgen.mob_spawns.into_iter() {
-        spawns
-            .entry(pos.into())
-            .or_default()
-            .push(Spawn::Mob(mob_kind));
+
+    /// Resets `rect` to solid wall, forgetting anything spawned in it.
+    fn clear_zone(&mut self, rect: Rect) {
+        self.room_count = None;
+        self.stamped_unique.clear();
+        self.tile_map.set_rect(rect, Some(TileKind::Wall));
+        self.mob_spawns.retain(|p, _| !rect.contains(*p));
+        self.item_spawns.retain(|p, _| !rect.contains(*p));
     }
-    for (pos, item_kind) in mapgen.item_spawns.into_iter() {
-        spawns
-            .entry(pos.into())
-            .or_default()
-            .push(Spawn::Item(item_kind));
+
+    /// Lays out the zone in `rect`, returning the part of it the zone's
+    /// population is scattered over.
+    fn gen_layout(&mut self, rect: Rect, generator: ZoneGenerator) -> Result<Rect, MapgenError> {
+        match generator {
+            ZoneGenerator::Field => self.gen_field(rect),
+            ZoneGenerator::CellularAutomata => self.gen_forest(rect)?,
+            ZoneGenerator::BspBuilding => return self.gen_warehouse(rect),
+            ZoneGenerator::ContainerYard => self.gen_railyard(rect)?,
+            ZoneGenerator::BossArena => self.gen_boss_arena(rect)?,
+            ZoneGenerator::Town => self.gen_town(rect)?,
+        }
+        Ok(rect)
+    }
+
+    fn gen_zone(&mut self, rect: Rect, spec: &ZoneSpec) -> Result<(), MapgenError> {
+        let mut layout = Ok(rect);
+        for _ in 0..MAX_LAYOUT_ATTEMPTS {
+            self.clear_zone(rect);
+            layout = self.gen_layout(rect, spec.generator);
+            if layout.is_ok() {
+                break;
+            }
+        }
+        let populated_rect = layout.unwrap_or_else(|e| {
+            warn!("zone {:?}: {e}; falling back to an open layout", spec.name);
+            self.clear_zone(rect);
+            self.gen_fallback(rect, spec.generator);
+            rect
+        });
+        let population = self.with_loot(&spec.population, spec.loot.as_deref())?;
+        self.populate(&spec.name, populated_rect, population)?;
+        for floor in &spec.floors {
+            self.gen_floor(rect, spec, floor)?;
+        }
+        Ok(())
     }
-    MapgenResult {
-        spawns,
-        zones: vec![
-            field_rect.into(),
-            forest_rect.into(),
-            warehouse_zone_rect.into(),
-            forest2_rect.into(),
-            railyard_rect.into(),
-            final_rect.into(),
-        ],
+
+    /// A patrol loop for each mob spawning in `rect` that patrols, from where
+    /// it spawns through tiles of the biggest open area of the zone.
+    fn gen_patrols(&mut self, rect: Rect) -> Vec<Vec<IVec2>> {
+        let mut patrollers = self
+            .mob_spawns
+            .iter()
+            .filter(|&(pos, name)| {
+                rect.contains(*pos) && self.bestiary.get(name).is_some_and(MobDef::patrols)
+            })
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        if patrollers.is_empty() {
+            return Vec::new();
+        }
+        patrollers.sort_by_key(|p| (p.x, p.y));
+        let mut open = self.largest_component(rect).into_iter().collect::<Vec<_>>();
+        open.sort_by_key(|p| (p.x, p.y));
+        patrollers
+            .into_iter()
+            .map(|start| {
+                std::iter::once(start)
+                    .chain(
+                        open.choose_multiple(&mut self.rng, PATROL_WAYPOINTS - 1)
+                            .copied(),
+                    )
+                    .map(IVec2::from)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The biggest set of tiles in `rect` the player can walk between
+    /// without leaving it.
+    fn largest_component(&self, rect: Rect) -> HashSet<Pos> {
+        let walkable = |pos: Pos| {
+            rect.contains(pos) && !self.tile_map[pos].is_some_and(|tile| tile.blocks_movement())
+        };
+        let reachable = |pos: Pos| {
+            pos.adjacent_cardinal()
+                .into_iter()
+                .filter(|p| walkable(*p))
+                .collect::<Vec<_>>()
+        };
+        let mut seen = HashSet::new();
+        let mut largest = HashSet::new();
+        for pos in rect {
+            if walkable(pos) && !seen.contains(&pos) {
+                let component = dfs(&[pos], reachable).collect::<HashSet<_>>();
+                seen.extend(component.iter().copied());
+                if component.len() > largest.len() {
+                    largest = component;
+                }
+            }
+        }
+        largest
+    }
+
+    /// Clears the shortest straight passage between the open parts of two
+    /// neighbouring zones, if they aren't already joined along their side.
+    fn connect_zones(&mut self, a: Rect, b: Rect) -> Result<(), MapgenError> {
+        let (side, dir) = facing_side(a, b).expect("linked zones share a side");
+        let (open_a, open_b) = (self.largest_component(a), self.largest_component(b));
+        let passage = side
+            .into_iter()
+            .filter_map(|start| {
+                let mut from = start;
+                while !open_a.contains(&from) {
+                    from = from - dir;
+                    if !a.contains(from) {
+                        return None;
+                    }
+                }
+                let mut to = start + dir;
+                while !b.contains(to) {
+                    to += dir;
+                }
+                while !open_b.contains(&to) {
+                    to += dir;
+                    if !b.contains(to) {
+                        return None;
+                    }
+                }
+                Some((from, to))
+            })
+            .min_by_key(|(from, to)| (*to - *from).mhn_dist());
+        let (from, to) = passage.ok_or(MapgenError::Disconnected)?;
+        let mut pos = from + dir;
+        while pos != to {
+            if self.tile_map[pos].is_some_and(|tile| tile.blocks_movement()) {
+                self.tile_map[pos] = None;
+            }
+            pos += dir;
+        }
+        Ok(())
+    }
+
+    /// Lays out a floor of BSP rooms, filling `rect`.
+    fn gen_floor_layout(&mut self, rect: Rect) -> Result<(), MapgenError> {
+        let opts = BspSplitOpts {
+            min_width: 4,
+            min_height: 4,
+            max_width: 10,
+            max_height: 10,
+        };
+        let room_graph = gen_bsp_tree(rect.shrink(1), opts, &mut self.rng)?.into_room_graph()?;
+        for room in room_graph.iter() {
+            self.tile_map.set_rect(room, None);
+            for p in room {
+                if self.rng.gen_bool(0.03) {
+                    self.tile_map[p] = Some(TileKind::Crate);
+                }
+            }
+            for adj in room_graph.get_adj(room).unwrap() {
+                if room.topleft() < adj.topleft() {
+                    let wall = get_connecting_wall(room, *adj).unwrap();
+                    self.tile_map[wall.choose(&mut self.rng)] = Some(TileKind::Door);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates `floor` of the zone in `rect` and puts stairs to it on a
+    /// tile that is free on both it and the ground floor.
+    fn gen_floor(
+        &mut self,
+        rect: Rect,
+        zone: &ZoneSpec,
+        floor: &FloorSpec,
+    ) -> Result<(), MapgenError> {
+        let name = format!("{} floor {}", zone.name, floor.floor);
+        let mut plan = self
+            .floors
+            .remove(&floor.floor)
+            .unwrap_or_else(FloorPlan::new);
+        self.swap_floor(&mut plan);
+        let mut layout = Ok(());
+        for _ in 0..MAX_LAYOUT_ATTEMPTS {
+            self.tile_map.set_rect(rect, Some(TileKind::Wall));
+            layout = self.gen_floor_layout(rect);
+            if layout.is_ok() {
+                break;
+            }
+        }
+        if let Err(e) = layout {
+            warn!("{name}: {e}; falling back to one big room");
+            self.tile_map.set_rect(rect.shrink(1), None);
+        }
+        let populated = self
+            .with_loot(&floor.population, floor.loot.as_deref())
+            .and_then(|population| self.populate(&name, rect, population));
+        let floor_free = self.free_tiles(rect).into_iter().collect::<HashSet<_>>();
+        self.swap_floor(&mut plan);
+        populated?;
+
+        let candidates = self
+            .free_tiles(rect)
+            .into_iter()
+            .filter(|p| floor_free.contains(p))
+            .collect::<Vec<_>>();
+        let stairs_pos = *candidates
+            .choose(&mut self.rng)
+            .ok_or(MapgenError::NotEnoughRoom {
+                zone: name,
+                wanted: 1,
+                free: 0,
+            })?;
+        let (there, here) = stairs(floor.floor);
+        self.tile_map[stairs_pos] = Some(here);
+        plan.tile_map[stairs_pos] = Some(there);
+        self.floors.insert(floor.floor, plan);
+        Ok(())
+    }
+
+    /// Spawns for every floor other than the ground, emptying them.
+    fn take_floor_spawns(&mut self) -> BTreeMap<i32, HashMap<IVec2, Vec<Spawn>>> {
+        std::mem::take(&mut self.floors)
+            .into_iter()
+            .map(|(floor, mut plan)| (floor, plan.take_spawns()))
+            .collect()
+    }
+}
+
+/// How the tiles of a zone are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ZoneGenerator {
+    /// Open field with scattered bushes, bordered by trees.
+    Field,
+    /// Forest dug out with cellular automata.
+    CellularAutomata,
+    /// Clearing with a BSP-partitioned building holding the sculpture and a shotgun.
+    BspBuilding,
+    /// Railyard of shipping containers.
+    ContainerYard,
+    /// Ruined BSP building with the Eyeball in the middle.
+    BossArena,
+    /// Abandoned town of small BSP houses along a grid of roads.
+    Town,
+}
+
+/// Where a zone goes, relative to a zone listed before it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Placement {
+    /// Right of the zone listed just before it.
+    #[default]
+    Next,
+    /// Right of the named zone.
+    East(String),
+    /// Above the named zone, with a row of trees between them.
+    North(String),
+    /// Below the named zone, with a row of trees between them.
+    South(String),
+}
+
+/// Describes one zone of the map. Each zone is placed against, and connected
+/// to, a zone before it; by default the one just before it, to its left.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ZoneSpec {
+    pub name: String,
+    pub generator: ZoneGenerator,
+    pub width: i32,
+    pub height: i32,
+    /// Spawns scattered over free tiles once the zone is generated.
+    #[serde(default)]
+    pub population: Vec<(usize, Spawn)>,
+    /// Loot table rolled for items scattered along with the population.
+    #[serde(default)]
+    pub loot: Option<String>,
+    pub fog: f32,
+    /// Music layer that plays while the player is in the zone.
+    #[serde(default)]
+    pub music: MusicLayer,
+    /// Floors above or below the zone, each reached by stairs from the ground.
+    #[serde(default)]
+    pub floors: Vec<FloorSpec>,
+    #[serde(default)]
+    pub placement: Placement,
+    /// How far the zone's center is moved along the side it's placed
+    /// against: up for `Next` and `East`, right for `North` and `South`.
+    #[serde(default)]
+    pub offset: i32,
+    /// Zones besides the one it's placed against that get a passage to this
+    /// one. They have to share a side with it.
+    #[serde(default)]
+    pub connects: Vec<String>,
+    /// Lines of the intro shown one after another while the player walks
+    /// around the zone.
+    #[serde(default)]
+    pub intro: Vec<String>,
+    /// Whether the first shot fired in the zone brings on an ambush.
+    #[serde(default)]
+    pub ambush: bool,
+}
+
+/// Another floor of a zone: BSP rooms filling the zone's rectangle.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FloorSpec {
+    /// Which floor: 1 for the one above the ground, -1 for a basement.
+    pub floor: i32,
+    /// Spawns scattered over free tiles of the floor.
+    #[serde(default)]
+    pub population: Vec<(usize, Spawn)>,
+    /// Loot table rolled for items scattered along with the population.
+    #[serde(default)]
+    pub loot: Option<String>,
+}
+
+/// Zone list shipped with the game, used when `assets/zones.ron` can't be read.
+pub const DEFAULT_ZONE_SPECS: &str = include_str!("../assets/zones.ron");
+
+pub fn parse_zone_specs(ron: &str) -> Result<Vec<ZoneSpec>, ron::error::SpannedError> {
+    ron::from_str(ron)
+}
+
+pub fn default_zone_specs() -> Vec<ZoneSpec> {
+    parse_zone_specs(DEFAULT_ZONE_SPECS).expect("default zone specs should parse")
+}
+
+/// A generated zone: where it ended up and the spec it was generated from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub rect: IRect,
+    pub spec: ZoneSpec,
+    /// Patrol loops for the zone's patrolling mobs, one each, starting where
+    /// it spawns.
+    pub patrols: Vec<Vec<IVec2>>,
+}
+
+/// The zone the boss is fought in: the boss arena, or failing that the last
+/// zone. The lever appears in its center if the boss vanishes unseen.
+pub fn boss_zone(zones: &[Zone]) -> Option<&Zone> {
+    zones
+        .iter()
+        .find(|zone| zone.spec.generator == ZoneGenerator::BossArena)
+        .or(zones.last())
+}
+
+/// Where each zone goes, following their placements. No zone may overlap
+/// another, or the rows of trees above and below it.
+fn layout_zones(zone_specs: &[ZoneSpec]) -> Result<Vec<Rect>, MapgenError> {
+    let mut rects: Vec<Rect> = Vec::new();
+    for (i, spec) in zone_specs.iter().enumerate() {
+        let anchor = |name: &str| {
+            zone_specs[..i]
+                .iter()
+                .position(|other| other.name == name)
+                .map(|j| rects[j])
+                .ok_or_else(|| MapgenError::UnknownZone(name.to_owned()))
+        };
+        let centered = |x: i32, y: i32| Rect::new_centered(Pos::new(x, y), spec.width, spec.height);
+        let east_of = |anchor: Rect| {
+            let rect = centered(0, anchor.center().y + spec.offset);
+            rect + Offset::new(anchor.x2 + 1 - rect.x1, 0)
+        };
+        let rect = match &spec.placement {
+            Placement::Next => match rects.last() {
+                Some(prev) => east_of(*prev),
+                None => centered(0, spec.offset),
+            },
+            Placement::East(name) => east_of(anchor(name)?),
+            Placement::North(name) => {
+                let anchor = anchor(name)?;
+                let rect = centered(anchor.center().x + spec.offset, 0);
+                rect + Offset::new(0, anchor.y2 + 2 - rect.y1)
+            }
+            Placement::South(name) => {
+                let anchor = anchor(name)?;
+                let rect = centered(anchor.center().x + spec.offset, 0);
+                rect + Offset::new(0, anchor.y1 - 2 - rect.y2)
+            }
+        };
+        if rects
+            .iter()
+            .any(|other| rect.expand_y(1).intersects(other) || other.expand_y(1).intersects(&rect))
+        {
+            return Err(MapgenError::ZoneOverlap(spec.name.clone()));
+        }
+        rects.push(rect);
+    }
+    Ok(rects)
+}
+
+/// Pairs of zones, by index, that get a passage between them: each zone
+/// and the one it's placed against, plus the ones it connects to.
+fn zone_links(zone_specs: &[ZoneSpec], rects: &[Rect]) -> Result<Vec<(usize, usize)>, MapgenError> {
+    let index = |name: &str| {
+        zone_specs
+            .iter()
+            .position(|spec| spec.name == name)
+            .ok_or_else(|| MapgenError::UnknownZone(name.to_owned()))
+    };
+    let mut links = Vec::new();
+    for (i, spec) in zone_specs.iter().enumerate() {
+        match &spec.placement {
+            Placement::Next if i > 0 => links.push((i - 1, i)),
+            Placement::Next => {}
+            Placement::East(name) | Placement::North(name) | Placement::South(name) => {
+                links.push((index(name)?, i));
+            }
+        }
+        for name in &spec.connects {
+            links.push((index(name)?, i));
+        }
+    }
+    for &(a, b) in &links {
+        if facing_side(rects[a], rects[b]).is_none() {
+            return Err(MapgenError::NotAdjacent(
+                zone_specs[a].name.clone(),
+                zone_specs[b].name.clone(),
+            ));
+        }
+    }
+    Ok(links)
+}
+
+/// If `b` is next to `a`, or one tile away, the tiles along the side of `a`
+/// facing `b` and the direction from `a` to `b`.
+fn facing_side(a: Rect, b: Rect) -> Option<(Vec<Pos>, Offset)> {
+    let sides = [
+        (b.x1 - a.x2, a.right_edge(), Offset::new(1, 0)),
+        (a.x1 - b.x2, a.left_edge(), Offset::new(-1, 0)),
+        (b.y1 - a.y2, a.top_edge(), Offset::new(0, 1)),
+        (a.y1 - b.y2, a.bottom_edge(), Offset::new(0, -1)),
+    ];
+    sides.into_iter().find_map(|(gap, side, dir)| {
+        if !(1..=2).contains(&gap) {
+            return None;
+        }
+        // slide `b` onto the side to find where they overlap
+        let b = b + Offset::new(-dir.x * gap, -dir.y * gap);
+        side.intersect(&b)
+            .map(|overlap| (overlap.into_iter().collect(), dir))
+    })
+}
+
+/// Generates a map from `zone_specs`. Zone generators that fail are rerolled a
+/// limited number of times and then replaced with open ground, and maps whose
+/// linked zones can't be joined are rerolled and then laid out as a chain of
+/// zones, left to right; errors are only returned for specs that can't be
+/// satisfied at all.
+pub fn gen_map(seed: u64, zone_specs: &[ZoneSpec]) -> Result<MapgenResult, MapgenError> {
+    gen_map_on(
+        seed,
+        zone_specs,
+        Difficulty::Normal,
+        &crate::loot::default_loot_tables(),
+        &crate::bestiary::default_bestiary(),
+    )
+}
+
+/// Like [`gen_map`], but with mob counts and ammo scaled for `difficulty`,
+/// loot rolled from `loot` and mobs looked up in `bestiary`.
+pub fn gen_map_on(
+    seed: u64,
+    zone_specs: &[ZoneSpec],
+    difficulty: Difficulty,
+    loot: &LootTables,
+    bestiary: &Bestiary,
+) -> Result<MapgenResult, MapgenError> {
+    gen_map_with_room_counts(seed, zone_specs, difficulty, loot, bestiary).map(|(result, _)| result)
+}
+
+/// Like [`gen_map`], but also returns how many rooms each zone's room graph
+/// had, or `None` for zones that aren't built from rooms.
+pub fn gen_map_with_room_counts(
+    seed: u64,
+    zone_specs: &[ZoneSpec],
+    difficulty: Difficulty,
+    loot: &LootTables,
+    bestiary: &Bestiary,
+) -> Result<(MapgenResult, Vec<Option<usize>>), MapgenError> {
+    if let Some(spec) = zone_specs
+        .iter()
+        .find(|spec| spec.width < MIN_ZONE_SIZE || spec.height < MIN_ZONE_SIZE)
+    {
+        return Err(MapgenError::ZoneTooSmall(spec.name.clone()));
+    }
+    let rects = layout_zones(zone_specs)?;
+    let links = zone_links(zone_specs, &rects)?;
+    // Zones that can't be joined where they meet get rerolled as a whole.
+    let mut reseed = StdRng::seed_from_u64(seed);
+    let mut attempt_seed = seed;
+    for _ in 0..MAX_CONNECT_ATTEMPTS {
+        match gen_zones(
+            attempt_seed,
+            zone_specs,
+            &rects,
+            &links,
+            difficulty,
+            loot,
+            bestiary,
+        ) {
+            Err(MapgenError::Disconnected) => attempt_seed = reseed.r#gen(),
+            result => return result,
+        }
+    }
+    warn!("seed {seed}: zones stay disconnected; falling back to a chain of zones");
+    let chain = zone_specs
+        .iter()
+        .map(|spec| ZoneSpec {
+            placement: Placement::Next,
+            offset: 0,
+            connects: Vec::new(),
+            ..spec.clone()
+        })
+        .collect::<Vec<_>>();
+    let rects = layout_zones(&chain)?;
+    let links = zone_links(&chain, &rects)?;
+    gen_zones(seed, &chain, &rects, &links, difficulty, loot, bestiary)
+}
+
+/// Generates each zone of `zone_specs` in its rect and joins the linked ones.
+fn gen_zones(
+    seed: u64,
+    zone_specs: &[ZoneSpec],
+    rects: &[Rect],
+    links: &[(usize, usize)],
+    difficulty: Difficulty,
+    loot: &LootTables,
+    bestiary: &Bestiary,
+) -> Result<(MapgenResult, Vec<Option<usize>>), MapgenError> {
+    let mut mapgen = Mapgen::new(seed, difficulty, loot, bestiary);
+    // Wall in every zone up front, so that nothing outside the zones is open
+    // and no zone's border is generated over another zone.
+    for rect in rects {
+        mapgen
+            .tile_map
+            .set_rect(rect.expand(1), Some(TileKind::Wall));
+    }
+
+    let mut zones = Vec::new();
+    let mut room_counts = Vec::new();
+    for (spec, &rect) in zone_specs.iter().zip(rects) {
+        mapgen.gen_zone(rect, spec)?;
+        room_counts.push(mapgen.room_count);
+        zones.push(Zone {
+            rect: rect.into(),
+            spec: spec.clone(),
+            patrols: mapgen.gen_patrols(rect),
+        });
+    }
+    for &(a, b) in links {
+        mapgen.connect_zones(rects[a], rects[b])?;
+    }
+
+    let floors = mapgen.take_floor_spawns();
+    let mut ground = FloorPlan::new();
+    mapgen.swap_floor(&mut ground);
+    let spawns = ground.take_spawns();
+    Ok((
+        MapgenResult {
+            spawns,
+            zones,
+            floors,
+        },
+        room_counts,
+    ))
+}
+
+/// Most an endless zone's mobs get multiplied by, however deep it is.
+const ENDLESS_MAX_MOB_SCALE: f64 = 3.0;
+/// How many more mobs each endless zone gets than its template, per depth.
+const ENDLESS_MOB_SCALE_PER_DEPTH: f64 = 0.15;
+
+/// Generates zones one at a time for endless mode, each placed to the right
+/// of the last and harder than it.
+///
+/// Zone specs come from a template list like `assets/zones.ron`: the first
+/// template is the starting zone, the rest are drawn at random, favouring
+/// later (harder) templates and adding mobs the deeper the zone is. Boss
+/// arenas are left out, since killing the boss ends the game.
+pub struct ZoneStream {
+    mapgen: Mapgen,
+    templates: Vec<ZoneSpec>,
+    depth: usize,
+    prev_rect: Option<Rect>,
+}
+
+impl ZoneStream {
+    pub fn new(
+        seed: u64,
+        templates: &[ZoneSpec],
+        difficulty: Difficulty,
+        loot: &LootTables,
+        bestiary: &Bestiary,
+    ) -> Result<Self, MapgenError> {
+        if let Some(spec) = templates
+            .iter()
+            .find(|spec| spec.width < MIN_ZONE_SIZE || spec.height < MIN_ZONE_SIZE)
+        {
+            return Err(MapgenError::ZoneTooSmall(spec.name.clone()));
+        }
+        let templates = templates
+            .iter()
+            .filter(|spec| spec.generator != ZoneGenerator::BossArena)
+            .cloned()
+            .collect::<Vec<_>>();
+        if templates.is_empty() {
+            return Err(MapgenError::NoZoneTemplates);
+        }
+        Ok(ZoneStream {
+            mapgen: Mapgen::new(seed, difficulty, loot, bestiary),
+            templates,
+            depth: 0,
+            prev_rect: None,
+        })
+    }
+
+    /// Number of zones generated so far.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn next_spec(&mut self) -> ZoneSpec {
+        let depth = self.depth;
+        let mut spec = if depth == 0 || self.templates.len() == 1 {
+            self.templates[0].clone()
+        } else {
+            let choices = &self.templates[1..];
+            let weight = |i: usize| 1.0 + (depth * i) as f64 / choices.len() as f64;
+            let indices = (0..choices.len()).collect::<Vec<_>>();
+            let i = indices
+                .choose_weighted(&mut self.mapgen.rng, |&i| weight(i))
+                .expect("weights are positive");
+            choices[*i].clone()
+        };
+        let scale = (1.0 + depth as f64 * ENDLESS_MOB_SCALE_PER_DEPTH).min(ENDLESS_MAX_MOB_SCALE);
+        for (count, spawn) in &mut spec.population {
+            if matches!(spawn, Spawn::Mob(_)) {
+                *count = (*count as f64 * scale).round() as usize;
+            }
+        }
+        spec.name = format!("{} {}", spec.name, depth + 1);
+        // Endless zones are always laid out in a line.
+        spec.placement = Placement::Next;
+        spec.offset = 0;
+        spec.connects.clear();
+        if depth > 0 {
+            spec.intro.clear();
+        }
+        spec
+    }
+
+    /// Generates the next zone. The result holds just that zone, the spawns
+    /// for its columns (including the walls above and below it that close off
+    /// the previous zone where it was taller) and its other floors.
+    ///
+    /// A zone whose spec can't be satisfied is replaced with an empty field,
+    /// so the stream never gets stuck.
+    pub fn next_zone(&mut self) -> MapgenResult {
+        let mut spec = self.next_spec();
+        let mut rect = Rect::new_centered(Pos::new(0, 0), spec.width, spec.height);
+        if let Some(prev_rect) = self.prev_rect {
+            rect = rect.shift_to_right_of(prev_rect);
+        }
+        if let Err(e) = self.mapgen.gen_zone(rect, &spec) {
+            warn!("zone {:?}: {e}; falling back to an empty field", spec.name);
+            self.mapgen.clear_zone(rect);
+            self.mapgen.floors.clear();
+            self.mapgen.gen_fallback(rect, ZoneGenerator::Field);
+            spec.generator = ZoneGenerator::Field;
+            spec.population.clear();
+            spec.loot = None;
+            spec.floors.clear();
+        }
+        let patrols = self.mapgen.gen_patrols(rect);
+
+        // The first zone also gets the wall column on its left.
+        let prev = self.prev_rect.unwrap_or(rect);
+        let region = Rect::new(
+            if self.prev_rect.is_some() {
+                rect.x1
+            } else {
+                rect.x1 - 1
+            },
+            rect.x2,
+            prev.y1.min(rect.y1) - 1,
+            prev.y2.max(rect.y2) + 1,
+        );
+        let mut spawns: HashMap<IVec2, Vec<Spawn>> = HashMap::new();
+        for pos in region {
+            if let Some(tile) = self.mapgen.tile_map[pos] {
+                spawns
+                    .entry(pos.into())
+                    .or_default()
+                    .push(Spawn::Tile(tile));
+            }
+        }
+        add_mob_and_item_spawns(
+            &mut spawns,
+            std::mem::take(&mut self.mapgen.mob_spawns),
+            std::mem::take(&mut self.mapgen.item_spawns),
+        );
+
+        self.depth += 1;
+        self.prev_rect = Some(rect);
+        MapgenResult {
+            spawns,
+            zones: vec![Zone {
+                rect: rect.into(),
+                spec,
+                patrols,
+            }],
+            floors: self.mapgen.take_floor_spawns(),
+        }
+    }
+
+    /// Forgets the tiles generated left of column `x`, which the player has
+    /// left behind for good.
+    pub fn forget_left_of(&mut self, x: i32) {
+        self.mapgen.tile_map.forget_left_of(x);
+    }
+}
+
+/// Everything [`validate`] found out of the player's reach. Floors are
+/// numbered as in [`MapgenResult::floors`], with 0 for the ground.
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    /// Names of zones without a single tile the player can reach.
+    pub unreachable_zones: Vec<String>,
+    pub unreachable_items: Vec<(i32, IVec2, ItemKind)>,
+    /// Boss spawns the player can't reach.
+    pub unreachable_boss: Vec<(i32, IVec2)>,
+    /// Where the lever appears if the boss is killed before it moves, if the
+    /// player can't reach it.
+    pub unreachable_lever: Option<IVec2>,
+    /// Stairs on other floors whose other end on the ground is missing or out
+    /// of reach.
+    pub unreachable_stairs: Vec<(i32, IVec2)>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        *self == ValidationReport::default()
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            return write!(f, "everything is reachable");
+        }
+        write!(f, "unreachable:")?;
+        for zone in &self.unreachable_zones {
+            write!(f, " zone {zone:?},")?;
+        }
+        for (floor, pos, item) in &self.unreachable_items {
+            write!(f, " {item:?} at {pos} on floor {floor},")?;
+        }
+        for (floor, pos) in &self.unreachable_boss {
+            write!(f, " boss at {pos} on floor {floor},")?;
+        }
+        if let Some(pos) = self.unreachable_lever {
+            write!(f, " lever at {pos},")?;
+        }
+        for (floor, pos) in &self.unreachable_stairs {
+            write!(f, " stairs at {pos} on floor {floor},")?;
+        }
+        Ok(())
+    }
+}
+
+/// The stairs on `floor` that lead to the ground, and the ones on the ground
+/// they lead from.
+fn stairs(floor: i32) -> (TileKind, TileKind) {
+    if floor < 0 {
+        (TileKind::StairsUp, TileKind::StairsDown)
+    } else {
+        (TileKind::StairsDown, TileKind::StairsUp)
+    }
+}
+
+/// Walking distance to every tile of `spawns` reachable from `starts`, each
+/// of which is already some distance away. Moves are cardinal, as for the
+/// player, and confined to `bounds`.
+fn walk_from(
+    spawns: &HashMap<IVec2, Vec<Spawn>>,
+    bounds: IRect,
+    starts: impl IntoIterator<Item = (IVec2, usize)>,
+) -> HashMap<IVec2, usize> {
+    let walkable = |pos: IVec2| {
+        bounds.contains(pos)
+            && !spawns.get(&pos).is_some_and(|spawns| {
+                spawns
+                    .iter()
+                    .any(|spawn| matches!(spawn, Spawn::Tile(t) if t.blocks_movement()))
+            })
+    };
+    let mut distances = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for (pos, dist) in starts {
+        if distances.get(&pos).is_none_or(|&d| dist < d) {
+            distances.insert(pos, dist);
+            queue.push(Reverse((dist, pos.x, pos.y)));
+        }
+    }
+    while let Some(Reverse((dist, x, y))) = queue.pop() {
+        let pos = IVec2::new(x, y);
+        if distances[&pos] < dist {
+            continue;
+        }
+        for next in Pos::from(pos).adjacent_cardinal() {
+            let next = IVec2::from(next);
+            if walkable(next) && distances.get(&next).is_none_or(|&d| dist + 1 < d) {
+                distances.insert(next, dist + 1);
+                queue.push(Reverse((dist + 1, next.x, next.y)));
+            }
+        }
+    }
+    distances
+}
+
+/// The zones plus a one-tile border, which is as far as the player can walk.
+fn walk_bounds(result: &MapgenResult) -> Option<IRect> {
+    result
+        .zones
+        .iter()
+        .map(|zone| zone.rect)
+        .reduce(|a, b| a.union(b))
+        .map(|bounds| bounds.inflate(1))
+}
+
+/// Walking distance from the player start to every tile the player can reach
+/// on the ground. Moves are cardinal, as for the player, and confined to the
+/// zones plus a one-tile border.
+pub fn walk_distances(result: &MapgenResult) -> HashMap<IVec2, usize> {
+    let Some(bounds) = walk_bounds(result) else {
+        return HashMap::new();
+    };
+    walk_from(&result.spawns, bounds, [(PLAYER_START, 0)])
+}
+
+/// Like [`walk_distances`], but for each floor other than the ground,
+/// walking onto it by the stairs from the ground. Taking the stairs doesn't
+/// count as a step.
+pub fn floor_walk_distances(
+    result: &MapgenResult,
+    ground: &HashMap<IVec2, usize>,
+) -> BTreeMap<i32, HashMap<IVec2, usize>> {
+    let Some(bounds) = walk_bounds(result) else {
+        return BTreeMap::new();
+    };
+    result
+        .floors
+        .iter()
+        .map(|(&floor, spawns)| {
+            let (here, there) = stairs(floor);
+            let starts = spawns
+                .iter()
+                .filter(|(pos, spawns)| {
+                    spawns.contains(&Spawn::Tile(here))
+                        && result
+                            .spawns
+                            .get(pos)
+                            .is_some_and(|s| s.contains(&Spawn::Tile(there)))
+                })
+                .filter_map(|(pos, _)| Some((*pos, *ground.get(pos)?)));
+            (floor, walk_from(spawns, bounds, starts))
+        })
+        .collect()
+}
+
+/// Checks that the player start can walk to every zone, item, boss in
+/// `bestiary` and the lever position, on the ground and, by their stairs,
+/// every other floor.
+pub fn validate(result: &MapgenResult, bestiary: &Bestiary) -> ValidationReport {
+    if result.zones.is_empty() {
+        return ValidationReport::default();
+    }
+    let reachable = walk_distances(result);
+    let floors_reachable = floor_walk_distances(result, &reachable);
+
+    let mut report = ValidationReport::default();
+    for zone in &result.zones {
+        if !reachable.keys().any(|pos| zone.rect.contains(*pos)) {
+            report.unreachable_zones.push(zone.spec.name.clone());
+        }
+    }
+    let floors = std::iter::once((0, &result.spawns, &reachable)).chain(
+        result
+            .floors
+            .iter()
+            .map(|(floor, spawns)| (*floor, spawns, &floors_reachable[floor])),
+    );
+    for (floor, spawns, reachable) in floors {
+        for (pos, spawns) in spawns {
+            if reachable.contains_key(pos) {
+                continue;
+            }
+            for spawn in spawns {
+                match spawn {
+                    Spawn::Item(item) => report.unreachable_items.push((floor, *pos, *item)),
+                    Spawn::Mob(name) if bestiary.get(name).is_some_and(|def| def.boss) => {
+                        report.unreachable_boss.push((floor, *pos))
+                    }
+                    Spawn::Tile(t) if floor != 0 && *t == stairs(floor).0 => {
+                        report.unreachable_stairs.push((floor, *pos))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    report
+        .unreachable_items
+        .sort_by_key(|(floor, pos, _)| (*floor, pos.x, pos.y));
+    report
+        .unreachable_boss
+        .sort_by_key(|(floor, pos)| (*floor, pos.x, pos.y));
+    report
+        .unreachable_stairs
+        .sort_by_key(|(floor, pos)| (*floor, pos.x, pos.y));
+    // Mirrors the fallback in `final_boss`.
+    let lever = boss_zone(&result.zones).unwrap().rect.center();
+    if !reachable.contains_key(&lever) {
+        report.unreachable_lever = Some(lever);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bestiary::default_bestiary, loot::default_loot_tables};
+
+    #[test]
+    fn test_gen_map_reproducible() {
+        let specs = default_zone_specs();
+        for seed in [0, 1, 0xf06] {
+            assert_eq!(gen_map(seed, &specs), gen_map(seed, &specs));
+        }
+        assert_ne!(gen_map(0, &specs), gen_map(1, &specs));
+        assert!(gen_map(0, &specs).is_ok());
+    }
+
+    #[test]
+    fn test_patrols_start_at_patrollers() {
+        let bestiary = default_bestiary();
+        let MapgenResult { spawns, zones, .. } = gen_map(0, &default_zone_specs()).unwrap();
+        let blocked = |pos: &IVec2| {
+            spawns.get(pos).is_some_and(|spawns| {
+                spawns
+                    .iter()
+                    .any(|spawn| matches!(spawn, Spawn::Tile(t) if t.blocks_movement()))
+            })
+        };
+        let mut total = 0;
+        for zone in &zones {
+            let mut patrollers = spawns
+                .iter()
+                .filter(|(pos, spawns)| {
+                    zone.rect.contains(**pos)
+                        && spawns.iter().any(|spawn| {
+                            matches!(spawn, Spawn::Mob(name) if bestiary.get(name).unwrap().patrols())
+                        })
+                })
+                .map(|(pos, _)| *pos)
+                .collect::<Vec<_>>();
+            let mut starts = zone
+                .patrols
+                .iter()
+                .map(|route| route[0])
+                .collect::<Vec<_>>();
+            patrollers.sort_by_key(|p| (p.x, p.y));
+            starts.sort_by_key(|p| (p.x, p.y));
+            assert_eq!(starts, patrollers, "{}", zone.spec.name);
+            for route in &zone.patrols {
+                assert_eq!(route.len(), PATROL_WAYPOINTS);
+                assert!(route.iter().all(|p| zone.rect.contains(*p) && !blocked(p)));
+            }
+            total += zone.patrols.len();
+        }
+        assert!(total > 0);
+    }
+
+    #[test]
+    fn test_one_sculpture_per_warehouse() {
+        let specs = default_zone_specs();
+        let warehouse = specs
+            .iter()
+            .position(|spec| spec.generator == ZoneGenerator::BspBuilding)
+            .unwrap();
+        for seed in 0..40 {
+            let MapgenResult { spawns, zones, .. } = gen_map(seed, &specs).unwrap();
+            let sculptures = spawns
+                .iter()
+                .filter(|(pos, _)| zones[warehouse].rect.contains(**pos))
+                .flat_map(|(_, spawns)| spawns)
+                .filter(|spawn| **spawn == Spawn::Mob(MobName::SCULPTURE))
+                .count();
+            assert_eq!(sculptures, 1, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn test_zone_stream() {
+        let templates = default_zone_specs();
+        for seed in [0, 1, 2] {
+            let mut stream = ZoneStream::new(
+                seed,
+                &templates,
+                Difficulty::Normal,
+                &default_loot_tables(),
+                &default_bestiary(),
+            )
+            .unwrap();
+            let mut level = MapgenResult::default();
+            for _ in 0..8 {
+                let MapgenResult { spawns, zones, .. } = stream.next_zone();
+                for (pos, spawns) in spawns {
+                    assert!(
+                        level.spawns.insert(pos, spawns).is_none(),
+                        "{pos} respawned"
+                    );
+                }
+                level.zones.extend(zones);
+            }
+            assert_eq!(level.zones[0].spec.generator, ZoneGenerator::Field);
+            for pair in level.zones.windows(2) {
+                assert_eq!(pair[0].rect.max.x + 1, pair[1].rect.min.x);
+                assert_ne!(pair[1].spec.generator, ZoneGenerator::BossArena);
+            }
+            let report = validate(&level, &default_bestiary());
+            assert!(report.unreachable_zones.is_empty(), "seed {seed}: {report}");
+            assert!(report.unreachable_items.is_empty(), "seed {seed}: {report}");
+        }
+
+        // a zone that can't be populated is replaced rather than retried
+        let mut templates = templates[..1].to_vec();
+        templates[0].population = vec![(10_000, Spawn::Mob(MobName::ZOMBIE))];
+        let mut stream = ZoneStream::new(
+            0,
+            &templates,
+            Difficulty::Normal,
+            &default_loot_tables(),
+            &default_bestiary(),
+        )
+        .unwrap();
+        for depth in 1..=3 {
+            let MapgenResult { spawns, zones, .. } = stream.next_zone();
+            assert_eq!(stream.depth(), depth);
+            assert_eq!(zones[0].spec.generator, ZoneGenerator::Field);
+            assert!(
+                !spawns
+                    .values()
+                    .flatten()
+                    .any(|spawn| matches!(spawn, Spawn::Mob(_)))
+            );
+        }
+    }
+
+    #[test]
+    fn test_floors_connect_by_stairs() {
+        let specs = default_zone_specs();
+        let bestiary = default_bestiary();
+        for seed in 0..20 {
+            let result = gen_map(seed, &specs).unwrap();
+            assert_eq!(result.floors.keys().copied().collect::<Vec<_>>(), [-1, 1]);
+            let report = validate(&result, &bestiary);
+            assert!(report.is_ok(), "seed {seed}: {report}");
+        }
+
+        // without the stairs down, nothing in the basement can be reached
+        let mut result = gen_map(0, &specs).unwrap();
+        let basement = &result.floors[&-1];
+        let (stairs_pos, _) = basement
+            .iter()
+            .find(|(_, spawns)| spawns.contains(&Spawn::Tile(TileKind::StairsUp)))
+            .unwrap();
+        result.spawns.insert(*stairs_pos, vec![Spawn::Tile(TileKind::Wall)]);
+        let report = validate(&result, &bestiary);
+        assert_eq!(report.unreachable_stairs, [(-1, *stairs_pos)]);
+        let items = basement
+            .values()
+            .flatten()
+            .filter(|spawn| matches!(spawn, Spawn::Item(_)))
+            .count();
+        assert!(items > 0);
+        assert_eq!(
+            report
+                .unreachable_items
+                .iter()
+                .filter(|(floor, ..)| *floor == -1)
+                .count(),
+            items
+        );
+    }
+
+    #[test]
+    fn test_railyard_first_try() {
+        let rect = Rect::new(0, 59, 0, 43);
+        let boxes_zone = Rect::new(1, 58, 0, 43);
+        for seed in 0..20 {
+            let mut mapgen = Mapgen::new(
+                seed,
+                Difficulty::Normal,
+                &default_loot_tables(),
+                &default_bestiary(),
+            );
+            // the layout gen_railyard settles on is its first attempt
+            railyard_rules()
+                .generate_once(
+                    boxes_zone,
+                    &railyard_aisles(boxes_zone),
+                    &mut mapgen.rng.clone(),
+                )
+                .unwrap_or_else(|err| panic!("seed {seed}: {err}"));
+            mapgen.gen_railyard(rect).unwrap();
+            let containers = rect
+                .into_iter()
+                .filter(|p| mapgen.tile_map[*p] == Some(TileKind::ShippingContainer))
+                .count();
+            assert!(
+                containers > rect.len() / 5,
+                "seed {seed}: {containers} containers"
+            );
+        }
+    }
+
+    #[test]
+    fn test_house_doors_face_the_road() {
+        let road = Rect::new(0, 19, 0, 19);
+        let house = Rect::new(4, 15, 5, 14);
+        for seed in 0..20 {
+            for faces_north in [true, false] {
+                let mut mapgen = Mapgen::new(
+                    seed,
+                    Difficulty::Normal,
+                    &default_loot_tables(),
+                    &default_bestiary(),
+                );
+                mapgen.tile_map.set_rect(road, None);
+                mapgen.gen_house(house, faces_north).unwrap();
+                let front_doors = house
+                    .into_iter()
+                    .filter(|p| !house.shrink(1).contains(*p))
+                    .filter(|p| mapgen.tile_map[*p] == Some(TileKind::Door))
+                    .collect::<Vec<_>>();
+                let [door] = front_doors[..] else {
+                    panic!("seed {seed}: front doors {front_doors:?}");
+                };
+                let (wall_y, outside) = if faces_north {
+                    (house.y2, Offset::new(0, 1))
+                } else {
+                    (house.y1, Offset::new(0, -1))
+                };
+                assert_eq!(door.y, wall_y, "seed {seed}");
+                assert_eq!(mapgen.tile_map[door + outside], None, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_disconnected_zones_fall_back_to_a_chain() {
+        let field = default_zone_specs()[0].clone();
+        // The yard only shares the field's bordered corner column, so no
+        // passage can join them.
+        let yard = ZoneSpec {
+            name: "yard".to_owned(),
+            generator: ZoneGenerator::ContainerYard,
+            width: 40,
+            height: 30,
+            population: Vec::new(),
+            loot: None,
+            floors: Vec::new(),
+            placement: Placement::South(field.name.clone()),
+            offset: -(field.width + 40) / 2 + 1,
+            ..field.clone()
+        };
+        let specs = [field, yard];
+        let rects = layout_zones(&specs).unwrap();
+        let links = zone_links(&specs, &rects).unwrap();
+        assert_eq!(
+            gen_zones(
+                0,
+                &specs,
+                &rects,
+                &links,
+                Difficulty::Normal,
+                &default_loot_tables(),
+                &default_bestiary(),
+            )
+            .map(|_| ()),
+            Err(MapgenError::Disconnected)
+        );
+
+        let result = gen_map(0, &specs).unwrap();
+        let (field, yard) = (result.zones[0].rect, result.zones[1].rect);
+        assert_eq!(field.max.x + 1, yard.min.x);
+        let reachable = walk_distances(&result);
+        assert!(reachable.keys().any(|pos| yard.contains(*pos)));
+    }
+
+    #[test]
+    fn test_zone_specs_drive_layout() {
+        let mut specs = default_zone_specs();
+        assert_eq!(specs.len(), 8);
+        // Repeat the forest and shrink the field.
+        specs.insert(2, specs[1].clone());
+        specs[0].width = 10;
+        let MapgenResult { zones, .. } = gen_map(0, &specs).unwrap();
+        assert_eq!(zones.len(), 9);
+        assert_eq!(zones[0].rect.width() + 1, 10);
+        // the route to the boss runs left to right
+        for pair in zones[..7].windows(2) {
+            assert_eq!(pair[0].rect.max.x + 1, pair[1].rect.min.x);
+        }
+        assert_eq!(zones[1].spec, zones[2].spec);
+    }
+
+    #[test]
+    fn test_zone_graph_layout() {
+        let specs = default_zone_specs();
+        let result = gen_map(0, &specs).unwrap();
+        let rect = |name: &str| {
+            result
+                .zones
+                .iter()
+                .find(|zone| zone.spec.name == name)
+                .unwrap()
+                .rect
+        };
+        // the farmstead hangs below the forest, behind a row of trees
+        let (forest, farmstead) = (rect("forest"), rect("farmstead"));
+        assert_eq!(farmstead.max.y + 2, forest.min.y);
+        assert!(forest.min.x <= farmstead.min.x && farmstead.max.x <= forest.max.x);
+        // the railyard and the marsh both lead from forest2 to the boss
+        let (railyard, marsh, last) = (rect("railyard"), rect("marsh"), rect("final"));
+        assert_eq!(rect("forest2").max.x + 1, marsh.min.x);
+        assert_eq!(railyard.min.x, marsh.min.x);
+        assert_eq!(marsh.max.y + 2, railyard.min.y);
+        for route in [railyard, marsh] {
+            assert_eq!(route.max.x + 1, last.min.x);
+            assert!(route.min.y <= last.max.y && last.min.y <= route.max.y);
+        }
+        assert_eq!(boss_zone(&result.zones).unwrap().spec.name, "final");
+
+        // Either route alone reaches the boss.
+        for blocked in ["railyard", "marsh"] {
+            let mut result = result.clone();
+            let blocked = rect(blocked);
+            for pos in Rect::new(blocked.min.x, blocked.max.x, blocked.min.y, blocked.max.y) {
+                result
+                    .spawns
+                    .insert(pos.into(), vec![Spawn::Tile(TileKind::Wall)]);
+            }
+            let reachable = walk_distances(&result);
+            assert!(reachable.contains_key(&last.center()));
+        }
+
+        let mut bad = specs.clone();
+        bad[6].placement = Placement::South("nowhere".to_owned());
+        assert_eq!(
+            gen_map(0, &bad),
+            Err(MapgenError::UnknownZone("nowhere".to_owned()))
+        );
+        let mut bad = specs.clone();
+        bad[7].offset = 0;
+        assert_eq!(
+            gen_map(0, &bad),
+            Err(MapgenError::ZoneOverlap("marsh".to_owned()))
+        );
+        let mut bad = specs.clone();
+        bad[6].connects.push("final".to_owned());
+        assert_eq!(
+            gen_map(0, &bad),
+            Err(MapgenError::NotAdjacent(
+                "final".to_owned(),
+                "farmstead".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_bad_specs_are_errors() {
+        let mut specs = default_zone_specs();
+        specs[2].width = MIN_ZONE_SIZE - 1;
+        assert_eq!(
+            gen_map(0, &specs),
+            Err(MapgenError::ZoneTooSmall(specs[2].name.clone()))
+        );
+
+        let mut specs = default_zone_specs();
+        specs[0].population = vec![(10_000, Spawn::Mob(MobName::ZOMBIE))];
+        assert!(matches!(
+            gen_map(0, &specs),
+            Err(MapgenError::NotEnoughRoom { wanted: 10_000, .. })
+        ));
+
+        let mut specs = default_zone_specs();
+        specs[0].population = vec![(1, Spawn::Mob(MobName::new("Nobody")))];
+        assert_eq!(
+            gen_map(0, &specs),
+            Err(MapgenError::UnknownMob(MobName::new("Nobody")))
+        );
+
+        let mut specs = default_zone_specs();
+        specs[1].loot = Some("nowhere".to_owned());
+        assert_eq!(
+            gen_map(0, &specs),
+            Err(MapgenError::UnknownLootTable("nowhere".to_owned()))
+        );
+
+        let opts = BspSplitOpts {
+            max_width: 8,
+            max_height: 8,
+            min_width: 4,
+            min_height: 3,
+        };
+        assert!(matches!(
+            gen_bsp_tree(Rect::new(0, 20, 0, 20), opts, &mut StdRng::seed_from_u64(0)),
+            Err(ProcgenError::BadBspOpts(_))
+        ));
+    }
+
+    #[test]
+    fn test_small_zones_generate() {
+        use ZoneGenerator::*;
+        for generator in [
+            Field,
+            CellularAutomata,
+            BspBuilding,
+            ContainerYard,
+            BossArena,
+            Town,
+        ] {
+            for size in MIN_ZONE_SIZE..MIN_ZONE_SIZE + 4 {
+                let spec = ZoneSpec {
+                    name: format!("{generator:?}"),
+                    generator,
+                    width: size,
+                    height: 2 * MIN_ZONE_SIZE + 3 - size,
+                    population: vec![(3, Spawn::Mob(MobName::ZOMBIE))],
+                    loot: Some("house".to_owned()),
+                    fog: 100.0,
+                    music: MusicLayer::Base,
+                    floors: vec![FloorSpec {
+                        floor: -1,
+                        population: vec![(1, Spawn::Mob(MobName::ZOMBIE))],
+                        loot: None,
+                    }],
+                    placement: Placement::Next,
+                    offset: 0,
+                    connects: vec![],
+                    intro: vec![],
+                    ambush: false,
+                };
+                for seed in 0..10 {
+                    gen_map(seed, std::slice::from_ref(&spec)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_difficulty_scales_mobs_and_ammo() {
+        let specs = default_zone_specs();
+        let totals = |difficulty| {
+            let (mut mobs, mut ammo) = (0, 0);
+            for seed in 0..4 {
+                let result = gen_map_on(
+                    seed,
+                    &specs,
+                    difficulty,
+                    &default_loot_tables(),
+                    &default_bestiary(),
+                )
+                .unwrap();
+                for spawn in result.spawns.values().flatten() {
+                    match spawn {
+                        Spawn::Mob(_) => mobs += 1,
+                        Spawn::Item(ItemKind::Ammo(_, n)) => ammo += n,
+                        _ => {}
+                    }
+                }
+                assert!(
+                    validate(&result, &default_bestiary()).is_ok(),
+                    "{difficulty} seed {seed}"
+                );
+            }
+            (mobs, ammo)
+        };
+        let totals = Difficulty::ALL.map(totals);
+        for pair in totals.windows(2) {
+            let [(easy_mobs, easy_ammo), (hard_mobs, hard_ammo)] = pair else {
+                unreachable!()
+            };
+            assert!(easy_mobs < hard_mobs, "{totals:?}");
+            assert!(easy_ammo > hard_ammo, "{totals:?}");
+        }
+        // a lone sculpture stays alone
+        assert_eq!(Difficulty::Nightmare.scale_mob_count(1), 1);
+    }
+
+    /// Generates and validates maps for seeds `0..seeds`, spread over threads.
+    fn validate_seeds(seeds: u64) {
+        let specs = default_zone_specs();
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get() as u64);
+        std::thread::scope(|scope| {
+            for thread in 0..threads {
+                let specs = &specs;
+                scope.spawn(move || {
+                    for seed in (thread..seeds).step_by(threads as usize) {
+                        let report = validate(&gen_map(seed, specs).unwrap(), &default_bestiary());
+                        assert!(report.is_ok(), "seed {seed}: {report}");
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_validate_seeds() {
+        validate_seeds(32);
+    }
+
+    /// Takes minutes in a debug build; run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn test_validate_many_seeds() {
+        validate_seeds(2000);
     }
 }
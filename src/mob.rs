@@ -1,95 +1,73 @@
-use std::time::Duration;
+use std::{borrow::Cow, collections::HashMap, time::Duration};
 
-use bevy::{prelude::*, time::Stopwatch};
-use line_drawing::WalkGrid;
+use bevy::{ecs::system::SystemParam, prelude::*, time::Stopwatch};
 use rand::{Rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     Player,
     animation::{MoveAnimation, WobbleEffect, WobbleEffects},
+    behaviour::{
+        Asleep, ChargeState, Gait, Intent, Memory, PatrolRoute, Surroundings, decide, step_down,
+    },
+    bestiary::{Bestiary, MobKind, load_bestiary},
+    difficulty::{Difficulty, RunState},
+    loot::LootTables,
     map::{
-        FlashlightMap, FovMap, LightsUp, Map, MapPos, PlayerVisibilityMap, SightBlockedMap, Tile,
-        WalkBlockedMap, Zones, path, update_flashlight_map, update_fov_map, update_lit,
-        update_visibility, update_walkability,
+        FlashlightMap, FovMap, HidingSpotMap, LightsUp, Map, MapPos, OpacityMap, PlayerDistanceMap,
+        PlayerVisibilityMap, SightBlockedMap, Tile, WalkBlockedMap, Zones, path,
+        update_flashlight_map, update_fov_map, update_lit, update_navigation_maps,
+        update_spatial_index, update_visibility,
     },
+    noise::{self, Noise, NoiseEvent},
     player::{PlayerDamageEvent, PlayerMoveEvent, ShootEvent},
     spawn::{Spawn, SpawnEvent},
+    vision::Facing,
 };
 
 const MAX_PATH: i32 = 100;
-const HIDER_CHASE_DISTANCE: i32 = 5;
-const KOOL_AID_OVERSHOOT: usize = 4;
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum MobKind {
-    Zombie,
-    Sculpture,
-    Hider,
-    Ghost,
-    KoolAidMan,
-    Eyeball,
-}
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum LightSensitivity {
-    Very,
-    Yes,
-    No,
-}
-
-impl MobKind {
-    pub fn get_light_sensitivity(&self) -> LightSensitivity {
-        use LightSensitivity::*;
-        use MobKind::*;
-        match self {
-            Ghost | Eyeball => Very,
-            Zombie | Hider => Yes,
-            Sculpture | KoolAidMan => No,
-        }
-    }
-    pub fn get_move_delay(&self) -> Duration {
-        use MobKind::*;
-        match self {
-            Eyeball => Duration::from_secs(1),
-            Zombie => Duration::from_secs(1),
-            Ghost => Duration::from_secs(1),
-            Sculpture => Duration::from_millis(32),
-            Hider => Duration::from_millis(700),
-            KoolAidMan => Duration::from_millis(200),
-        }
+/// The name of a kind of mob: its entry in the bestiary, and how zones, levels
+/// and spawns refer to it. Written in RON as a string, like `"Zombie"`. Mobs
+/// that have been spawned carry the [`MobKind`] it names instead.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct MobName(Cow<'static, str>);
+
+impl MobName {
+    pub const ZOMBIE: MobName = MobName(Cow::Borrowed("Zombie"));
+    pub const SLEEPING_ZOMBIE: MobName = MobName(Cow::Borrowed("SleepingZombie"));
+    pub const SCULPTURE: MobName = MobName(Cow::Borrowed("Sculpture"));
+    pub const HIDER: MobName = MobName(Cow::Borrowed("Hider"));
+    pub const GHOST: MobName = MobName(Cow::Borrowed("Ghost"));
+    pub const KOOL_AID_MAN: MobName = MobName(Cow::Borrowed("KoolAidMan"));
+    pub const EYEBALL: MobName = MobName(Cow::Borrowed("Eyeball"));
+
+    pub fn new(name: &str) -> Self {
+        MobName(Cow::Owned(name.to_owned()))
     }
+}
 
-    pub fn max_damage(&self) -> i32 {
-        use MobKind::*;
-        match self {
-            Zombie => 3,
-            Ghost => 99,
-            Eyeball => 99,
-            Sculpture => 99,
-            Hider => 2,
-            KoolAidMan => 5,
-        }
+impl std::fmt::Debug for MobName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    pub fn get_ease_function_for_movement(&self) -> EaseFunction {
-        use MobKind::*;
-        match self {
-            Eyeball => EaseFunction::CircularInOut,
-            Zombie => EaseFunction::BounceIn,
-            Ghost => EaseFunction::Linear,
-            Sculpture => EaseFunction::Linear,
-            Hider => EaseFunction::CubicIn,
-            KoolAidMan => EaseFunction::BounceOut,
-        }
+impl std::fmt::Display for MobName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    pub fn hears_player(&self) -> bool {
-        use MobKind::*;
-        !matches!(self, Zombie | Ghost)
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LightSensitivity {
+    Very,
+    Yes,
+    No,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Mob {
     pub kind: MobKind,
     pub move_timer: Timer,
@@ -104,44 +82,57 @@ pub struct MobDamageEvent {
 
 fn damage_mobs(
     mut commands: Commands,
-    mut q_mob: Query<(Entity, &mut Mob)>,
+    mut q_mob: Query<(Entity, &mut Mob, &MapPos)>,
     mut ev_mob_damage: EventReader<MobDamageEvent>,
+    mut ev_spawn: EventWriter<SpawnEvent>,
+    loot: Res<LootTables>,
+    bestiary: Res<Bestiary>,
+    difficulty: Res<Difficulty>,
 ) {
+    let mut rng = rand::thread_rng();
     for MobDamageEvent { damage, entity } in ev_mob_damage.read() {
-        if let Ok((entity, mut mob)) = q_mob.get_mut(*entity) {
+        if let Ok((entity, mut mob, pos)) = q_mob.get_mut(*entity) {
+            let max_damage = bestiary.def(mob.kind).max_damage(*difficulty);
+            let was_alive = mob.damage < max_damage;
             mob.damage += damage;
-            if mob.damage >= mob.kind.max_damage() {
+            commands.entity(entity).remove::<Asleep>();
+            if mob.damage >= max_damage {
                 commands.entity(entity).despawn();
+                if !was_alive {
+                    continue;
+                }
+                for item in loot.roll_mob(bestiary.name(mob.kind), &mut rng) {
+                    let item = difficulty.scale_loot(item);
+                    ev_spawn.send(SpawnEvent(pos.0, Spawn::Item(item)));
+                }
             }
         }
     }
 }
 
-#[allow(clippy::too_many_arguments)]
+/// A path from `source` to `target` that goes the way `gait` allows, keeping
+/// out of the flashlight beam where it can.
 fn path_to(
     source: IVec2,
     target: IVec2,
+    gait: Gait,
     walk_blocked_map: &WalkBlockedMap,
     sight_blocked_map: &SightBlockedMap,
     fov_map: &FovMap,
     flashlight_map: &FlashlightMap,
-    avoid_player_sight: bool,
-    avoid_doors: bool,
-    avoid_player_flashlight: bool,
-    path_through_walls: bool,
 ) -> Option<Vec<IVec2>> {
     path(
         source,
         target,
         MAX_PATH,
         |p| {
-            (!path_through_walls && walk_blocked_map.0.contains(&p))
-                || (avoid_doors && sight_blocked_map.0.contains(&p))
+            (!gait.through_walls && walk_blocked_map.0.contains(&p))
+                || (gait.avoid_doors && sight_blocked_map.0.contains(&p))
         },
         |p| {
-            if avoid_player_sight && fov_map.0.contains(&p) {
+            if gait.avoid_sight && fov_map.0.contains(&p) {
                 99
-            } else if avoid_player_flashlight && flashlight_map.0.contains(&p) {
+            } else if flashlight_map.0.contains(&p) {
                 1
             } else {
                 0
@@ -150,21 +141,6 @@ fn path_to(
     )
 }
 
-fn find_hiding_spot(
-    source: IVec2,
-    walk_blocked_map: &WalkBlockedMap,
-    sight_blocked_map: &SightBlockedMap,
-) -> Option<IVec2> {
-    pathfinding::directed::bfs::bfs_reach(source, |&p| {
-        rogue_algebra::Pos::from(p)
-            .adjacent_cardinal()
-            .map(IVec2::from)
-            .into_iter()
-            .filter(|p| !walk_blocked_map.0.contains(p))
-    })
-    .find(|&p| sight_blocked_map.0.contains(&p))
-}
-
 #[derive(Component)]
 pub struct SeesPlayer;
 
@@ -183,28 +159,43 @@ impl SawPlayer {
     }
 }
 
+/// Mobs see the player when they're in their view cone and in range; see
+/// [`crate::vision`]. Mobs that see the player turn to keep them in view, and
+/// sleeping mobs see nothing.
+#[allow(clippy::complexity)]
 fn update_mobs_seeing_player(
     mut commands: Commands,
-    mobs: Query<(Entity, &MapPos, Option<&SawPlayer>), With<SeesPlayer>>,
-    player_visibility_map: Res<PlayerVisibilityMap>,
+    mut mobs: Query<
+        (Entity, &Mob, &MapPos, &mut Facing, Option<&SawPlayer>),
+        (With<SeesPlayer>, Without<Asleep>),
+    >,
+    opacity_map: Res<OpacityMap>,
     sight_blocked_map: Res<SightBlockedMap>,
+    flashlight_map: Res<FlashlightMap>,
+    bestiary: Res<Bestiary>,
     mut ev_player_move: EventReader<PlayerMoveEvent>,
     player: Query<&MapPos, (With<Player>, Without<Mob>)>,
 ) {
     let player_pos = player.single();
     let last_player_move = ev_player_move.read().last();
-    for (entity, mob_pos, saw_player) in mobs.iter() {
-        let player_sees_mob = player_visibility_map.0.contains(&mob_pos.0);
-        let player_is_hidden = sight_blocked_map.0.contains(&player_pos.0);
-        if player_sees_mob && !player_is_hidden {
+    let player_is_hidden = sight_blocked_map.0.contains(&player_pos.0);
+    for (entity, mob, mob_pos, mut facing, saw_player) in mobs.iter_mut() {
+        let vision = bestiary.def(mob.kind).vision;
+        let in_beam = flashlight_map.0.contains(&mob_pos.0);
+        let sees = |target, hidden| {
+            let range = vision.detection_range(in_beam, hidden);
+            vision.sees(mob_pos.0, facing.0, range, target, &opacity_map.0)
+        };
+        if sees(player_pos.0, player_is_hidden) {
             commands.entity(entity).insert(SawPlayer::new(player_pos.0));
+            facing.turn(player_pos.0 - mob_pos.0);
         } else if let Some(PlayerMoveEvent { source, dest }) = last_player_move {
             if let Some(SawPlayer {
                 pos: last_seen_player_pos,
                 ..
             }) = saw_player
             {
-                if *last_seen_player_pos == source.0 && player_sees_mob {
+                if *last_seen_player_pos == source.0 && sees(dest.0, false) {
                     // Mob saw player move into a hiding spot.
                     commands.entity(entity).insert(SawPlayer::new(dest.0));
                 }
@@ -219,31 +210,75 @@ pub struct HearsPlayer;
 #[derive(Component)]
 pub struct HeardPlayer {
     pub pos: IVec2,
+    /// How loud it was where the mob heard it.
+    pub loudness: i32,
     pub time_since: Stopwatch,
 }
 
 impl HeardPlayer {
-    pub fn new(pos: IVec2) -> Self {
+    pub fn new(pos: IVec2, loudness: i32) -> Self {
         Self {
             pos,
+            loudness,
             time_since: Stopwatch::new(),
         }
     }
+
+    /// How loud the noise seems now, fading a point a second.
+    fn remembered_loudness(&self) -> i32 {
+        self.loudness - self.time_since.elapsed_secs() as i32
+    }
 }
 
+/// Mobs that hear a noise go to see what made it, unless they're already
+/// after something louder. Sleeping mobs only hear noises loud enough to wake
+/// them.
+#[allow(clippy::complexity)]
 fn update_hearing_player(
     mut commands: Commands,
-    mobs: Query<Entity, With<HearsPlayer>>,
-    mut ev_shoot: EventReader<ShootEvent>,
+    mobs: Query<
+        (Entity, &Mob, &MapPos, Option<&HeardPlayer>, Has<Asleep>),
+        Or<(With<HearsPlayer>, With<Asleep>)>,
+    >,
+    tiles: Query<&Tile>,
+    mut ev_noise: EventReader<NoiseEvent>,
     map: Res<Map>,
+    bestiary: Res<Bestiary>,
+    mut heard: Local<HashMap<Entity, HeardPlayer>>,
 ) {
-    const HEARING_RADIUS: i32 = 20;
-    for ShootEvent { start, .. } in ev_shoot.read() {
-        let map_pos = MapPos::from_vec2(*start);
-        for entity in mobs.iter_many(map.get_nearby(map_pos.0, HEARING_RADIUS)) {
-            commands.entity(entity).insert(HeardPlayer::new(map_pos.0));
+    for &event in ev_noise.read() {
+        let noise = Noise::propagate(event, |p| {
+            tiles
+                .iter_many(map.get(p))
+                .map(|tile| tile.0.muffling())
+                .max()
+                .unwrap_or(0)
+        });
+        for (entity, mob, pos, heard_player, asleep) in
+            mobs.iter_many(map.get_nearby(event.pos, event.loudness))
+        {
+            let Some(loudness) = noise.loudness_at(pos.0) else {
+                continue;
+            };
+            if asleep {
+                let wake_loudness = bestiary.def(mob.kind).wake_loudness();
+                if wake_loudness.is_some_and(|wake_loudness| loudness < wake_loudness) {
+                    continue;
+                }
+                commands.entity(entity).remove::<Asleep>();
+            }
+            let louder = heard
+                .get(&entity)
+                .or(heard_player)
+                .is_none_or(|heard| heard.remembered_loudness() <= loudness);
+            if louder {
+                heard.insert(entity, HeardPlayer::new(event.pos, loudness));
+            }
         }
     }
+    for (entity, heard_player) in heard.drain() {
+        commands.entity(entity).insert(heard_player);
+    }
 }
 
 #[allow(clippy::type_complexity)]
@@ -254,6 +289,7 @@ fn forget_player(
         Query<(Entity, &Mob, &MapPos, &mut SawPlayer)>,
     )>,
     time: Res<Time>,
+    bestiary: Res<Bestiary>,
 ) {
     const FORGET_DURATION: Duration = Duration::from_secs(30);
     for (entity, mob, pos, mut heard_player) in set.p0().iter_mut() {
@@ -261,9 +297,7 @@ fn forget_player(
         if pos.0 == heard_player.pos || heard_player.time_since.elapsed() > FORGET_DURATION {
             // Mob is standing on last seen player position.
             commands.entity(entity).remove::<HeardPlayer>();
-            if matches!(mob.kind, MobKind::Sculpture) {
-                info!("forgot about player (hearing)!");
-            }
+            debug!("{} forgot about player (hearing)", bestiary.name(mob.kind));
         }
     }
     for (entity, mob, pos, mut saw_player) in set.p1().iter_mut() {
@@ -271,38 +305,24 @@ fn forget_player(
         if pos.0 == saw_player.pos || saw_player.time_since.elapsed() > FORGET_DURATION {
             // Mob is standing on last seen player position.
             commands.entity(entity).remove::<SawPlayer>();
-            if matches!(mob.kind, MobKind::Sculpture) {
-                info!("forgot about player (seeing)!");
-            }
+            debug!("{} forgot about player (seeing)", bestiary.name(mob.kind));
         }
     }
 }
 
-#[derive(Component, Debug)]
-pub enum KoolAidMovement {
-    Moving(Vec<IVec2>),
-    Resting(Timer),
-}
-
-impl Default for KoolAidMovement {
-    fn default() -> Self {
-        Self::Resting(Timer::new(Duration::from_secs(0), TimerMode::Once))
-    }
-}
-
+#[allow(clippy::type_complexity)]
 fn apply_light_sensitivity(
-    mut mobs: Query<(Entity, &mut WobbleEffects, &mut Mob, &LightsUp)>,
+    mut mobs: Query<(Entity, &mut WobbleEffects, &mut Mob, &LightsUp), With<MapPos>>,
     mut ev_damage: EventWriter<MobDamageEvent>,
+    bestiary: Res<Bestiary>,
 ) {
     let mut rng = rand::thread_rng();
     for (entity, mut wobble, mut mob, lit) in mobs.iter_mut() {
-        let sensitivity = mob.kind.get_light_sensitivity();
+        let def = bestiary.def(mob.kind);
+        let sensitivity = def.light_sensitivity;
         if lit.is_lit && matches!(sensitivity, LightSensitivity::Yes | LightSensitivity::Very) {
             let mut angle = rng.r#gen::<f32>() - 0.5;
-            let lit_threshold = match mob.kind {
-                MobKind::Eyeball => 30.0,
-                _ => 1.0,
-            };
+            let lit_threshold = def.light_threshold;
             if lit.is_brightly_lit {
                 angle *= 2.0;
                 if sensitivity == LightSensitivity::Very {
@@ -326,6 +346,14 @@ fn apply_light_sensitivity(
     }
 }
 
+/// The shared maps mobs step downhill on, rebuilt by
+/// [`update_navigation_maps`].
+#[derive(SystemParam)]
+struct NavigationMaps<'w> {
+    player_distance: Res<'w, PlayerDistanceMap>,
+    hiding_spots: Res<'w, HidingSpotMap>,
+}
+
 #[allow(clippy::complexity)]
 fn move_mobs(
     mut commands: Commands,
@@ -336,7 +364,10 @@ fn move_mobs(
         &mut Transform,
         Option<&SawPlayer>,
         Option<&HeardPlayer>,
-        Option<&mut KoolAidMovement>,
+        Option<&mut ChargeState>,
+        Option<&mut PatrolRoute>,
+        &mut Facing,
+        Has<Asleep>,
     )>,
     player: Query<&MapPos, (With<Player>, Without<Mob>)>,
     mut walk_blocked_map: ResMut<WalkBlockedMap>,
@@ -344,147 +375,190 @@ fn move_mobs(
     vis_map: Res<PlayerVisibilityMap>,
     fov_map: Res<FovMap>,
     flashlight_map: Res<FlashlightMap>,
+    navigation_maps: NavigationMaps,
+    zones: Res<Zones>,
     time: Res<Time>,
     mut ev_bust: EventWriter<BustThroughWallEvent>,
     mut ev_player_damage: EventWriter<PlayerDamageEvent>,
+    bestiary: Res<Bestiary>,
+    difficulty: Res<Difficulty>,
+    mut claimed: Local<Vec<IVec2>>,
 ) {
+    let mut rng = rand::thread_rng();
     let player_pos = player.single();
-    for (entity, mut mob, mut mob_pos, transform, saw_player, heard_player, mut kool_aid) in
-        mobs.iter_mut()
+    for (
+        entity,
+        mut mob,
+        mut mob_pos,
+        transform,
+        saw_player,
+        heard_player,
+        mut charge,
+        mut patrol,
+        mut facing,
+        asleep,
+    ) in mobs.iter_mut()
     {
         mob.move_timer.tick(time.delta());
-        if mob.move_timer.finished() {
-            let last_known_player_pos = saw_player
+        if !mob.move_timer.finished() {
+            continue;
+        }
+        let def = bestiary.def(mob.kind);
+        let surroundings = Surroundings {
+            pos: mob_pos.0,
+            zone: zones.get(mob_pos.0).map(|zone| zone.rect),
+            asleep,
+            last_known_player_pos: saw_player
                 .map(|saw| saw.pos)
-                .or(heard_player.map(|heard| heard.pos));
-            let mut target_pos = match mob.kind {
-                MobKind::Hider => last_known_player_pos
-                    .filter(|p| {
-                        p.distance_squared(mob_pos.0) <= HIDER_CHASE_DISTANCE * HIDER_CHASE_DISTANCE
-                            || vis_map.0.contains(&mob_pos.0) && mob.damage > 0
+                .or(heard_player.map(|heard| heard.pos)),
+            hurt: mob.damage > 0,
+            walk_blocked_map: &walk_blocked_map,
+            sight_blocked_map: &sight_blocked_map,
+            vis_map: &vis_map,
+            fov_map: &fov_map,
+            hiding_spot_map: &navigation_maps.hiding_spots,
+        };
+        let (intent, gait) = decide(
+            &def.behaviours,
+            &surroundings,
+            Memory {
+                charge: charge.as_deref_mut(),
+                patrol: patrol.as_deref_mut(),
+            },
+            time.delta(),
+            &mut rng,
+        );
+        let move_pos = match intent {
+            Intent::Stay => None,
+            Intent::Step(step) => Some(step),
+            Intent::Toward(target_pos) => {
+                let follow_player_distance_map =
+                    target_pos == player_pos.0 && !gait.avoid_sight && !gait.avoid_doors;
+                follow_player_distance_map
+                    .then(|| {
+                        step_down(
+                            &navigation_maps.player_distance.0,
+                            mob_pos.0,
+                            &walk_blocked_map,
+                        )
+                    })
+                    .flatten()
+                    .or_else(|| {
+                        path_to(
+                            mob_pos.0,
+                            target_pos,
+                            gait,
+                            &walk_blocked_map,
+                            &sight_blocked_map,
+                            &fov_map,
+                            &flashlight_map,
+                        )
+                        .and_then(|path| path.get(1).copied())
                     })
-                    .or_else(|| find_hiding_spot(mob_pos.0, &walk_blocked_map, &sight_blocked_map)),
-                _ => last_known_player_pos,
-            };
-            if let Some(kool_aid) = kool_aid.as_deref_mut() {
-                target_pos = match kool_aid {
-                    KoolAidMovement::Moving(path) => {
-                        if path.is_empty() {
-                            *kool_aid = KoolAidMovement::Resting(Timer::new(
-                                Duration::from_secs(1),
-                                TimerMode::Once,
-                            ));
-                            None
-                        } else {
-                            path.first().copied()
-                        }
-                    }
-                    KoolAidMovement::Resting(timer) => {
-                        timer.tick(time.delta());
-                        if let Some(pos) = target_pos {
-                            if timer.finished() {
-                                let mut path: Vec<IVec2> =
-                                    WalkGrid::new(mob_pos.0.into(), pos.into())
-                                        .map(From::from)
-                                        .collect();
-                                // overshoot a bit
-                                let overshot = pos + (pos - mob_pos.0);
-                                path.extend(
-                                    WalkGrid::new(pos.into(), overshot.into())
-                                        .map(IVec2::from)
-                                        .take(KOOL_AID_OVERSHOOT),
-                                );
-                                *kool_aid = KoolAidMovement::Moving(path);
-                            }
-                            None
-                        } else {
-                            None
-                        }
-                    }
-                }
             }
-            if let Some(target_pos) = target_pos {
-                let avoid_player_sight = matches!(mob.kind, MobKind::Sculpture);
-                let avoid_doors = matches!(mob.kind, MobKind::Sculpture);
-                let avoid_player_flashlight = true;
-                let bust_through_walls = kool_aid.is_some();
-                let move_pos = if bust_through_walls {
-                    Some(target_pos)
-                } else {
-                    path_to(
-                        mob_pos.0,
-                        target_pos,
-                        &walk_blocked_map,
-                        &sight_blocked_map,
-                        &fov_map,
-                        &flashlight_map,
-                        avoid_player_sight,
-                        avoid_doors,
-                        avoid_player_flashlight,
-                        false,
-                    )
-                    .and_then(|path| path.get(1).copied())
-                };
-                if let Some(move_pos) = move_pos {
-                    if move_pos != player_pos.0 {
-                        // Sculpture can't move into sight or onto doors
-                        if !(matches!(mob.kind, MobKind::Sculpture)
-                            && (fov_map.0.contains(&move_pos)
-                                || fov_map.0.contains(&mob_pos.0)
-                                || sight_blocked_map.0.contains(&move_pos)))
-                        {
-                            walk_blocked_map.0.insert(move_pos);
-                            mob_pos.0 = move_pos;
-                            commands.entity(entity).insert(MoveAnimation {
-                                from: transform.translation.truncate(),
-                                to: mob_pos.to_vec2(),
-                                timer: Timer::new(mob.kind.get_move_delay() / 2, TimerMode::Once),
-                                ease: mob.kind.get_ease_function_for_movement(),
-                            });
-                            if bust_through_walls {
-                                ev_bust.send(BustThroughWallEvent(mob_pos.0));
-                            }
-                            if let Some(KoolAidMovement::Moving(path)) = kool_aid.as_deref_mut() {
-                                if !path.is_empty() {
-                                    path.remove(0);
-                                }
-                            }
-                            mob.move_timer.reset();
-                        }
-                    } else if !(matches!(mob.kind, MobKind::Sculpture)
-                        && fov_map.0.contains(&mob_pos.0))
-                    {
-                        // info!("{:?} damaging player", mob.kind);
-                        ev_player_damage.send(PlayerDamageEvent { damage: 1 });
-                        mob.move_timer.reset();
-                    }
-                }
+        };
+        let Some(move_pos) = move_pos else {
+            continue;
+        };
+        if move_pos != player_pos.0 {
+            // sneaky mobs can't move into sight or onto doors
+            if (gait.avoid_sight
+                && (fov_map.0.contains(&move_pos) || fov_map.0.contains(&mob_pos.0)))
+                || (gait.avoid_doors && sight_blocked_map.0.contains(&move_pos))
+            {
+                continue;
+            }
+            facing.turn(move_pos - mob_pos.0);
+            // keep other mobs off the tile for the rest of this frame
+            if walk_blocked_map.0.insert(move_pos) {
+                claimed.push(move_pos);
             }
+            mob_pos.0 = move_pos;
+            commands.entity(entity).insert(MoveAnimation {
+                from: transform.translation.truncate(),
+                to: mob_pos.to_vec2(),
+                timer: Timer::new(def.move_delay(*difficulty) / 2, TimerMode::Once),
+                ease: def.ease_function(),
+            });
+            if gait.through_walls {
+                ev_bust.send(BustThroughWallEvent(mob_pos.0));
+            }
+            if let Some(charge) = charge.as_deref_mut() {
+                charge.advance();
+            }
+            mob.move_timer.reset();
+        } else if !(gait.avoid_sight && fov_map.0.contains(&mob_pos.0)) {
+            facing.turn(player_pos.0 - mob_pos.0);
+            ev_player_damage.send(PlayerDamageEvent { damage: 1 });
+            mob.move_timer.reset();
         }
     }
+    // the spatial index picks up where the mobs really are next frame
+    for pos in claimed.drain(..) {
+        walk_blocked_map.0.remove(&pos);
+    }
 }
 
-fn spawn_kool_aid_man(
+/// Gives newly spawned patrolling mobs the nearest patrol route of their zone.
+fn assign_patrol_routes(
+    mut commands: Commands,
+    mobs: Query<(Entity, &Mob, &MapPos), Added<Mob>>,
+    zones: Res<Zones>,
+    bestiary: Res<Bestiary>,
+) {
+    for (entity, mob, pos) in mobs.iter() {
+        if !bestiary.def(mob.kind).patrols() {
+            continue;
+        }
+        let Some(zone) = zones.get(pos.0) else {
+            continue;
+        };
+        let nearest = zone.patrols.iter().min_by_key(|route| {
+            route
+                .iter()
+                .map(|p| p.distance_squared(pos.0))
+                .min()
+                .unwrap_or(i32::MAX)
+        });
+        if let Some(route) = nearest {
+            commands
+                .entity(entity)
+                .insert(PatrolRoute::join(route.clone(), pos.0));
+        }
+    }
+}
+
+/// Sends an ambusher, see [`crate::bestiary::MobDef::ambusher`], after the
+/// first player to shoot in a zone set up for ambushes.
+fn spawn_ambusher(
     mut ev_shoot: EventReader<ShootEvent>,
     mut ev_spawn: EventWriter<SpawnEvent>,
     mut spawned: Local<bool>,
     player_visibility_map: Res<PlayerVisibilityMap>,
     zones: Res<Zones>,
+    bestiary: Res<Bestiary>,
 ) {
     for shoot_event in ev_shoot.read() {
         if !*spawned {
-            if let Some(rect) = zones.0.get(2) {
-                let map_pos = MapPos::from_vec2(shoot_event.start);
-                if rect.contains(map_pos.0) {
+            let map_pos = MapPos::from_vec2(shoot_event.start);
+            if let Some(zone) = zones.get(map_pos.0) {
+                if zone.spec.ambush {
                     let mut rng = rand::thread_rng();
                     let spawn_rect = rogue_algebra::Rect::new_centered(map_pos.0.into(), 10, 10);
                     let legal = spawn_rect
                         .into_iter()
                         .map(IVec2::from)
-                        .filter(|pos| !player_visibility_map.0.contains(pos))
+                        .filter(|pos| !player_visibility_map.0.contains((*pos).into()))
                         .collect::<Vec<_>>();
-                    if let Some(spawn_pos) = legal.choose(&mut rng) {
-                        ev_spawn.send(SpawnEvent(*spawn_pos, Spawn::Mob(MobKind::KoolAidMan)));
+                    let ambushers = bestiary
+                        .kinds()
+                        .filter(|&kind| bestiary.def(kind).ambusher)
+                        .collect::<Vec<_>>();
+                    if let (Some(spawn_pos), Some(&kind)) =
+                        (legal.choose(&mut rng), ambushers.choose(&mut rng))
+                    {
+                        let spawn = Spawn::Mob(bestiary.name(kind).clone());
+                        ev_spawn.send(SpawnEvent(*spawn_pos, spawn));
                         *spawned = true;
                     }
                 }
@@ -499,12 +573,21 @@ struct BustThroughWallEvent(IVec2);
 fn bust_through_walls(
     mut commands: Commands,
     mut ev_bust: EventReader<BustThroughWallEvent>,
+    mut ev_noise: EventWriter<NoiseEvent>,
     map: Res<Map>,
     query: Query<Entity, With<Tile>>,
 ) {
     for BustThroughWallEvent(pos) in ev_bust.read() {
+        let mut busted = false;
         for entity in query.iter_many(map.get(*pos)) {
             commands.entity(entity).despawn();
+            busted = true;
+        }
+        if busted {
+            ev_noise.send(NoiseEvent {
+                pos: *pos,
+                loudness: noise::WALL_BREAKING,
+            });
         }
     }
 }
@@ -513,26 +596,82 @@ pub struct MobPlugin;
 
 impl Plugin for MobPlugin {
     fn build(&self, app: &mut App) {
+        app.insert_resource(load_bestiary());
         app.add_systems(
             Update,
             (
-                spawn_kool_aid_man,
+                spawn_ambusher,
                 update_mobs_seeing_player,
                 update_hearing_player,
                 forget_player,
                 damage_mobs,
+                assign_patrol_routes,
                 move_mobs,
                 bust_through_walls,
                 apply_light_sensitivity,
             )
                 .chain()
+                .after(update_spatial_index)
                 .after(update_visibility)
-                .after(update_walkability)
                 .after(update_fov_map)
                 .after(update_flashlight_map)
-                .after(update_lit),
+                .after(update_navigation_maps)
+                .after(update_lit)
+                .run_if(in_state(RunState::Playing)),
         )
         .add_event::<MobDamageEvent>()
-        .add_event::<BustThroughWallEvent>();
+        .add_event::<BustThroughWallEvent>()
+        .add_event::<NoiseEvent>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::{system::RunSystemOnce, world::CommandQueue};
+
+    use super::*;
+    use crate::{assets::GameAssets, bestiary::default_bestiary, spawn::spawn_entity};
+
+    #[test]
+    fn test_zombies_see_the_player_unless_asleep() {
+        let bestiary = default_bestiary();
+        assert!(bestiary.get(&MobName::ZOMBIE).unwrap().wake_loudness().is_none());
+        let mut world = World::new();
+        world.init_resource::<OpacityMap>();
+        world.init_resource::<SightBlockedMap>();
+        world.init_resource::<FlashlightMap>();
+        world.init_resource::<Events<PlayerMoveEvent>>();
+        world.insert_resource(bestiary.clone());
+        // just ahead of mobs facing the way they spawn
+        world.spawn((
+            Player {
+                damage: 0,
+                max_damage: 1,
+            },
+            MapPos(IVec2::new(0, -3)),
+        ));
+        let assets = GameAssets::placeholder();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let mut spawn = |x, name| {
+            let spawn = Spawn::Mob(name);
+            spawn_entity(
+                &mut commands,
+                &assets,
+                &bestiary,
+                Difficulty::Normal,
+                IVec2::new(x, 0),
+                &spawn,
+            )
+            .unwrap()
+            .id()
+        };
+        let zombie = spawn(0, MobName::ZOMBIE);
+        let sleeper = spawn(1, MobName::SLEEPING_ZOMBIE);
+        queue.apply(&mut world);
+
+        world.run_system_once(update_mobs_seeing_player).unwrap();
+        assert!(world.entity(zombie).contains::<SawPlayer>());
+        assert!(!world.entity(sleeper).contains::<SawPlayer>());
     }
 }
@@ -0,0 +1,132 @@
+//! Noises, and how far they carry. Sound spreads out from where it's made a
+//! tile at a time, losing a point of loudness for every tile it crosses and
+//! more for tiles that muffle it, like walls and doors; see
+//! [`TileKind::muffling`](crate::map::TileKind::muffling). Mobs that hear a
+//! noise go to see what made it.
+use bevy::prelude::*;
+use rogue_algebra::dijkstra::DijkstraMap;
+
+pub const GUNSHOT: i32 = 20;
+pub const WALL_BREAKING: i32 = 16;
+pub const RELOAD: i32 = 6;
+pub const DRY_FIRE: i32 = 4;
+pub const FOOTSTEP: i32 = 3;
+
+/// Something made a noise at a tile.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoiseEvent {
+    pub pos: IVec2,
+    pub loudness: i32,
+}
+
+/// How loud a noise is wherever it can be heard.
+pub struct Noise {
+    loudness: i32,
+    /// Loudness lost on the way to each tile.
+    falloff: DijkstraMap,
+}
+
+impl Noise {
+    /// Spreads a noise out from where it was made. `muffling` is the
+    /// loudness lost passing through a tile, on top of the 1 lost crossing
+    /// any tile.
+    pub fn propagate(
+        NoiseEvent { pos, loudness }: NoiseEvent,
+        mut muffling: impl FnMut(IVec2) -> i32,
+    ) -> Self {
+        let size = loudness.max(0) * 2 + 1;
+        let bound = rogue_algebra::Rect::new_centered(pos.into(), size, size);
+        let falloff = DijkstraMap::new(bound, &[pos.into()], |p| {
+            Some(1 + muffling(p.into()).max(0))
+        });
+        Noise { loudness, falloff }
+    }
+
+    /// How loud the noise is at `pos`, or `None` if it can't be heard there.
+    pub fn loudness_at(&self, pos: IVec2) -> Option<i32> {
+        let loudness = self.loudness - self.falloff.get(pos.into())?;
+        (loudness > 0).then_some(loudness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{level::DEFAULT_LEGEND, spawn::Spawn};
+
+    /// Makes `loudness` noise at `S` on a map drawn with the level file
+    /// characters for tiles, like `#` for a wall, and returns how loud it is
+    /// at `a`.
+    fn loudness_at_a(map: &str, loudness: i32) -> Option<i32> {
+        let rows = map.trim().lines().collect::<Vec<_>>();
+        let tiles = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .map(move |(x, c)| (IVec2::new(x as i32, y as i32), c))
+            })
+            .collect::<Vec<_>>();
+        let find = |c| tiles.iter().find(|&&(_, t)| t == c).unwrap().0;
+        let muffling = |pos| match tiles.iter().find(|&&(p, _)| p == pos) {
+            Some(&(_, c)) => DEFAULT_LEGEND
+                .iter()
+                .find_map(|(l, spawn)| match spawn {
+                    Spawn::Tile(tile) if *l == c => Some(tile.muffling()),
+                    _ => None,
+                })
+                .unwrap_or(0),
+            // no sneaking round the outside of the map
+            None => 99,
+        };
+        let noise = Noise::propagate(
+            NoiseEvent {
+                pos: find('S'),
+                loudness,
+            },
+            muffling,
+        );
+        noise.loudness_at(find('a'))
+    }
+
+    #[test]
+    fn test_noise_falls_off_with_distance() {
+        let open = "
+S...a
+.....";
+        assert_eq!(loudness_at_a(open, 10), Some(6));
+        assert_eq!(loudness_at_a(open, 5), Some(1));
+        assert_eq!(loudness_at_a(open, 4), None);
+        assert_eq!(loudness_at_a(open, 0), None);
+    }
+
+    #[test]
+    fn test_noise_is_muffled() {
+        // through the wall, or the long way round
+        let wall = "
+..#..
+S.#.a
+..#..";
+        assert_eq!(loudness_at_a(wall, 20), Some(8));
+        let long_way = "
+.....
+..#..
+S.#.a
+..#..";
+        assert_eq!(loudness_at_a(long_way, 20), Some(12));
+        let door = "
+###.
+S.+a
+####";
+        assert_eq!(loudness_at_a(door, 10), Some(3));
+        let bushes = "
+S\"\"a";
+        assert_eq!(loudness_at_a(bushes, 10), Some(3));
+        let sealed = "
+#####
+#S#a#
+#####";
+        assert_eq!(loudness_at_a(sealed, 10), None);
+    }
+}
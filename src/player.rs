@@ -2,27 +2,31 @@ use rand::seq::SliceRandom;
 use std::{collections::HashMap, f32::consts::PI, time::Duration};
 
 use bevy::{
+    ecs::system::SystemParam,
     input::mouse::MouseWheel,
     math::bounding::{Aabb2d, RayCast2d},
     prelude::*,
     render::view::RenderLayers,
 };
 use rand::Rng as _;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     animation::{MoveAnimation, TextEvent, WobbleEffects},
     assets::{GameAssets, SpriteKind},
     despawn_after::DespawnAfter,
+    difficulty::{Difficulty, RunState},
     lighting::UI_LAYER,
-    map::{BlocksMovement, Map, MapPos, Pickup, Tile, TILE_HEIGHT, TILE_WIDTH},
+    map::{BlocksMovement, ItemKind, Map, MapPos, Pickup, Tile, TILE_HEIGHT, TILE_WIDTH},
     mob::{Mob, MobDamageEvent},
+    noise::{self, NoiseEvent},
     renderer::PlaneMouseMovedEvent,
     ui::UiSettings,
     GameState, PrimaryCamera, SDF_RES, Z_PLAYER,
 };
 
 const PLAYER_MOVE_DELAY: Duration = Duration::from_millis(350);
-const PLAYER_START: IVec2 = IVec2::new(0, 0);
+pub const PLAYER_START: IVec2 = IVec2::new(0, 0);
 const PLAYER_FOCUS_TIME_SECS: f32 = 2.0;
 const PLAYER_MOVE_FOCUS_PENALTY_SECS: f32 = 1.0;
 const PLAYER_SHOOT_FOCUS_PENALTY_SECS: f32 = 0.5;
@@ -34,11 +38,13 @@ pub const FLASHLIGHT_MAX_BATTERY: f32 = 1.0;
 #[derive(Component)]
 pub struct Player {
     pub damage: i32,
+    /// Damage it takes to kill the player, set by the difficulty.
+    pub max_damage: i32,
 }
 
 impl Player {
     pub fn is_dead(&self) -> bool {
-        self.damage >= PLAYER_MAX_DAMAGE
+        self.damage >= self.max_damage
     }
 }
 
@@ -81,7 +87,7 @@ struct ShootState {
     reloading: Option<Timer>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum GunType {
     Pistol,
     Shotgun,
@@ -150,6 +156,28 @@ pub struct Inventory {
     pub guns: HashMap<GunType, GunState>,
 }
 
+impl Inventory {
+    /// Takes an item the player picked up or found. Ammo loaded in a gun
+    /// beyond what it holds goes to the spare ammo.
+    pub fn add(&mut self, item: ItemKind) {
+        match item {
+            ItemKind::Ammo(gun_type, num_ammo) => {
+                self.guns.entry(gun_type).or_default().ammo_available += num_ammo;
+            }
+            ItemKind::Gun(gun_type, ammo) => {
+                let gun_state = self.guns.entry(gun_type).or_default();
+                gun_state.present = true;
+                gun_state.ammo_loaded += ammo;
+                let gun_info = gun_type.get_info();
+                if gun_state.ammo_loaded > gun_info.max_load {
+                    gun_state.ammo_available += gun_state.ammo_loaded - gun_info.max_load;
+                    gun_state.ammo_loaded = gun_info.max_load;
+                }
+            }
+        }
+    }
+}
+
 fn swap_gun(
     mut commands: Commands,
     mut inventory: ResMut<Inventory>,
@@ -243,11 +271,12 @@ impl Default for FlashlightInfo {
 fn update_flashlight(
     mouse_button: Res<ButtonInput<MouseButton>>,
     mut flashlight_info: ResMut<FlashlightInfo>,
+    difficulty: Res<Difficulty>,
     time: Res<Time>,
 ) {
     flashlight_info.ease_timer.tick(time.delta());
     flashlight_info.battery = if flashlight_info.focused {
-        flashlight_info.battery - time.delta_secs() / 8.0
+        flashlight_info.battery - time.delta_secs() * difficulty.flashlight_drain_scale() / 8.0
     } else {
         flashlight_info.battery + time.delta_secs() / 16.0
     };
@@ -361,6 +390,13 @@ fn update_reload_indicator(
     transform.rotation = Quat::from_rotation_z(fraction_left * PI);
 }
 
+/// The buttons [`update_shooting`] reads: left click fires and R reloads.
+#[derive(SystemParam)]
+struct ShootInput<'w> {
+    mouse_button: Res<'w, ButtonInput<MouseButton>>,
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+}
+
 #[allow(clippy::complexity)]
 fn update_shooting(
     mut commands: Commands,
@@ -368,8 +404,7 @@ fn update_shooting(
     mut shoot_state: ResMut<ShootState>,
     mouse_world_coords: Res<MouseWorldCoords>,
     time: Res<Time>,
-    mouse_button: Res<ButtonInput<MouseButton>>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    input: ShootInput,
     map: Res<Map>,
     mobs: Query<(Entity, &Transform), (With<Mob>, Without<Player>)>,
     tiles: Query<(&Tile, &Transform), (Without<Mob>, Without<Player>)>,
@@ -378,6 +413,7 @@ fn update_shooting(
     mut ev_spawn_bullet: EventWriter<ShootEvent>,
     mut ev_damage_mob: EventWriter<MobDamageEvent>,
     mut ev_player_move: EventReader<PlayerMoveEvent>,
+    mut ev_noise: EventWriter<NoiseEvent>,
     mut inventory: ResMut<Inventory>,
 ) {
     let (player_pos, player) = player_query.single();
@@ -397,6 +433,13 @@ fn update_shooting(
     shoot_state.focus = shoot_state.focus.clamp(0.0, 1.0);
 
     let mouse_offset = mouse_world_coords.0 - player_pos.translation.truncate();
+    let player_map_pos = MapPos::from_vec3(player_pos.translation).0;
+    let mut make_noise = |loudness| {
+        ev_noise.send(NoiseEvent {
+            pos: player_map_pos,
+            loudness,
+        });
+    };
     let equipped = inventory.equipped;
     let equipped_info = inventory.equipped.get_info();
     let gun_state = inventory.guns.entry(equipped).or_default();
@@ -406,12 +449,13 @@ fn update_shooting(
     );
     shoot_state.jitter_radians = jitter_degrees * PI / 180.0;
 
-    if keyboard_input.pressed(KeyCode::KeyR)
+    if input.keyboard_input.pressed(KeyCode::KeyR)
         && shoot_state.reloading.is_none()
         && gun_state.ammo_available > 0
         && gun_state.ammo_loaded < equipped_info.max_load
     {
         shoot_state.reloading = Some(Timer::new(equipped_info.reload_time, TimerMode::Once));
+        make_noise(noise::RELOAD);
 
         // TODO(kazasrinivas3): Clean this up at some point...
         if let Some(sound) = match equipped {
@@ -445,7 +489,7 @@ fn update_shooting(
         }
     }
 
-    if shoot_state.reloading.is_none() && mouse_button.just_pressed(MouseButton::Left) {
+    if shoot_state.reloading.is_none() && input.mouse_button.just_pressed(MouseButton::Left) {
         let sound = if gun_state.ammo_loaded > 0 {
             match equipped {
                 GunType::Pistol => &assets.sfx.fire_pistol,
@@ -469,11 +513,13 @@ fn update_shooting(
         }
         // shoot
         if gun_state.ammo_loaded == 0 && !settings.inf_ammo {
+            make_noise(noise::DRY_FIRE);
             return;
         }
         if !settings.inf_ammo {
             gun_state.ammo_loaded -= 1;
         }
+        make_noise(noise::GUNSHOT);
 
         // TODO(kazasrinivas3): Clean this up at some point...
         if let Some(sound) = match equipped {
@@ -496,8 +542,7 @@ fn update_shooting(
             let line_start = player_pos.translation.truncate();
             shoot_state.focus -= PLAYER_SHOOT_FOCUS_PENALTY_SECS / PLAYER_FOCUS_TIME_SECS;
             let mut collisions = vec![];
-            let player_pos_ivec2 = MapPos::from_vec3(player_pos.translation).0;
-            for (entity, transform) in mobs.iter_many(map.get_nearby(player_pos_ivec2, 100)) {
+            for (entity, transform) in mobs.iter_many(map.get_nearby(player_map_pos, 100)) {
                 collisions.push((
                     Some(entity),
                     Aabb2d::new(
@@ -506,7 +551,7 @@ fn update_shooting(
                     ),
                 ));
             }
-            for (tile, transform) in tiles.iter_many(map.get_nearby(player_pos_ivec2, 100)) {
+            for (tile, transform) in tiles.iter_many(map.get_nearby(player_map_pos, 100)) {
                 if tile.0.blocks_movement() {
                     collisions.push((
                         None,
@@ -606,6 +651,7 @@ fn move_player(
     tile_map: Res<Map>,
     time: Res<Time>,
     mut ev_player_move: EventWriter<PlayerMoveEvent>,
+    mut ev_noise: EventWriter<NoiseEvent>,
 ) {
     timer.0.tick(time.delta());
     if timer.0.finished() {
@@ -667,6 +713,10 @@ fn move_player(
                     source: from,
                     dest: world_pos.clone(),
                 });
+                ev_noise.send(NoiseEvent {
+                    pos: world_pos.0,
+                    loudness: noise::FOOTSTEP,
+                });
             }
         }
     }
@@ -685,21 +735,7 @@ fn pickup(
             q_pickups.iter_many(tile_map.0.get(&dest.0).unwrap_or(&vec![]))
         {
             commands.entity(entity).despawn();
-            match kind {
-                crate::map::ItemKind::Ammo(gun_type, num_ammo) => {
-                    inventory.guns.entry(*gun_type).or_default().ammo_available += num_ammo;
-                }
-                crate::map::ItemKind::Gun(gun_type, ammo) => {
-                    let gun_state = inventory.guns.entry(*gun_type).or_default();
-                    gun_state.present = true;
-                    gun_state.ammo_loaded += *ammo;
-                    let gun_info = gun_type.get_info();
-                    if gun_state.ammo_loaded > gun_info.max_load {
-                        gun_state.ammo_available += gun_state.ammo_loaded - gun_info.max_load;
-                        gun_state.ammo_loaded = gun_info.max_load;
-                    }
-                }
-            }
+            inventory.add(*kind);
             ev_text.send(TextEvent {
                 text: format!("got {kind}!"),
                 position: dest.to_vec2(),
@@ -710,11 +746,14 @@ fn pickup(
     }
 }
 
-fn startup(mut commands: Commands, assets: Res<GameAssets>) {
+fn startup(mut commands: Commands, assets: Res<GameAssets>, difficulty: Res<Difficulty>) {
     let player_start_translation =
         Vec3::new(PLAYER_START.x as f32, PLAYER_START.y as f32, Z_PLAYER);
     commands.spawn((
-        Player { damage: 0 },
+        Player {
+            damage: 0,
+            max_damage: difficulty.player_max_damage(),
+        },
         MapPos(PLAYER_START),
         assets.get_sprite(SpriteKind::Player),
         Transform::from_translation(player_start_translation),
@@ -751,7 +790,7 @@ pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
-            Startup,
+            OnEnter(RunState::Playing),
             (startup, make_sight_lines, make_reload_indicator).chain(),
         )
         .add_systems(
@@ -768,7 +807,8 @@ impl Plugin for PlayerPlugin {
                 update_reload_indicator,
                 damage_player,
             )
-                .chain(),
+                .chain()
+                .run_if(in_state(RunState::Playing)),
         )
         .add_event::<ShootEvent>()
         .add_event::<PlayerMoveEvent>()
@@ -0,0 +1,166 @@
+//! Hand-authored room layouts ("prefabs") that map generation stamps into BSP
+//! rooms for set pieces like an armory or a sculpture gallery.
+//!
+//! Prefabs live in `assets/prefabs.ron` and are drawn with the level file
+//! characters (see [`crate::level`]), plus a per-prefab legend for anything
+//! else. A prefab is always stamped with at least one tile of open floor
+//! between it and the room's walls, so the doors into the room stay connected;
+//! doors (`+`) in a prefab's own walls are its anchors to that ring of floor.
+use std::collections::HashMap;
+
+use rogue_algebra::Offset;
+use serde::{Deserialize, Serialize};
+
+use crate::{level::DEFAULT_LEGEND, mapgen::ZoneGenerator, spawn::Spawn};
+
+/// Prefabs shipped with the game.
+pub const DEFAULT_PREFABS: &str = include_str!("../assets/prefabs.ron");
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Prefab {
+    pub name: String,
+    /// Generators whose rooms this prefab can be stamped into.
+    pub generators: Vec<ZoneGenerator>,
+    /// Rows from north to south. `.` is open floor.
+    pub rows: Vec<String>,
+    /// Spawns for characters the level format doesn't know.
+    #[serde(default)]
+    pub legend: Vec<(char, Vec<Spawn>)>,
+    /// Stamped at most once per zone, like a set piece holding a landmark.
+    #[serde(default)]
+    pub unique: bool,
+}
+
+#[derive(Debug)]
+pub enum PrefabError {
+    Ron(ron::error::SpannedError),
+    /// A prefab whose rows aren't all the same length, or that has no rows.
+    NotRectangular(String),
+    /// A character that is neither in the level legend nor the prefab's.
+    UnknownChar(String, char),
+}
+
+impl std::fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrefabError::Ron(e) => write!(f, "{e}"),
+            PrefabError::NotRectangular(name) => write!(f, "prefab {name:?} is not rectangular"),
+            PrefabError::UnknownChar(name, c) => {
+                write!(f, "prefab {name:?}: {c:?} is not in the legend")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrefabError {}
+
+impl Prefab {
+    pub fn width(&self) -> i32 {
+        self.rows
+            .first()
+            .map_or(0, |row| row.chars().count() as i32)
+    }
+
+    pub fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+
+    /// Every tile of the prefab as an offset from its top-left corner (y going
+    /// north, as on the map) with what spawns there; empty for open floor.
+    pub fn cells(&self) -> Result<Vec<(Offset, Vec<Spawn>)>, PrefabError> {
+        if self.width() == 0
+            || self
+                .rows
+                .iter()
+                .any(|row| row.chars().count() as i32 != self.width())
+        {
+            return Err(PrefabError::NotRectangular(self.name.clone()));
+        }
+        let legend: HashMap<char, Vec<Spawn>> = DEFAULT_LEGEND
+            .iter()
+            .map(|(c, spawn)| (*c, vec![spawn.clone()]))
+            .chain(self.legend.iter().cloned())
+            .collect();
+        let mut cells = Vec::new();
+        for (row, line) in self.rows.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                let spawns = match c {
+                    '.' => vec![],
+                    c => legend
+                        .get(&c)
+                        .cloned()
+                        .ok_or_else(|| PrefabError::UnknownChar(self.name.clone(), c))?,
+                };
+                cells.push((Offset::new(col as i32, -(row as i32)), spawns));
+            }
+        }
+        Ok(cells)
+    }
+}
+
+/// Parses a prefab list, checking that every prefab can be laid out.
+pub fn parse_prefabs(ron: &str) -> Result<Vec<Prefab>, PrefabError> {
+    let prefabs: Vec<Prefab> = ron::from_str(ron).map_err(PrefabError::Ron)?;
+    for prefab in &prefabs {
+        prefab.cells()?;
+    }
+    Ok(prefabs)
+}
+
+pub fn default_prefabs() -> Vec<Prefab> {
+    parse_prefabs(DEFAULT_PREFABS).expect("default prefabs should parse")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rogue_algebra::Pos;
+
+    use super::*;
+
+    #[test]
+    fn test_default_prefabs_open_onto_their_border() {
+        // Everything walkable in a prefab must be reachable from the ring of
+        // floor it gets stamped inside.
+        for prefab in default_prefabs() {
+            let walkable = prefab
+                .cells()
+                .unwrap()
+                .into_iter()
+                .filter(|(_, spawns)| {
+                    !spawns
+                        .iter()
+                        .any(|s| matches!(s, Spawn::Tile(t) if t.blocks_movement()))
+                })
+                .map(|(offset, _)| Pos::new(offset.x, offset.y))
+                .collect::<HashSet<_>>();
+            let (width, height) = (prefab.width(), prefab.height());
+            let mut reached = walkable
+                .iter()
+                .copied()
+                .filter(|p| p.x == 0 || p.x == width - 1 || p.y == 0 || p.y == 1 - height)
+                .collect::<Vec<_>>();
+            let mut seen = reached.iter().copied().collect::<HashSet<_>>();
+            while let Some(pos) = reached.pop() {
+                for next in pos.adjacent_cardinal() {
+                    if walkable.contains(&next) && seen.insert(next) {
+                        reached.push(next);
+                    }
+                }
+            }
+            assert_eq!(seen, walkable, "{} has sealed-off floor", prefab.name);
+        }
+
+        let bad = r#"[(name: "x", generators: [], rows: ["..", "."])]"#;
+        assert!(matches!(
+            parse_prefabs(bad),
+            Err(PrefabError::NotRectangular(_))
+        ));
+        let bad = r#"[(name: "x", generators: [], rows: [".?"])]"#;
+        assert!(matches!(
+            parse_prefabs(bad),
+            Err(PrefabError::UnknownChar(_, '?'))
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+//! Renders a generated map to the terminal or to a PNG, one tile per
+//! character or pixel, without a window or GPU.
+use std::io::Write;
+
+use bevy::math::IVec2;
+
+use crate::{
+    bestiary::Bestiary,
+    level::DEFAULT_LEGEND,
+    map::{ItemKind, TileKind},
+    mapgen::MapgenResult,
+    mob::MobName,
+    player::PLAYER_START,
+    spawn::Spawn,
+};
+
+const FLOOR_COLOR: [u8; 3] = [24, 24, 24];
+const START_COLOR: [u8; 3] = [255, 255, 255];
+
+fn tile_color(tile: TileKind) -> [u8; 3] {
+    match tile {
+        TileKind::Wall => [110, 110, 110],
+        TileKind::Door => [150, 100, 50],
+        TileKind::Bush => [50, 120, 50],
+        TileKind::Tree => [20, 75, 20],
+        TileKind::Crate => [170, 130, 80],
+        TileKind::ShippingContainer => [160, 70, 40],
+        TileKind::Lever | TileKind::LeverPulled => [230, 200, 40],
+        TileKind::StairsDown | TileKind::StairsUp => [200, 200, 255],
+    }
+}
+
+fn mob_color(mob: &MobName, bestiary: &Bestiary) -> [u8; 3] {
+    if bestiary.get(mob).is_some_and(|def| def.boss) {
+        [255, 0, 255]
+    } else {
+        [230, 40, 40]
+    }
+}
+
+fn item_color(item: ItemKind) -> [u8; 3] {
+    match item {
+        ItemKind::Ammo(..) => [60, 200, 230],
+        ItemKind::Gun(..) => [40, 120, 255],
+    }
+}
+
+/// The spawn that stands out most on a tile: mobs over items over tiles.
+fn marker(spawns: &[Spawn]) -> Option<&Spawn> {
+    spawns.iter().max_by_key(|spawn| match spawn {
+        Spawn::Tile(_) => 0,
+        Spawn::Item(_) => 1,
+        Spawn::Mob(_) => 2,
+    })
+}
+
+fn color(spawn: Option<&Spawn>, bestiary: &Bestiary) -> [u8; 3] {
+    match spawn {
+        None => FLOOR_COLOR,
+        Some(Spawn::Tile(tile)) => tile_color(*tile),
+        Some(Spawn::Mob(mob)) => mob_color(mob, bestiary),
+        Some(Spawn::Item(item)) => item_color(*item),
+    }
+}
+
+fn symbol(spawn: Option<&Spawn>) -> char {
+    match spawn {
+        None => '.',
+        Some(Spawn::Item(ItemKind::Ammo(..))) => '$',
+        Some(Spawn::Item(ItemKind::Gun(..))) => '!',
+        Some(spawn) => DEFAULT_LEGEND
+            .iter()
+            .find(|(_, s)| s == spawn)
+            .map_or('?', |(c, _)| *c),
+    }
+}
+
+/// Calls `f` with the position and marker of every tile, row by row from the
+/// top.
+fn for_each_tile(result: &MapgenResult, mut f: impl FnMut(IVec2, Option<&Spawn>)) {
+    let bounds = result.bounds();
+    for y in (bounds.min.y..=bounds.max.y).rev() {
+        for x in bounds.min.x..=bounds.max.x {
+            let pos = IVec2::new(x, y);
+            f(pos, result.spawns.get(&pos).and_then(|s| marker(s)));
+        }
+    }
+}
+
+/// Draws the map with ANSI truecolor escapes, one character per tile, using
+/// the level file characters for tiles and mobs. Bosses in `bestiary` stand
+/// out from other mobs.
+pub fn to_ansi(result: &MapgenResult, bestiary: &Bestiary) -> String {
+    let bounds = result.bounds();
+    let mut out = String::new();
+    let mut current_color = None;
+    for_each_tile(result, |pos, spawn| {
+        let (color, c) = if spawn.is_none() && pos == PLAYER_START {
+            (START_COLOR, '@')
+        } else {
+            (color(spawn, bestiary), symbol(spawn))
+        };
+        if current_color != Some(color) {
+            let [r, g, b] = color;
+            out.push_str(&format!("\x1b[38;2;{r};{g};{b}m"));
+            current_color = Some(color);
+        }
+        out.push(c);
+        if pos.x == bounds.max.x {
+            out.push_str("\x1b[0m\n");
+            current_color = None;
+        }
+    });
+    out
+}
+
+/// Writes the map as an RGB PNG, one pixel per tile.
+pub fn write_png(
+    result: &MapgenResult,
+    bestiary: &Bestiary,
+    writer: impl Write,
+) -> Result<(), png::EncodingError> {
+    let bounds = result.bounds();
+    let mut pixels = Vec::new();
+    for_each_tile(result, |pos, spawn| {
+        pixels.extend(if spawn.is_none() && pos == PLAYER_START {
+            START_COLOR
+        } else {
+            color(spawn, bestiary)
+        });
+    });
+    let mut encoder = png::Encoder::new(
+        writer,
+        bounds.width() as u32 + 1,
+        bounds.height() as u32 + 1,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bestiary::default_bestiary,
+        mapgen::{default_zone_specs, gen_map},
+    };
+
+    #[test]
+    fn test_preview_size() {
+        let result = gen_map(0, &default_zone_specs()).unwrap();
+        let bounds = result.bounds();
+        let (width, height) = (bounds.width() as u32 + 1, bounds.height() as u32 + 1);
+
+        let bestiary = default_bestiary();
+        let ansi = to_ansi(&result, &bestiary);
+        assert_eq!(ansi.lines().count() as u32, height);
+        assert!(ansi.contains('@'));
+
+        let mut png = Vec::new();
+        write_png(&result, &bestiary, &mut png).unwrap();
+        let info = png::Decoder::new(std::io::Cursor::new(png))
+            .read_info()
+            .unwrap();
+        assert_eq!((info.info().width, info.info().height), (width, height));
+    }
+}
@@ -1,12 +1,25 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     assets::GameAssets,
-    map::{Map, MapPos},
-    mob::{HeardPlayer, Mob, MobKind, SawPlayer},
+    bestiary::Bestiary,
+    map::{Map, MapPos, Zones},
+    mob::{HeardPlayer, Mob, SawPlayer},
     player::Player,
 };
 
+/// Layer of the soundtrack. The base layer always plays until the boss shows
+/// up; the others fade in when a zone or a mob that saw the player calls for them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MusicLayer {
+    #[default]
+    Base,
+    Active,
+    Monk,
+    Boss,
+}
+
 #[derive(Component)]
 struct BaseTrack;
 
@@ -99,6 +112,7 @@ fn adjust_radio_static(
     seen_mobs: Query<(Entity, &MapPos, &Mob), With<crate::mob::SeesPlayer>>,
     query_radio_track: Query<Entity, With<RadioStaticTrack>>,
     map: Res<Map>,
+    bestiary: Res<Bestiary>,
 ) {
     let Ok(player_pos) = player.get_single() else {
         return;
@@ -112,7 +126,7 @@ fn adjust_radio_static(
     let mut closest_enemy_dist: f32 = 150.0;
     for (_, pos, _) in heard_mobs
         .iter_many(map.get_nearby(player_pos.0, HEARING_RADIUS))
-        .filter(|(_, _, mob)| matches!(mob.kind, MobKind::Zombie))
+        .filter(|(_, _, mob)| bestiary.def(mob.kind).radio_static)
     {
         closest_enemy_dist =
             closest_enemy_dist.min((player_pos.to_vec2() - pos.to_vec2()).length());
@@ -120,7 +134,7 @@ fn adjust_radio_static(
 
     for (_, pos, _) in seen_mobs
         .iter_many(map.get_nearby(player_pos.0, HEARING_RADIUS))
-        .filter(|(_, _, mob)| matches!(mob.kind, MobKind::Zombie))
+        .filter(|(_, _, mob)| bestiary.def(mob.kind).radio_static)
     {
         closest_enemy_dist =
             closest_enemy_dist.min((player_pos.to_vec2() - pos.to_vec2()).length());
@@ -140,14 +154,17 @@ fn adjust_radio_static(
     }
 }
 
-#[allow(clippy::type_complexity)]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 fn update_mob_audio(
     mut commands: Commands,
-    q_saw_player: Query<&Mob, Or<(With<SawPlayer>, With<HeardPlayer>)>>,
+    q_saw_player: Query<&Mob, (Or<(With<SawPlayer>, With<HeardPlayer>)>, With<MapPos>)>,
     query_base_track: Query<Entity, With<BaseTrack>>,
     query_active_track: Query<(Entity, Option<&FadeIn>, Option<&FadeOut>), With<ActiveTrack>>,
     query_monk_track: Query<(Entity, Option<&FadeIn>, Option<&FadeOut>), With<MonkTrack>>,
     query_boss_track: Query<(Entity, Option<&FadeIn>), With<BossTrack>>,
+    player: Query<&MapPos, With<Player>>,
+    zones: Res<Zones>,
+    bestiary: Res<Bestiary>,
 ) {
     let Ok(base_track) = query_base_track.get_single() else {
         return;
@@ -163,15 +180,21 @@ fn update_mob_audio(
         return;
     };
 
-    let should_play_active = q_saw_player
-        .iter()
-        .any(|mob| mob.kind == MobKind::KoolAidMan);
-    let should_play_monk = q_saw_player
-        .iter()
-        .any(|mob| (mob.kind == MobKind::Sculpture));
-    let should_play_boss = q_saw_player
-        .iter()
-        .any(|mob| (mob.kind == MobKind::Eyeball));
+    let zone_layer = player
+        .get_single()
+        .ok()
+        .and_then(|pos| zones.get(pos.0))
+        .map(|zone| zone.spec.music)
+        .unwrap_or_default();
+    let plays = |layer| {
+        zone_layer == layer
+            || q_saw_player
+                .iter()
+                .any(|mob| bestiary.def(mob.kind).music == Some(layer))
+    };
+    let should_play_active = plays(MusicLayer::Active);
+    let should_play_monk = plays(MusicLayer::Monk);
+    let should_play_boss = plays(MusicLayer::Boss);
 
     if should_play_active && active_fading_in.is_none() && !should_play_boss {
         commands
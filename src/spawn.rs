@@ -1,20 +1,25 @@
 use bevy::{prelude::*, render::view::RenderLayers};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     Eyeball, Z_ITEMS, Z_MOBS, Z_TILES,
     animation::WobbleEffects,
     assets::{GameAssets, SpriteKind},
+    behaviour::{Asleep, ChargeState},
+    bestiary::Bestiary,
+    difficulty::{Difficulty, RunState},
     map::{
         BlocksMovement, BlocksSight, ItemKind, LightsUp, MapPos, Pickup, TILE_HEIGHT, TILE_WIDTH,
         Tile, TileKind,
     },
-    mob::{HearsPlayer, KoolAidMovement, Mob, MobKind, SeesPlayer},
+    mob::{HearsPlayer, Mob, MobName, SeesPlayer},
+    vision::Facing,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Spawn {
     Tile(TileKind),
-    Mob(MobKind),
+    Mob(MobName),
     Item(ItemKind),
 }
 
@@ -34,66 +39,107 @@ pub struct SpawnEvent(pub IVec2, pub Spawn);
 pub fn spawn(
     mut commands: Commands,
     world_assets: Res<GameAssets>,
+    bestiary: Res<Bestiary>,
+    difficulty: Res<Difficulty>,
     mut ev_spawn: EventReader<SpawnEvent>,
 ) {
     for SpawnEvent(pos, spawn) in ev_spawn.read() {
-        let sprite = world_assets.get_sprite(SpriteKind::Spawn(spawn.clone()));
-        let z = match spawn {
-            Spawn::Tile(..) => Z_TILES,
-            Spawn::Mob(..) => Z_MOBS,
-            Spawn::Item(..) => Z_ITEMS,
-        };
-        let mut entity_commands = commands.spawn((
-            sprite,
-            MapPos(*pos),
-            Transform::from_translation(Vec3::new(
-                TILE_WIDTH * pos.x as f32,
-                TILE_HEIGHT * pos.y as f32,
-                z,
-            )),
-            RenderLayers::layer(1),
-        ));
-        if spawn.blocks_movement() {
-            entity_commands.insert(BlocksMovement);
+        spawn_entity(
+            &mut commands,
+            &world_assets,
+            &bestiary,
+            *difficulty,
+            *pos,
+            spawn,
+        );
+    }
+}
+
+/// Spawns the entity for `spawn` at `pos` right away, for callers that need
+/// to adjust it afterwards. Mobs missing from the bestiary aren't spawned.
+pub fn spawn_entity<'a>(
+    commands: &'a mut Commands,
+    world_assets: &GameAssets,
+    bestiary: &Bestiary,
+    difficulty: Difficulty,
+    pos: IVec2,
+    spawn: &Spawn,
+) -> Option<EntityCommands<'a>> {
+    let sprite_kind = match spawn {
+        Spawn::Tile(t) => SpriteKind::Tile(*t),
+        Spawn::Item(i) => SpriteKind::Item(*i),
+        Spawn::Mob(name) => {
+            let Some(def) = bestiary.get(name) else {
+                error!("can't spawn {name}, it isn't in the bestiary");
+                return None;
+            };
+            SpriteKind::Mob(def.sprite)
+        }
+    };
+    let sprite = world_assets.get_sprite(sprite_kind);
+    let z = match spawn {
+        Spawn::Tile(..) => Z_TILES,
+        Spawn::Mob(..) => Z_MOBS,
+        Spawn::Item(..) => Z_ITEMS,
+    };
+    let mut entity_commands = commands.spawn((
+        sprite,
+        MapPos(pos),
+        Transform::from_translation(Vec3::new(
+            TILE_WIDTH * pos.x as f32,
+            TILE_HEIGHT * pos.y as f32,
+            z,
+        )),
+        RenderLayers::layer(1),
+    ));
+    if spawn.blocks_movement() {
+        entity_commands.insert(BlocksMovement);
+    }
+    match spawn {
+        Spawn::Tile(t) => {
+            if t.blocks_sight() {
+                entity_commands.insert(BlocksSight);
+            }
+            entity_commands.insert(Tile(*t));
         }
-        match spawn {
-            Spawn::Tile(t) => {
-                if t.blocks_sight() {
-                    entity_commands.insert(BlocksSight);
-                }
-                entity_commands.insert(Tile(*t));
+        Spawn::Mob(name) => {
+            let kind = bestiary.kind(name).unwrap();
+            let def = bestiary.def(kind);
+            entity_commands.insert((
+                SeesPlayer,
+                Mob {
+                    move_timer: Timer::new(def.move_delay(difficulty), TimerMode::Once),
+                    damage: 0,
+                    kind,
+                },
+                LightsUp::default(),
+                WobbleEffects::default(),
+                Facing::default(),
+            ));
+            if def.hears_player {
+                entity_commands.insert(HearsPlayer);
             }
-            Spawn::Mob(kind) => {
-                entity_commands.insert((
-                    SeesPlayer,
-                    Mob {
-                        move_timer: Timer::new(kind.get_move_delay(), TimerMode::Once),
-                        damage: 0,
-                        kind: *kind,
-                    },
-                    LightsUp::default(),
-                    WobbleEffects::default(),
-                ));
-                if kind.hears_player() {
-                    entity_commands.insert(HearsPlayer);
-                }
-                if let MobKind::KoolAidMan = kind {
-                    entity_commands.insert(KoolAidMovement::default());
-                } else if let MobKind::Eyeball = kind {
-                    entity_commands.insert(Eyeball);
-                }
+            if def.charges() {
+                entity_commands.insert(ChargeState::default());
             }
-            Spawn::Item(kind) => {
-                entity_commands.insert(Pickup(*kind));
+            if def.wake_loudness().is_some() {
+                entity_commands.insert(Asleep);
             }
+            if def.boss {
+                entity_commands.insert(Eyeball);
+            }
+        }
+        Spawn::Item(kind) => {
+            entity_commands.insert(Pickup(*kind));
         }
     }
+    Some(entity_commands)
 }
 
 pub struct SpawnPlugin;
 
 impl Plugin for SpawnPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, spawn);
+        app.add_systems(Update, spawn.run_if(in_state(RunState::Playing)));
     }
 }
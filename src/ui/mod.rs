@@ -6,11 +6,10 @@ use bevy_egui::{
 
 use crate::{
     assets::PRESS_START_2P_BYTES,
-    mob::MobKind,
-    player::{
-        FLASHLIGHT_MAX_BATTERY, FlashlightInfo, GunInfo, GunState, Inventory, PLAYER_MAX_DAMAGE,
-        Player,
-    },
+    difficulty::{Difficulty, RunState},
+    map::{MapSeed, Zones},
+    mob::MobName,
+    player::{FLASHLIGHT_MAX_BATTERY, FlashlightInfo, GunInfo, GunState, Inventory, Player},
     spawn::Spawn,
 };
 
@@ -30,6 +29,7 @@ pub struct UiSettings {
     pub toggle_2d: bool,
     pub show_fov: bool,
     pub show_flashlight: bool,
+    pub show_view_cones: bool,
     pub low_graphics: bool,
 }
 
@@ -42,6 +42,7 @@ impl Default for UiSettings {
             show_visibility: false,
             show_fov: false,
             show_flashlight: false,
+            show_view_cones: false,
             nohurt: false,
             inf_ammo: false,
             toggle_2d: false,
@@ -83,6 +84,30 @@ pub enum UiEvent {
     Spawn(Spawn),
 }
 
+/// Start screen: the run starts once a difficulty is picked.
+fn pick_difficulty(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut next_state: ResMut<NextState<RunState>>,
+) {
+    let Some(ctx) = contexts.try_ctx_mut() else {
+        return;
+    };
+    egui::CentralPanel::default().show(ctx, |ui| {
+        ui.vertical_centered(|ui| {
+            ui.label("\n\nSomething in the Fog\n\n");
+            ui.label("Difficulty\n");
+            for difficulty in Difficulty::ALL {
+                if ui.button(difficulty.name()).clicked() {
+                    commands.insert_resource(difficulty);
+                    next_state.set(RunState::Playing);
+                }
+            }
+        });
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
 fn update(
     mut contexts: EguiContexts,
     keyboard_input: Res<ButtonInput<KeyCode>>,
@@ -91,6 +116,9 @@ fn update(
     inventory: Res<Inventory>,
     player: Query<&Player>,
     flashlight: Res<FlashlightInfo>,
+    seed: Res<MapSeed>,
+    difficulty: Res<Difficulty>,
+    zones: Res<Zones>,
 ) {
     settings.show_performance_overlay ^= keyboard_input.just_pressed(KeyCode::F3);
     settings.show_debug_settings ^= keyboard_input.just_pressed(KeyCode::F4);
@@ -103,6 +131,8 @@ fn update(
         ui.horizontal(|ui| {
             ui.label("Something in the Fog V1.1\n");
         });
+        ui.label(format!("seed: {}", seed.0));
+        ui.label(format!("difficulty: {}\n", *difficulty));
 
         ui.with_layout(egui::Layout::right_to_left(Align::Min), |ui| {
             ui.label("Status");
@@ -114,11 +144,11 @@ fn update(
             let player = player.single();
             ui.colored_label(
                 Color32::RED,
-                "x".repeat(0.max(PLAYER_MAX_DAMAGE - player.damage) as usize),
+                "x".repeat(0.max(player.max_damage - player.damage) as usize),
             );
             ui.colored_label(
                 Color32::GRAY,
-                "x".repeat(player.damage.min(PLAYER_MAX_DAMAGE) as usize),
+                "x".repeat(player.damage.min(player.max_damage) as usize),
             );
         });
         ui.horizontal(|ui| {
@@ -201,6 +231,8 @@ fn update(
                 ui.separator();
                 ui.checkbox(&mut settings.show_flashlight, "flash");
                 ui.separator();
+                ui.checkbox(&mut settings.show_view_cones, "cones");
+                ui.separator();
                 ui.checkbox(&mut settings.nohurt, "nohurt");
                 ui.separator();
                 ui.checkbox(&mut settings.inf_ammo, "inf ammo");
@@ -209,17 +241,17 @@ fn update(
                 ui.separator();
                 ui.label("spawn");
                 if ui.button("m").clicked() {
-                    ev.send(UiEvent::Spawn(Spawn::Mob(MobKind::Sculpture)));
+                    ev.send(UiEvent::Spawn(Spawn::Mob(MobName::SCULPTURE)));
                 }
                 if ui.button("k").clicked() {
-                    ev.send(UiEvent::Spawn(Spawn::Mob(MobKind::KoolAidMan)));
+                    ev.send(UiEvent::Spawn(Spawn::Mob(MobName::KOOL_AID_MAN)));
                 }
                 if ui.button("z").clicked() {
-                    ev.send(UiEvent::Spawn(Spawn::Mob(MobKind::Zombie)));
+                    ev.send(UiEvent::Spawn(Spawn::Mob(MobName::ZOMBIE)));
                 }
                 ui.label("Teleport to... ");
-                for i in 0..=5 {
-                    if ui.button(format!("{i}")).clicked() {
+                for (i, zone) in zones.0.iter().enumerate() {
+                    if ui.button(&zone.spec.name).clicked() {
                         ev.send(UiEvent::TeleportPlayer(i));
                     }
                 }
@@ -267,7 +299,13 @@ impl Plugin for UiPlugin {
             .init_resource::<UiSettings>()
             .add_event::<UiEvent>()
             .add_systems(Startup, startup)
-            .add_systems(Update, update)
+            .add_systems(
+                Update,
+                (
+                    pick_difficulty.run_if(in_state(RunState::PickingDifficulty)),
+                    update.run_if(in_state(RunState::Playing)),
+                ),
+            )
             .add_systems(
                 PreUpdate,
                 absorb_egui_inputs
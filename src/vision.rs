@@ -0,0 +1,243 @@
+//! What mobs can see. Each mob faces the way it last moved or looked, and
+//! sees what's inside its kind's view cone and range that nothing blocks
+//! sight of. A player in the dark or hiding in a bush or doorway has to get
+//! closer to be seen.
+use bevy::{prelude::*, render::view::RenderLayers};
+use rogue_algebra::{
+    Pos,
+    bitgrid::BitGrid,
+    fov::{FovAlgorithm, FovOpts, RadiusMetric, cast_fov, line_of_sight},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Player,
+    behaviour::Asleep,
+    bestiary::Bestiary,
+    lighting::UI_LAYER,
+    map::{FlashlightMap, MapPos, OpacityMap, SightBlockedMap, TILE_HEIGHT, TILE_WIDTH},
+    mob::Mob,
+    ui::UiSettings,
+};
+
+/// How a kind of mob sees.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Vision {
+    /// Width of the view cone; 360 sees all round.
+    pub cone_degrees: f32,
+    /// How far it sees a player who's in its flashlight beam, in tiles.
+    pub range: i32,
+}
+
+impl Default for Vision {
+    fn default() -> Self {
+        Self {
+            cone_degrees: 360.0,
+            range: 20,
+        }
+    }
+}
+
+impl Vision {
+    /// Whether `offset` from a mob facing `facing` is inside its cone.
+    pub fn in_cone(&self, facing: IVec2, offset: IVec2) -> bool {
+        if self.cone_degrees >= 360.0 || offset == IVec2::ZERO || facing == IVec2::ZERO {
+            return true;
+        }
+        let half_cone = (self.cone_degrees / 2.0).to_radians();
+        facing.as_vec2().angle_to(offset.as_vec2()).abs() <= half_cone
+    }
+
+    /// How far a mob sees the player: all the way when the player's beam is
+    /// on the mob, half as far when the player's in the dark, and only right
+    /// next to them when they're hiding.
+    pub fn detection_range(&self, mob_in_beam: bool, player_hidden: bool) -> i32 {
+        if player_hidden {
+            1.min(self.range)
+        } else if mob_in_beam {
+            self.range
+        } else {
+            self.range / 2
+        }
+    }
+
+    fn cast(
+        &self,
+        pos: IVec2,
+        facing: IVec2,
+        range: i32,
+        opacity: &BitGrid,
+        mut see: impl FnMut(IVec2),
+    ) {
+        let opts = FovOpts {
+            algorithm: FovAlgorithm::Symmetric,
+            radius: range,
+            metric: RadiusMetric::Euclidean,
+        };
+        cast_fov(
+            pos.into(),
+            opts,
+            |p| opacity.contains(p),
+            |p| {
+                let p = IVec2::from(p);
+                if self.in_cone(facing, p - pos) {
+                    see(p);
+                }
+            },
+        );
+    }
+
+    /// Whether a mob at `pos` facing `facing` sees `target` within `range`.
+    /// Only the line to `target` is checked, so this also sees the odd tile
+    /// past a corner that [`Vision::view`] leaves out.
+    pub fn sees(
+        &self,
+        pos: IVec2,
+        facing: IVec2,
+        range: i32,
+        target: IVec2,
+        opacity: &BitGrid,
+    ) -> bool {
+        let offset = Pos::from(target) - Pos::from(pos);
+        if !RadiusMetric::Euclidean.contains(offset, range) || !self.in_cone(facing, target - pos) {
+            return false;
+        }
+        line_of_sight(pos.into(), target.into(), |p| opacity.contains(p))
+    }
+
+    /// Every tile a mob at `pos` facing `facing` sees within `range`.
+    pub fn view(&self, pos: IVec2, facing: IVec2, range: i32, opacity: &BitGrid) -> Vec<IVec2> {
+        let mut seen = Vec::new();
+        self.cast(pos, facing, range, opacity, |p| seen.push(p));
+        seen.sort_by_key(|p| (p.x, p.y));
+        seen.dedup();
+        seen
+    }
+}
+
+/// The way a mob is looking. Doesn't need to be a unit vector.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Facing(pub IVec2);
+
+impl Default for Facing {
+    fn default() -> Self {
+        Self(IVec2::NEG_Y)
+    }
+}
+
+impl Facing {
+    /// Turns towards `offset`, unless it's no direction at all.
+    pub fn turn(&mut self, offset: IVec2) {
+        if offset != IVec2::ZERO {
+            self.0 = offset;
+        }
+    }
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct ViewConeGizmos;
+
+/// Debug overlay outlining the tiles each waking mob can see the player on,
+/// as things stand: red for mobs that see them, yellow for the rest.
+#[allow(clippy::complexity)]
+fn draw_view_cones(
+    mut gizmos: Gizmos<ViewConeGizmos>,
+    settings: Res<UiSettings>,
+    mobs: Query<(&Mob, &MapPos, &Facing), Without<Asleep>>,
+    player: Query<&MapPos, (With<Player>, Without<Mob>)>,
+    bestiary: Res<Bestiary>,
+    opacity_map: Res<OpacityMap>,
+    flashlight_map: Res<FlashlightMap>,
+    sight_blocked_map: Res<SightBlockedMap>,
+) {
+    if !settings.show_view_cones {
+        return;
+    }
+    let Ok(player_pos) = player.get_single() else {
+        return;
+    };
+    let player_hidden = sight_blocked_map.0.contains(&player_pos.0);
+    let tile_size = Vec2::new(TILE_WIDTH, TILE_HEIGHT) * 0.8;
+    for (mob, pos, facing) in mobs.iter() {
+        let vision = bestiary.def(mob.kind).vision;
+        let range = vision.detection_range(flashlight_map.0.contains(&pos.0), player_hidden);
+        let view = vision.view(pos.0, facing.0, range, &opacity_map.0);
+        let color = if view.contains(&player_pos.0) {
+            Color::srgba(1.0, 0.0, 0.0, 0.5)
+        } else {
+            Color::srgba(1.0, 1.0, 0.0, 0.3)
+        };
+        for p in view {
+            gizmos.rect_2d(
+                Isometry2d::from_translation(MapPos(p).to_vec2()),
+                tile_size,
+                color,
+            );
+        }
+        let from = pos.to_vec2();
+        let to = from + facing.0.as_vec2().normalize_or_zero() * TILE_WIDTH;
+        gizmos.line_2d(from, to, color.with_alpha(1.0));
+    }
+}
+
+pub struct VisionPlugin;
+
+impl Plugin for VisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_gizmo_config(
+            ViewConeGizmos,
+            GizmoConfig {
+                render_layers: RenderLayers::layer(UI_LAYER),
+                ..default()
+            },
+        )
+        .add_systems(Update, draw_view_cones);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mobs_see_ahead_but_not_behind_or_through_walls() {
+        let vision = Vision {
+            cone_degrees: 90.0,
+            range: 6,
+        };
+        let mut opacity = BitGrid::new(rogue_algebra::Rect::new(-10, 10, -10, 10));
+        opacity.insert(Pos::new(0, 3));
+        let facing = IVec2::X;
+        let sees = |target| vision.sees(IVec2::ZERO, facing, vision.range, target, &opacity);
+
+        assert!(sees(IVec2::new(5, 0)));
+        assert!(sees(IVec2::new(4, 3)));
+        // out of range
+        assert!(!sees(IVec2::new(7, 0)));
+        // outside the cone
+        assert!(!sees(IVec2::new(-3, 0)));
+        assert!(!sees(IVec2::new(1, 4)));
+        // behind the wall
+        assert!(!vision.sees(IVec2::ZERO, IVec2::Y, 6, IVec2::new(0, 5), &opacity));
+        assert!(vision.sees(IVec2::ZERO, IVec2::Y, 6, IVec2::new(0, 2), &opacity));
+
+        let view = vision.view(IVec2::ZERO, facing, vision.range, &opacity);
+        assert!(view.contains(&IVec2::new(5, 0)) && !view.contains(&IVec2::new(-3, 0)));
+        assert!(view.iter().all(|&p| sees(p)));
+
+        let all_round = Vision::default();
+        assert!(all_round.sees(IVec2::ZERO, facing, 6, IVec2::new(-3, 0), &opacity));
+    }
+
+    #[test]
+    fn test_darkness_and_hiding_shorten_range() {
+        let vision = Vision {
+            cone_degrees: 120.0,
+            range: 10,
+        };
+        assert_eq!(vision.detection_range(true, false), 10);
+        assert_eq!(vision.detection_range(false, false), 5);
+        assert_eq!(vision.detection_range(true, true), 1);
+        assert_eq!(vision.detection_range(false, true), 1);
+    }
+}